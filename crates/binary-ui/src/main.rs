@@ -91,6 +91,18 @@ fn main() -> AnyResult<()> {
         }
     })?;
 
+    let proxy_alert = event_loop.create_proxy();
+    library_web::set_alert(move |alert| {
+        let event = NcWindowEvent::Notify(alert.message.clone());
+        match proxy_alert.send_event(UserEvent::NcWindowEvent(event)) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                log::error!("发布订阅提醒事件异常! {}", e);
+                Err(Box::new(BizError::EventSend("订阅提醒".to_string())))
+            }
+        }
+    })?;
+
     let proxy_exit = event_loop.create_proxy();
     library_web::updater::set_exit(move |i| match proxy_exit.send_event(UserEvent::EXIT()) {
         Ok(_) => {}
@@ -98,6 +98,8 @@ pub fn dispatch_window(id: WindowId, c: Box<Callback>) -> AnyResult<()> {
 
 pub enum NcWindowEvent {
     OpenKernel(String),
+    /// 在主窗口显示系统通知
+    Notify(String),
 }
 
 pub struct Window {
@@ -224,6 +226,15 @@ impl Window {
                 }
             }
 
+            Event::UserEvent(UserEvent::NcWindowEvent(NcWindowEvent::Notify(message))) => {
+                self.consumer(self.main, |_, v| match on_notify(v, &message) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::error!("显示通知异常! {}", e)
+                    }
+                })
+            }
+
             Event::UserEvent(UserEvent::EMPTY()) => {
                 if let Ok(c) = self.r.try_recv() {
                     let (id, c) = match c {
@@ -326,6 +337,28 @@ fn on_kernel_page_load(w: &tao::window::Window, v: &ViewWrapper) -> AnyResult<()
     Ok(())
 }
 
+/// 通过页面的通知接口显示系统通知, 同时派发 nc-notify 事件给页面
+fn on_notify(v: &ViewWrapper, message: &str) -> AnyResult<()> {
+    let body = message
+        .replace('\\', "\\\\")
+        .replace('\'', "\\'")
+        .replace('\n', "\\n");
+    let js = format!(
+        r#"
+        (() => {{
+            const body = '{body}';
+            window.dispatchEvent(new CustomEvent('nc-notify', {{ detail: body }}));
+            if (!('Notification' in window)) return;
+            const show = () => new Notification('NC', {{ body }});
+            if (Notification.permission === 'granted') show();
+            else if (Notification.permission !== 'denied')
+                Notification.requestPermission().then((p) => p === 'granted' && show());
+        }})();
+    "#
+    );
+    v.eval(&js)
+}
+
 pub trait TaoWindowExt {
     fn focus_show(&self);
 }
@@ -2,12 +2,17 @@ use crate::core::{http_get, RequestExt};
 use crate::share;
 use library_core::boolean::is_true;
 use library_core::core::{AnyResult, BizError};
+use library_nc::dns::DnsSetting;
 use library_nc::http::pick_host;
 use library_nc::kernel::{
-    dns_default_cn, dns_default_proxy, exclude_default, include_main, KernelConfig, NodeContains,
+    default_mixed_listen, default_mixed_port, default_ui, dns_default_cn, dns_default_proxy,
+    exclude_default, include_main, KernelConfig, NodeContains,
 };
-use library_nc::rule::{Rule, RuleType};
+use library_nc::rule::{rule_interval_default, Rule, RuleType};
+use library_nc::singbox::SingBoxVersion;
 use library_nc::subscribe::{Subscribe, HEADER_INFO};
+use library_nc::tun::TunSetting;
+use library_nc::validate;
 use std::collections::HashMap;
 use worker::wasm_bindgen::UnwrapThrowExt;
 use worker::Error::RustError;
@@ -20,8 +25,12 @@ struct ConvertParams {
     fake_ip: bool,
     geo_cn: bool,
     debug: bool,
+    /// 远程规则更新间隔, 单位: 秒
+    rule_interval: u32,
     include: NodeContains,
     exclude: NodeContains,
+    /// 生成 sing-box 配置的目标内核版本
+    sing_box_version: SingBoxVersion,
 }
 
 impl ConvertParams {
@@ -79,6 +88,13 @@ impl ConvertParams {
         let fake_ip = Self::convert_bool(source.get("fake_ip")).unwrap_or(true);
         let debug = Self::convert_bool(source.get("debug")).unwrap_or(false);
         let geo_cn = Self::convert_bool(source.get("geo_cn")).unwrap_or(true);
+        let rule_interval = Self::first(&source, "rule_interval")
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            .filter(|i| *i > 0)
+            .unwrap_or(rule_interval_default);
+        let sing_box_version = Self::first(&source, "version")
+            .and_then(|v| SingBoxVersion::parse(&v))
+            .unwrap_or_default();
 
         let uo = if remote.starts_with("s:") {
             let source = &remote[2..];
@@ -118,8 +134,10 @@ impl ConvertParams {
             fake_ip,
             geo_cn,
             debug,
+            rule_interval,
             include,
             exclude,
+            sing_box_version,
         })
     }
 
@@ -130,18 +148,30 @@ impl ConvertParams {
         rules_proxy: Vec<Rule>,
         rules_reject: Vec<Rule>,
     ) -> AnyResult<KernelConfig> {
+        let interval = |rules: Vec<Rule>| -> Vec<Rule> {
+            rules
+                .into_iter()
+                .map(|r| r.with_interval(self.rule_interval))
+                .collect()
+        };
         let config = KernelConfig {
             nodes: subscribe.nodes,
             debug: self.debug,
             tun: self.tun,
+            tun_setting: TunSetting::default(),
             fake_ip: self.fake_ip,
             ipv6: self.ipv6,
             geo_cn_direct: self.geo_cn,
-            rules_direct,
-            rules_proxy,
-            rules_reject,
+            rules_direct: interval(rules_direct),
+            rules_proxy: interval(rules_proxy),
+            rules_reject: interval(rules_reject),
+            rules_custom: vec![],
+            rules_geo: vec![],
             dns_cn: dns_default_cn.clone(),
             dns_proxy: dns_default_proxy.clone(),
+            dns: DnsSetting::default(),
+            inbounds: vec![],
+            allow_lan: true,
         }
         .with_default(&self.include, &self.exclude);
 
@@ -175,6 +205,7 @@ async fn subscribe(params: &ConvertParams) -> AnyResult<Subscribe> {
 
 struct Remote {
     pub config: KernelConfig,
+    pub sing_box_version: SingBoxVersion,
     pub filename: String,
     pub info: Option<String>,
 }
@@ -201,6 +232,7 @@ async fn build_remote(
 
     Ok(Remote {
         config,
+        sing_box_version: params.sing_box_version,
         filename: host,
         info,
     })
@@ -227,7 +259,13 @@ pub async fn sing_box(req: Request, env: Env) -> AnyResult<Response> {
     .await?;
     let config = &remote.config;
     console_debug!("配置转换");
-    let json = config.sing_box_default()?;
+    let json = config.sing_box(
+        default_ui,
+        default_mixed_listen,
+        default_mixed_port,
+        remote.sing_box_version,
+    )?;
+    validate::sing_box(&json)?;
     console_debug!("返回配置");
 
     let builder = Response::builder();
@@ -256,6 +294,7 @@ pub async fn clash(req: Request, env: Env) -> AnyResult<Response> {
     let config = &remote.config;
     console_debug!("配置转换");
     let yml = config.clash_default()?;
+    validate::clash(&yml)?;
     console_debug!("返回配置");
 
     let builder = Response::builder();
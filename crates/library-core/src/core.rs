@@ -62,6 +62,10 @@ pub enum BizError {
     NotFoundKey(),
     #[error("无效Key: {0}!")]
     InvalidKey(String),
+    #[error("内核接口请求失败: {0}")]
+    KernelApi(String),
+    #[error("无效参数: {0}")]
+    InvalidParam(String),
 }
 
 pub enum Exit {
@@ -1,4 +1,13 @@
 mod v202507180;
+mod v202610190;
+mod v202610191;
+mod v202610192;
+mod v202610193;
+mod v202610194;
+mod v202610195;
+mod v202610196;
+mod v202610197;
+mod v202610198;
 
 use crate::app::get_app;
 use crate::app_config::AppConfig;
@@ -228,5 +237,41 @@ fn _version(conn: &ConnectionThreadSafe) -> AnyResult<()> {
         log::debug!("更新到: 20250718");
         v202507180::init(conn)?
     }
+    if version < 202610190 {
+        log::debug!("更新到: 202610190");
+        v202610190::init(conn)?
+    }
+    if version < 202610191 {
+        log::debug!("更新到: 202610191");
+        v202610191::init(conn)?
+    }
+    if version < 202610192 {
+        log::debug!("更新到: 202610192");
+        v202610192::init(conn)?
+    }
+    if version < 202610193 {
+        log::debug!("更新到: 202610193");
+        v202610193::init(conn)?
+    }
+    if version < 202610194 {
+        log::debug!("更新到: 202610194");
+        v202610194::init(conn)?
+    }
+    if version < 202610195 {
+        log::debug!("更新到: 202610195");
+        v202610195::init(conn)?
+    }
+    if version < 202610196 {
+        log::debug!("更新到: 202610196");
+        v202610196::init(conn)?
+    }
+    if version < 202610197 {
+        log::debug!("更新到: 202610197");
+        v202610197::init(conn)?
+    }
+    if version < 202610198 {
+        log::debug!("更新到: 202610198");
+        v202610198::init(conn)?
+    }
     Ok(())
 }
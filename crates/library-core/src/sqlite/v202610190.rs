@@ -0,0 +1,14 @@
+use crate::app_config::AppConfig;
+use crate::core::AnyResult;
+use sqlite::ConnectionThreadSafe;
+
+pub(super) fn init(conn: &ConnectionThreadSafe) -> AnyResult<()> {
+    conn.execute(
+        "
+-- 规则内容格式: auto/classical/domain/hosts/adguard/surge/yaml
+ALTER TABLE tbl_rule ADD COLUMN format TEXT DEFAULT 'auto';
+        ",
+    )?;
+
+    AppConfig::version_set(202610190)
+}
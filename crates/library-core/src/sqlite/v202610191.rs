@@ -0,0 +1,14 @@
+use crate::app_config::AppConfig;
+use crate::core::AnyResult;
+use sqlite::ConnectionThreadSafe;
+
+pub(super) fn init(conn: &ConnectionThreadSafe) -> AnyResult<()> {
+    conn.execute(
+        "
+-- 优化(去重/合并)前的规则数量, count 系列字段为优化后的数量
+ALTER TABLE tbl_rule ADD COLUMN count_raw INTEGER DEFAULT 0;
+        ",
+    )?;
+
+    AppConfig::version_set(202610191)
+}
@@ -0,0 +1,14 @@
+use crate::app_config::AppConfig;
+use crate::core::AnyResult;
+use sqlite::ConnectionThreadSafe;
+
+pub(super) fn init(conn: &ConnectionThreadSafe) -> AnyResult<()> {
+    conn.execute(
+        "
+-- 自定义规则 json数组: [{line, target}], 按顺序排在订阅规则之前
+ALTER TABLE tbl_config ADD COLUMN rules_custom TEXT DEFAULT '[]';
+        ",
+    )?;
+
+    AppConfig::version_set(202610192)
+}
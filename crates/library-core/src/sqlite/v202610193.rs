@@ -0,0 +1,14 @@
+use crate::app_config::AppConfig;
+use crate::core::AnyResult;
+use sqlite::ConnectionThreadSafe;
+
+pub(super) fn init(conn: &ConnectionThreadSafe) -> AnyResult<()> {
+    conn.execute(
+        "
+-- geo 分类规则 json数组: [{kind, category, target}]
+ALTER TABLE tbl_config ADD COLUMN rules_geo TEXT DEFAULT '[]';
+        ",
+    )?;
+
+    AppConfig::version_set(202610193)
+}
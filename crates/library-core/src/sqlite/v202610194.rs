@@ -0,0 +1,29 @@
+use crate::app_config::AppConfig;
+use crate::core::AnyResult;
+use sqlite::ConnectionThreadSafe;
+
+pub(super) fn init(conn: &ConnectionThreadSafe) -> AnyResult<()> {
+    conn.execute(
+        "
+-- 生成过的配置, 每个配置保留最近的若干版本
+CREATE TABLE tbl_config_history
+(
+    id   TEXT PRIMARY KEY,
+    config_id TEXT,
+    -- 触发方式: timer 定时刷新, upsert 修改配置, refresh 手动刷新, rollback 回滚
+    cause TEXT,
+    -- 与上一版本相比发生变化的来源 json数组: [config, setting, subscribe:id, rule:id]
+    changes TEXT,
+    -- 生成时各来源的摘要 json对象
+    source TEXT,
+    -- sing-box 配置
+    content TEXT,
+    -- 创建时间: 毫秒级别时间戳
+    create_time INTEGER
+);
+CREATE INDEX idx_config_history_config_id ON tbl_config_history (config_id, create_time);
+        ",
+    )?;
+
+    AppConfig::version_set(202610194)
+}
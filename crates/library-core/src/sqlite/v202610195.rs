@@ -0,0 +1,28 @@
+use crate::app_config::AppConfig;
+use crate::core::AnyResult;
+use sqlite::ConnectionThreadSafe;
+
+pub(super) fn init(conn: &ConnectionThreadSafe) -> AnyResult<()> {
+    conn.execute(
+        "
+-- 流量统计, 按天, 配置, 顶层出站累计
+CREATE TABLE tbl_traffic_stat
+(
+    -- 本地日期: 2006-01-02
+    day TEXT,
+    config_id TEXT,
+    -- 顶层出站的 tag, 为空时表示无法归属出站的流量
+    outbound TEXT,
+    -- 上传流量, 单位: Bytes
+    upload INTEGER,
+    -- 下载流量, 单位: Bytes
+    download INTEGER,
+    -- 更新时间: 毫秒级别时间戳
+    update_time INTEGER,
+    PRIMARY KEY (day, config_id, outbound)
+);
+        ",
+    )?;
+
+    AppConfig::version_set(202610195)
+}
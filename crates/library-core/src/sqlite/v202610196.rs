@@ -0,0 +1,26 @@
+use crate::app_config::AppConfig;
+use crate::core::AnyResult;
+use sqlite::ConnectionThreadSafe;
+
+pub(super) fn init(conn: &ConnectionThreadSafe) -> AnyResult<()> {
+    conn.execute(
+        "
+-- 订阅提醒, 每个订阅的每种提醒最多一条, 条件解除后删除
+CREATE TABLE tbl_subscribe_alert
+(
+    id   TEXT PRIMARY KEY,
+    subscribe_id TEXT,
+    -- 提醒类型: traffic 剩余流量不足, expire 即将过期
+    kind TEXT,
+    message TEXT,
+    -- 创建时间: 毫秒级别时间戳
+    create_time INTEGER,
+    -- 确认时间: 毫秒级别时间戳, 0 表示未确认
+    ack_time INTEGER DEFAULT 0,
+    UNIQUE (subscribe_id, kind)
+);
+        ",
+    )?;
+
+    AppConfig::version_set(202610196)
+}
@@ -0,0 +1,29 @@
+use crate::app_config::AppConfig;
+use crate::core::AnyResult;
+use sqlite::ConnectionThreadSafe;
+
+pub(super) fn init(conn: &ConnectionThreadSafe) -> AnyResult<()> {
+    conn.execute(
+        "
+-- 订阅每次刷新时的流量使用情况
+CREATE TABLE tbl_subscribe_usage
+(
+    id   TEXT PRIMARY KEY,
+    subscribe_id TEXT,
+    -- 下载流量, 单位: Bytes
+    download INTEGER,
+    -- 上传流量, 单位: Bytes
+    upload INTEGER,
+    -- 最大可用流量, 单位: Bytes
+    max INTEGER,
+    -- 过期时间: 毫秒级别时间戳
+    expire_time INTEGER,
+    -- 创建时间: 毫秒级别时间戳
+    create_time INTEGER
+);
+CREATE INDEX idx_subscribe_usage_subscribe_id ON tbl_subscribe_usage (subscribe_id, create_time);
+        ",
+    )?;
+
+    AppConfig::version_set(202610197)
+}
@@ -0,0 +1,24 @@
+use crate::app_config::AppConfig;
+use crate::core::AnyResult;
+use sqlite::ConnectionThreadSafe;
+
+pub(super) fn init(conn: &ConnectionThreadSafe) -> AnyResult<()> {
+    conn.execute(
+        "
+-- 连续刷新失败次数, 成功后清零
+ALTER TABLE tbl_subscribe ADD COLUMN fail_count INTEGER DEFAULT 0;
+-- 最近一次刷新失败的原因
+ALTER TABLE tbl_subscribe ADD COLUMN last_error TEXT DEFAULT '';
+-- 失败后下次允许定时刷新的时间: 毫秒级别时间戳
+ALTER TABLE tbl_subscribe ADD COLUMN next_retry_time INTEGER DEFAULT 0;
+ALTER TABLE tbl_rule ADD COLUMN fail_count INTEGER DEFAULT 0;
+ALTER TABLE tbl_rule ADD COLUMN last_error TEXT DEFAULT '';
+ALTER TABLE tbl_rule ADD COLUMN next_retry_time INTEGER DEFAULT 0;
+ALTER TABLE tbl_config ADD COLUMN fail_count INTEGER DEFAULT 0;
+ALTER TABLE tbl_config ADD COLUMN last_error TEXT DEFAULT '';
+ALTER TABLE tbl_config ADD COLUMN next_retry_time INTEGER DEFAULT 0;
+        ",
+    )?;
+
+    AppConfig::version_set(202610198)
+}
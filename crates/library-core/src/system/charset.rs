@@ -37,3 +37,8 @@ pub fn convert(bytes: Vec<u8>, charset: &str) -> AnyResult<String> {
     let string = encoding.decode(&bytes, DecoderTrap::Replace)?;
     Ok(string)
 }
+
+#[cfg(not(target_os = "windows"))]
+pub fn get_system() -> AnyResult<String> {
+    Ok("utf-8".to_string())
+}
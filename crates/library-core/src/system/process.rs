@@ -1,7 +1,7 @@
 use crate::core::{current_millis, AnyResult};
 use crate::system;
 use std::io::Read;
-use std::process::{Child, Command, ExitStatus, Stdio};
+use std::process::{Child, ChildStderr, ChildStdout, Command, ExitStatus, Stdio};
 use std::thread::sleep;
 use std::time::Duration;
 
@@ -93,6 +93,16 @@ impl Process {
         Ok(())
     }
 
+    /// 发送 SIGHUP 信号, 通知进程重新加载
+    #[cfg(not(target_os = "windows"))]
+    pub fn hangup(&self) -> AnyResult<()> {
+        let r = unsafe { libc::kill(self.id as libc::pid_t, libc::SIGHUP) };
+        if r < 0 {
+            return Err(Box::new(std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
     pub fn out_bytes(&mut self) -> AnyResult<Option<Vec<u8>>> {
         if let Some(out) = &mut self.child.stdout {
             let mut buffer = Vec::new();
@@ -103,6 +113,11 @@ impl Process {
         Ok(None)
     }
 
+    /// 取出标准输出, 由调用方持续读取
+    pub fn take_out(&mut self) -> Option<ChildStdout> {
+        self.child.stdout.take()
+    }
+
     pub fn out_string(&mut self) -> AnyResult<Option<String>> {
         if let Some(vec) = self.out_bytes()? {
             let string = system::charset::convert(vec, &self.charset)?;
@@ -122,6 +137,11 @@ impl Process {
         Ok(None)
     }
 
+    /// 取出错误输出, 由调用方持续读取
+    pub fn take_err(&mut self) -> Option<ChildStderr> {
+        self.child.stderr.take()
+    }
+
     pub fn err_string(&mut self) -> AnyResult<Option<String>> {
         if let Some(vec) = self.err_bytes()? {
             let string = system::charset::convert(vec, &self.charset)?;
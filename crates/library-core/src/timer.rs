@@ -1,22 +1,94 @@
-use crate::core::AnyResult;
+pub mod cron;
+
+use crate::core::{current_millis, AnyResult};
+use crate::timer::cron::Cron;
+use chrono::Local;
 use futures::FutureExt;
 use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
 use tokio::sync::{Mutex, Notify};
-use tokio::time::interval;
+use tokio::time::{sleep_until, Instant};
 
 type BoxedTask =
 Box<dyn FnMut() -> Pin<Box<dyn Future<Output=AnyResult<()>> + Send>> + Send + Sync>;
 
+/// 调度方式
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// 固定间隔, 启动后立即执行一次
+    Interval(Duration),
+    /// cron 表达式
+    Cron(Cron),
+}
+
+impl Schedule {
+    pub fn describe(&self) -> String {
+        match self {
+            Schedule::Interval(d) => format!("every {:?}", d),
+            Schedule::Cron(c) => c.source().to_string(),
+        }
+    }
+}
+
+/// 定时器状态, 时间均为毫秒级别时间戳
+#[derive(Debug, Clone, Default)]
+pub struct TimerStatus {
+    pub name: String,
+    pub schedule: String,
+    /// 随机延迟上限, 单位: 毫秒
+    pub jitter: u128,
+    pub running: bool,
+    pub paused: bool,
+    pub stopped: bool,
+    /// 累计执行次数
+    pub runs: u64,
+    pub last_run: Option<u128>,
+    /// 上次执行耗时, 单位: 毫秒
+    pub last_duration: Option<u128>,
+    pub last_success: Option<bool>,
+    pub last_error: Option<String>,
+    /// 下次计划执行时间, 暂停或停止时为空
+    pub next_run: Option<u128>,
+}
+
 pub struct Timer {
     name: String,
     task: Mutex<BoxedTask>,
-    interval: Duration,
+    /// 创建时的调度方式, 用于恢复默认
+    default: Schedule,
+    schedule: std::sync::Mutex<Schedule>,
+    jitter: std::sync::Mutex<Duration>,
+    /// 唤醒立即执行
     notify: Notify,
+    /// 暂停, 恢复, 停止, 修改调度时重新计算等待
+    control: Notify,
+    /// 调度方式已修改, 需要重新计算下次执行时间
+    rescheduled: AtomicBool,
+    running: AtomicBool,
+    /// 执行期间收到唤醒, 结束后再执行一次
+    pending: AtomicBool,
+    paused: AtomicBool,
+    stopped: AtomicBool,
+    status: std::sync::Mutex<TimerStatus>,
 }
 
 impl Timer {
-    pub fn new<F, R>(name: String, interval: Duration, mut task: F) -> Arc<Self>
+    pub fn new<F, R>(name: String, interval: Duration, task: F) -> Arc<Self>
+    where
+        F: FnMut() -> R + Send + Sync + 'static,
+        R: Future<Output=AnyResult<()>> + Send + 'static,
+    {
+        Self::with_schedule(name, Schedule::Interval(interval), Duration::ZERO, task)
+    }
+
+    /// 每次计划执行前额外等待 0 到 jitter 之间的随机时间, 避免多个任务同时触发
+    pub fn with_schedule<F, R>(
+        name: String,
+        schedule: Schedule,
+        jitter: Duration,
+        mut task: F,
+    ) -> Arc<Self>
     where
         F: FnMut() -> R + Send + Sync + 'static,
         R: Future<Output=AnyResult<()>> + Send + 'static,
@@ -26,11 +98,27 @@ impl Timer {
             Box::pin(fut)
         });
 
+        let status = TimerStatus {
+            name: name.clone(),
+            schedule: schedule.describe(),
+            jitter: jitter.as_millis(),
+            ..Default::default()
+        };
+
         let executor = Arc::new(Self {
             name,
             task: Mutex::new(boxed_task),
-            interval,
+            default: schedule.clone(),
+            schedule: std::sync::Mutex::new(schedule),
+            jitter: std::sync::Mutex::new(jitter),
             notify: Notify::new(),
+            control: Notify::new(),
+            rescheduled: AtomicBool::new(false),
+            running: AtomicBool::new(false),
+            pending: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            status: std::sync::Mutex::new(status),
         });
 
         let cloned = executor.clone();
@@ -41,33 +129,189 @@ impl Timer {
         executor
     }
 
+    fn jitter(&self) -> Duration {
+        let jitter = self.jitter.lock().map(|j| *j).unwrap_or_default();
+        if jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(fastrand::u64(0..jitter.as_millis() as u64))
+    }
+
+    /// 下次计划执行时间
+    fn next(&self, first: bool) -> Option<Instant> {
+        let schedule = self.schedule.lock().ok()?.clone();
+        let base = match &schedule {
+            Schedule::Interval(_) if first => Instant::now(),
+            Schedule::Interval(d) => Instant::now() + *d,
+            Schedule::Cron(c) => {
+                let now = Local::now();
+                let wait = (c.next_after(now)? - now).to_std().unwrap_or_default();
+                Instant::now() + wait
+            }
+        };
+        Some(base + self.jitter())
+    }
+
+    fn set_status<F: FnOnce(&mut TimerStatus)>(&self, f: F) {
+        if let Ok(mut s) = self.status.lock() {
+            f(&mut s)
+        }
+    }
+
     async fn run(self: Arc<Self>) {
-        let mut interval = interval(self.interval);
+        let mut next = self.next(true);
+        if next.is_none() {
+            log::warn!("[{}] 没有可执行的时间", self.name);
+        }
         loop {
+            if self.stopped.load(Ordering::SeqCst) {
+                self.set_status(|s| s.next_run = None);
+                break;
+            }
+            let due = next.filter(|_| !self.paused.load(Ordering::SeqCst));
+            let next_run = due.and_then(|d| {
+                let wait = d.saturating_duration_since(Instant::now()).as_millis();
+                current_millis().ok().map(|now| now + wait)
+            });
+            self.set_status(|s| s.next_run = next_run);
+
+            let sleep = async {
+                match due {
+                    Some(d) => sleep_until(d).await,
+                    None => futures::future::pending().await,
+                }
+            };
             tokio::select! {
-                _ = interval.tick() => {
+                _ = sleep => {
                     self.execute().await;
+                    next = self.next(false);
                 }
                 _ = self.notify.notified() => {
                     self.execute().await;
                 }
+                _ = self.control.notified() => {
+                    if self.rescheduled.swap(false, Ordering::SeqCst) {
+                        next = self.next(false);
+                    }
+                }
             }
         }
+        log::debug!("[{}] 已停止", self.name);
     }
 
+    /// 执行任务, 执行期间收到唤醒时结束后再执行一次
     async fn execute(&self) {
+        loop {
+            self.execute_once().await;
+            if !self.pending.swap(false, Ordering::SeqCst)
+                || self.paused.load(Ordering::SeqCst)
+                || self.stopped.load(Ordering::SeqCst)
+            {
+                break;
+            }
+            log::debug!("[{}] 执行期间收到唤醒, 再次执行", self.name);
+        }
+    }
+
+    async fn execute_once(&self) {
         let mut task = self.task.lock().await;
+        self.running.store(true, Ordering::SeqCst);
+        let start = current_millis().unwrap_or(0);
+        self.set_status(|s| {
+            s.running = true;
+            s.last_run = Some(start);
+        });
 
         let result = AssertUnwindSafe((task)()).catch_unwind().await;
 
-        match result {
-            Ok(Ok(())) => {}
-            Ok(Err(e)) => log::error!("[{}] 任务执行失败: {}", self.name, e),
-            Err(_) => log::error!("[{}] 任务发生 panic", self.name),
-        }
+        let error = match result {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => {
+                log::error!("[{}] 任务执行失败: {}", self.name, e);
+                Some(e.to_string())
+            }
+            Err(_) => {
+                log::error!("[{}] 任务发生 panic", self.name);
+                Some("panic".to_string())
+            }
+        };
+        let end = current_millis().unwrap_or(start);
+        self.set_status(|s| {
+            s.running = false;
+            s.runs += 1;
+            s.last_duration = Some(end.saturating_sub(start));
+            s.last_success = Some(error.is_none());
+            s.last_error = error;
+        });
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
+    /// 立即执行一次. 暂停或停止时忽略, 正在执行时在结束后再执行一次, 不会并发执行
     pub fn wake(&self) {
+        if self.paused.load(Ordering::SeqCst) || self.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        if self.running.load(Ordering::SeqCst) {
+            self.pending.store(true, Ordering::SeqCst);
+            // 标记前执行已结束时, 由此处唤醒
+            if self.running.load(Ordering::SeqCst) || !self.pending.swap(false, Ordering::SeqCst) {
+                log::debug!("[{}] 正在执行, 结束后再执行一次", self.name);
+                return;
+            }
+        }
         self.notify.notify_one();
     }
+
+    /// 修改调度方式与随机延迟, schedule 为空时恢复创建时的调度方式
+    pub fn set_schedule(&self, schedule: Option<Schedule>, jitter: Duration) {
+        let schedule = schedule.unwrap_or_else(|| self.default.clone());
+        let describe = schedule.describe();
+        if let Ok(mut s) = self.schedule.lock() {
+            *s = schedule;
+        }
+        if let Ok(mut j) = self.jitter.lock() {
+            *j = jitter;
+        }
+        self.set_status(|s| {
+            s.schedule = describe;
+            s.jitter = jitter.as_millis();
+        });
+        self.rescheduled.store(true, Ordering::SeqCst);
+        self.control.notify_one();
+    }
+
+    /// 暂停计划执行, 正在执行的任务不受影响
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+        self.set_status(|s| s.paused = true);
+        self.control.notify_one();
+    }
+
+    /// 恢复计划执行, 暂停期间错过的计划会立即执行一次
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.set_status(|s| s.paused = false);
+        self.control.notify_one();
+    }
+
+    /// 停止后不可恢复
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.set_status(|s| s.stopped = true);
+        self.control.notify_one();
+    }
+
+    pub fn status(&self) -> TimerStatus {
+        match self.status.lock() {
+            Ok(s) => s.clone(),
+            Err(_) => TimerStatus {
+                name: self.name.clone(),
+                ..Default::default()
+            },
+        }
+    }
 }
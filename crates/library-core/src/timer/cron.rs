@@ -0,0 +1,309 @@
+use crate::core::{AnyResult, BizError};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+
+/// 向后查找下次执行时间的最大年数
+const search_years: i32 = 5;
+
+/// 5 段 cron 表达式: 分 时 日 月 周, 按本地时间计算
+///
+/// 每段支持 `*`, `a`, `a-b`, `*/n`, `a-b/n`, `a/n` 及逗号分隔的组合, 周日为 0 或 7.
+/// 日和周同时指定时满足其一即可. 另支持 `@hourly`, `@daily`, `@weekly`, `@monthly`, `@yearly`
+#[derive(Debug, Clone)]
+pub struct Cron {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// 日是否为 *
+    days_any: bool,
+    /// 周是否为 *
+    weekdays_any: bool,
+}
+
+fn invalid(expr: &str, reason: &str) -> Box<BizError> {
+    Box::new(BizError::InvalidParam(format!(
+        "cron 表达式 [{}] {}",
+        expr, reason
+    )))
+}
+
+/// 解析一段, 返回取值的位图
+fn field(expr: &str, part: &str, min: u32, max: u32) -> AnyResult<u64> {
+    let mut bits = 0u64;
+    for item in part.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((r, s)) => match s.parse::<u32>() {
+                Ok(s) if s > 0 => (r, s),
+                _ => return Err(invalid(expr, &format!("步长 {} 无效", s))),
+            },
+            None => (item, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            let a = a
+                .parse::<u32>()
+                .map_err(|_| invalid(expr, &format!("{} 无效", item)))?;
+            let b = b
+                .parse::<u32>()
+                .map_err(|_| invalid(expr, &format!("{} 无效", item)))?;
+            (a, b)
+        } else {
+            let a = range
+                .parse::<u32>()
+                .map_err(|_| invalid(expr, &format!("{} 无效", item)))?;
+            // a/n 表示从 a 到最大值
+            (a, if item.contains('/') { max } else { a })
+        };
+        if start < min || end > max || start > end {
+            return Err(invalid(expr, &format!("{} 超出范围 {}-{}", item, min, max)));
+        }
+        let mut v = start;
+        while v <= end {
+            bits |= 1 << v;
+            v += step;
+        }
+    }
+    Ok(bits)
+}
+
+fn has(bits: u64, v: u32) -> bool {
+    bits & (1 << v) != 0
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> AnyResult<Self> {
+        let source = expr.trim();
+        let normalized = match source {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            v => v,
+        };
+        let parts: Vec<&str> = normalized.split_whitespace().collect();
+        if parts.len() != 5 {
+            return Err(invalid(source, "必须为 5 段: 分 时 日 月 周"));
+        }
+        let mut weekdays = field(source, parts[4], 0, 7)?;
+        // 7 和 0 都表示周日
+        if has(weekdays, 7) {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            source: source.to_string(),
+            minutes: field(source, parts[0], 0, 59)?,
+            hours: field(source, parts[1], 0, 23)?,
+            days: field(source, parts[2], 1, 31)?,
+            months: field(source, parts[3], 1, 12)?,
+            weekdays,
+            days_any: parts[2] == "*",
+            weekdays_any: parts[4] == "*",
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn match_day(&self, date: &NaiveDate) -> bool {
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+        match (self.days_any, self.weekdays_any) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    /// 指定时间之后的下次执行时间
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        self.next_naive(after.naive_local(), |t| {
+            Local.from_local_datetime(t).earliest()
+        })
+    }
+
+    /// 按本地时间查找, resolve 转换为带时区的时间, 时间不存在时返回 None
+    fn next_naive<T>(
+        &self,
+        after: NaiveDateTime,
+        resolve: impl Fn(&NaiveDateTime) -> Option<T>,
+    ) -> Option<T> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = start.year() + search_years;
+        let mut t = start;
+        while t.year() <= limit {
+            if !has(self.months, t.month()) {
+                let (y, m) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = NaiveDate::from_ymd_opt(y, m, 1)?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.match_day(&t.date()) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !has(self.hours, t.hour()) {
+                t = hour_start(&t) + Duration::hours(1);
+                continue;
+            }
+            if !has(self.minutes, t.minute()) {
+                t += Duration::minutes(1);
+                continue;
+            }
+            // 夏令时跳过的时间不存在, 继续向后找
+            if let Some(v) = resolve(&t) {
+                return Some(v);
+            }
+            t += Duration::minutes(1);
+        }
+        None
+    }
+}
+
+fn hour_start(t: &NaiveDateTime) -> NaiveDateTime {
+    t.date().and_hms_opt(t.hour(), 0, 0).unwrap_or(*t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn next(expr: &str, after: &str) -> NaiveDateTime {
+        Cron::parse(expr)
+            .unwrap()
+            .next_naive(at(after), |t| Some(*t))
+            .unwrap()
+    }
+
+    #[test]
+    fn day_or_weekday() {
+        // 日和周同时指定时满足其一即可, 2026-10-21 为周三, 2026-10-25 为周日
+        assert_eq!(
+            next("0 0 21 * 0", "2026-10-19 10:00"),
+            at("2026-10-21 00:00")
+        );
+        assert_eq!(
+            next("0 0 21 * 0", "2026-10-21 00:00"),
+            at("2026-10-25 00:00")
+        );
+        assert_eq!(
+            next("0 0 21 * 7", "2026-10-21 00:00"),
+            at("2026-10-25 00:00")
+        );
+        // 只指定其一时按指定的匹配
+        assert_eq!(
+            next("0 0 * * 0", "2026-10-19 10:00"),
+            at("2026-10-25 00:00")
+        );
+        assert_eq!(
+            next("0 0 21 * *", "2026-10-21 00:00"),
+            at("2026-11-21 00:00")
+        );
+    }
+
+    #[test]
+    fn step() {
+        assert_eq!(
+            next("*/15 * * * *", "2026-10-19 10:07"),
+            at("2026-10-19 10:15")
+        );
+        assert_eq!(
+            next("*/15 * * * *", "2026-10-19 10:45"),
+            at("2026-10-19 11:00")
+        );
+        assert_eq!(
+            next("5/20 * * * *", "2026-10-19 10:26"),
+            at("2026-10-19 10:45")
+        );
+        assert_eq!(
+            next("10-30/10 * * * *", "2026-10-19 10:30"),
+            at("2026-10-19 11:10")
+        );
+        assert_eq!(
+            next("0 */6 * * *", "2026-10-19 19:00"),
+            at("2026-10-20 00:00")
+        );
+        // 秒被忽略, 不会返回当前分钟
+        let after = at("2026-10-19 10:15") + Duration::seconds(30);
+        let cron = Cron::parse("*/15 * * * *").unwrap();
+        assert_eq!(
+            cron.next_naive(after, |t| Some(*t)),
+            Some(at("2026-10-19 10:30"))
+        );
+    }
+
+    #[test]
+    fn month_rollover() {
+        // 11 月没有 31 日
+        assert_eq!(
+            next("0 0 31 * *", "2026-10-31 00:00"),
+            at("2026-12-31 00:00")
+        );
+        assert_eq!(next("@yearly", "2026-10-19 10:00"), at("2027-01-01 00:00"));
+        assert_eq!(
+            next("30 23 * * *", "2026-12-31 23:30"),
+            at("2027-01-01 23:30")
+        );
+        assert_eq!(
+            next("0 0 29 2 *", "2026-10-19 10:00"),
+            at("2028-02-29 00:00")
+        );
+        // 超出查找范围时没有执行时间
+        let cron = Cron::parse("0 0 30 2 *").unwrap();
+        assert_eq!(cron.next_naive(at("2026-10-19 10:00"), |t| Some(*t)), None);
+    }
+
+    #[test]
+    fn dst_gap() {
+        // 模拟 2026-03-29 02:00 至 03:00 因夏令时不存在
+        let gap = |t: &NaiveDateTime| {
+            let skipped =
+                t.date() == NaiveDate::from_ymd_opt(2026, 3, 29).unwrap() && t.hour() == 2;
+            (!skipped).then_some(*t)
+        };
+        let cron = Cron::parse("30 2 * * *").unwrap();
+        assert_eq!(
+            cron.next_naive(at("2026-03-28 03:00"), gap),
+            Some(at("2026-03-30 02:30"))
+        );
+        let cron = Cron::parse("*/30 * * * *").unwrap();
+        assert_eq!(
+            cron.next_naive(at("2026-03-29 01:50"), gap),
+            Some(at("2026-03-29 03:00"))
+        );
+    }
+
+    #[test]
+    fn next_after_local() {
+        let now = Local::now();
+        let next = Cron::parse("* * * * *").unwrap().next_after(now).unwrap();
+        assert!(next > now);
+        assert!(next - now <= Duration::minutes(2));
+    }
+
+    #[test]
+    fn invalid() {
+        for expr in [
+            "* * *",
+            "*/0 * * * *",
+            "60 * * * *",
+            "0 0 0 * *",
+            "0 0 * 13 *",
+            "5-1 * * * *",
+        ] {
+            assert!(Cron::parse(expr).is_err(), "{}", expr);
+        }
+    }
+}
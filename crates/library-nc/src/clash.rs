@@ -1,6 +1,9 @@
+use crate::dns::{DnsAddress, DnsProtocol};
+use crate::inbound::{local_listen, local_socket, InboundUser};
 use crate::kernel::{
-    default_mixed_listen, default_mixed_port, default_ui, key_direct, key_proxy, key_reject,
-    tag_auto, tag_fallback, tag_selector, test_url, KernelConfig,
+    default_mixed_listen, default_mixed_port, default_ui, dns_default_cn, dns_default_proxy,
+    key_direct, key_proxy, key_reject, tag_auto, tag_fallback, tag_selector, test_url,
+    KernelConfig,
 };
 use crate::rule::{ClashRule, Rule, RuleType};
 use indexmap::IndexMap;
@@ -20,25 +23,9 @@ const URL_MMDB: &str =
 const TAG_DIRECT: &str = "DIRECT";
 const TAG_REJECT: &str = "REJECT";
 
-const FAKE_IP_FILTER_STR: &[&str] = &[
-    "+.lan",
-    "+.local",
-    "+.localhost",
-    "+.localdomain",
-    "+.msftconnecttest.com",
-    "+.msftncsi.com",
-    "+.stun.*",
-    "+.stun.*.*",
-    "+.stun.*.*.*",
-    "+.stun.*.*.*.*",
-    "localhost.*",
-    "localhost.*.*",
-    "localhost.*.*.*",
-    "localhost.*.*.*.*",
-];
-
-const FAKE_IP_FILTER: LazyLock<Vec<String>> =
-    LazyLock::new(|| FAKE_IP_FILTER_STR.iter().map(|s| s.to_string()).collect());
+/// fake-ip 地址段与 dns 监听的默认值
+const FAKE_IP_RANGE: &str = "198.18.0.1/16";
+const DNS_LISTEN: &str = "0.0.0.0:1053";
 
 #[derive(Serialize)]
 struct ClashConfig {
@@ -76,8 +63,12 @@ struct ClashConfig {
     ipv6: bool,
     #[serde(rename = "tun")]
     tun: TunConfig,
+    #[serde(rename = "hosts", skip_serializing_if = "IndexMap::is_empty")]
+    hosts: IndexMap<String, Value>,
     #[serde(rename = "dns")]
     dns: DnsConfig,
+    #[serde(rename = "listeners", skip_serializing_if = "Vec::is_empty")]
+    listeners: Vec<Listener>,
     #[serde(rename = "proxies")]
     proxies: Vec<Proxy>,
     #[serde(rename = "proxy-groups")]
@@ -106,12 +97,31 @@ struct GeoxUrl {
     mmdb: String,
 }
 
+/// 额外的代理入站
+#[derive(Serialize)]
+struct Listener {
+    #[serde(rename = "name")]
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(rename = "listen")]
+    listen: String,
+    #[serde(rename = "port")]
+    port: u16,
+    #[serde(rename = "users", skip_serializing_if = "Vec::is_empty")]
+    users: Vec<InboundUser>,
+}
+
 #[derive(Serialize)]
 struct TunConfig {
     #[serde(rename = "enable")]
     enable: bool,
     #[serde(rename = "stack")]
     stack: String,
+    #[serde(rename = "device", skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+    #[serde(rename = "mtu", skip_serializing_if = "Option::is_none")]
+    mtu: Option<u32>,
     #[serde(rename = "dns-hijack")]
     dns_hijack: Vec<String>,
     #[serde(rename = "auto-route")]
@@ -120,6 +130,15 @@ struct TunConfig {
     auto_detect_interface: bool,
     #[serde(rename = "ipv6")]
     ipv6: bool,
+    #[serde(
+        rename = "route-exclude-address",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    route_exclude_address: Vec<String>,
+    #[serde(rename = "include-uid", skip_serializing_if = "Vec::is_empty")]
+    include_uid: Vec<u32>,
+    #[serde(rename = "exclude-uid", skip_serializing_if = "Vec::is_empty")]
+    exclude_uid: Vec<u32>,
 }
 
 #[derive(Serialize)]
@@ -134,6 +153,8 @@ struct DnsConfig {
     prefer_h3: bool,
     #[serde(rename = "cache-algorithm")]
     cache_algorithm: String,
+    #[serde(rename = "use-hosts")]
+    use_hosts: bool,
     #[serde(rename = "use-system-hosts")]
     use_system_hosts: bool,
     #[serde(rename = "enhanced-mode")]
@@ -186,14 +207,21 @@ struct ProxyGroup {
     pub proxies: Vec<String>,
 }
 
+/// 规则提供者, 规则列表, 规则提供者名称
+type ClashRules = (IndexMap<String, ClashRule>, Vec<String>, Vec<String>);
+
 impl KernelConfig {
     pub fn clash_default(&self) -> AnyResult<String> {
         self.clash(default_ui, default_mixed_listen, default_mixed_port)
     }
 
     pub fn clash(&self, ui: &str, mixed_listen: &str, mixed_port: u16) -> AnyResult<String> {
+        if self.tun {
+            self.tun_setting.validate_clash()?;
+        }
+
         // 构建规则相关数据
-        let (rule_providers, rules, rule_names) = self.clash_build_rules();
+        let (rule_providers, rules, rule_names) = self.clash_build_rules()?;
 
         // 构建DNS配置
         let dns = self.clash_build_dns(rule_names);
@@ -207,8 +235,8 @@ impl KernelConfig {
         // 构建完整配置
         let config = ClashConfig {
             port: mixed_port,
-            allow_lan: true,
-            bind_address: mixed_listen.into(),
+            allow_lan: self.allow_lan,
+            bind_address: local_listen(mixed_listen, self.allow_lan),
             mode: "rule".into(),
             log_level: if self.debug { "debug" } else { "info" }.into(),
             external_controller: ui.into(),
@@ -229,15 +257,10 @@ impl KernelConfig {
                 mmdb: URL_MMDB.into(),
             },
             ipv6: self.ipv6,
-            tun: TunConfig {
-                enable: self.tun,
-                stack: "system".into(),
-                dns_hijack: vec!["any:53".into(), "tcp://any:53".into()],
-                auto_route: true,
-                auto_detect_interface: true,
-                ipv6: self.ipv6,
-            },
+            tun: self.clash_build_tun(),
+            hosts: self.clash_build_hosts(),
             dns,
+            listeners: self.clash_build_listeners(),
             proxies,
             proxy_groups,
             rule_providers,
@@ -248,7 +271,70 @@ impl KernelConfig {
         Ok(yml)
     }
 
-    fn clash_build_rules(&self) -> (IndexMap<String, ClashRule>, Vec<String>, Vec<String>) {
+    fn clash_build_listeners(&self) -> Vec<Listener> {
+        self.inbounds
+            .iter()
+            .enumerate()
+            .map(|(i, inbound)| Listener {
+                name: inbound.tag(i),
+                kind: inbound.kind.clone(),
+                listen: local_listen(&inbound.listen, self.allow_lan),
+                port: inbound.port,
+                users: inbound.users.clone(),
+            })
+            .collect()
+    }
+
+    fn clash_build_tun(&self) -> TunConfig {
+        let setting = &self.tun_setting;
+        TunConfig {
+            enable: self.tun,
+            stack: setting.stack.clone(),
+            device: (!setting.interface_name.is_empty()).then(|| setting.interface_name.clone()),
+            mtu: (setting.mtu > 0).then_some(setting.mtu),
+            dns_hijack: vec!["any:53".into(), "tcp://any:53".into()],
+            auto_route: true,
+            auto_detect_interface: true,
+            ipv6: self.ipv6,
+            route_exclude_address: setting.route_exclude_address.clone(),
+            include_uid: setting.include_uid.clone(),
+            exclude_uid: setting.exclude_uid.clone(),
+        }
+    }
+
+    /// tun 的分应用代理, 排除的进程直连, 指定了仅代理时其余的直连. 仅作用于 tun 入站
+    fn clash_build_bypass_rules(&self) -> Vec<String> {
+        let mut rules = Vec::new();
+        if !self.tun {
+            return rules;
+        }
+        let setting = &self.tun_setting;
+        let any = |list: &Vec<String>| {
+            let names = list
+                .iter()
+                .map(|name| format!("(PROCESS-NAME,{})", name))
+                .collect::<Vec<_>>()
+                .join(",");
+            format!("(OR,({}))", names)
+        };
+        if !setting.exclude_process.is_empty() {
+            rules.push(format!(
+                "AND,((IN-TYPE,TUN),{}),{}",
+                any(&setting.exclude_process),
+                TAG_DIRECT
+            ));
+        }
+        if !setting.include_process.is_empty() {
+            rules.push(format!(
+                "AND,((IN-TYPE,TUN),(NOT,({}))),{}",
+                any(&setting.include_process),
+                TAG_DIRECT
+            ));
+        }
+        rules
+    }
+
+    fn clash_build_rules(&self) -> AnyResult<ClashRules> {
         let mut rules_process = Vec::new();
         let mut rules_other = Vec::new();
         let mut rules_ip = Vec::new();
@@ -260,21 +346,21 @@ impl KernelConfig {
             &mut rules_process,
             &mut rules_other,
             &mut rules_ip,
-        );
+        )?;
         self.clash_process_rules(
             self.rules_direct.iter(),
             key_direct,
             &mut rules_process,
             &mut rules_other,
             &mut rules_ip,
-        );
+        )?;
         self.clash_process_rules(
             self.rules_proxy.iter(),
             key_proxy,
             &mut rules_process,
             &mut rules_other,
             &mut rules_ip,
-        );
+        )?;
 
         // 构建规则提供者和规则列表
         let mut rule_providers = IndexMap::new();
@@ -283,25 +369,43 @@ impl KernelConfig {
         self.clash_build_rule_providers(rules_other, &mut names, &mut rule_providers);
         self.clash_build_rule_providers(rules_ip, &mut names, &mut rule_providers);
 
-        let mut rules = Vec::new();
+        let mut rules = self.clash_build_bypass_rules();
         let mut rule_names = Vec::new();
 
+        // 自定义规则排在分应用代理之后, 所有规则集之前
+        for r in self.custom_rules() {
+            rules.push(r.clash_line(self.clash_outbound_by_prefix(&r.target)));
+        }
+
+        // geo 规则排在自定义规则之后
+        for r in self.geo_rules() {
+            let outbound = r
+                .group()
+                .unwrap_or_else(|| self.clash_outbound_by_prefix(r.key()));
+            let rule = if r.is_ip() {
+                format!("GEOIP,{},{},no-resolve", r.category(), outbound)
+            } else {
+                format!("GEOSITE,{},{}", r.category(), outbound)
+            };
+            rules.push(rule);
+        }
+
         for name in names {
-            let (rule_type, target, outbound) = if name.ends_with("_i_geo") {
-                ("GEOIP", "CN", self.clash_outbound_by_prefix(&name))
+            let outbound = self.clash_outbound_by_prefix(&name);
+            let rule = if name.ends_with("_i_geo") {
+                format!("GEOIP,CN,{}", outbound)
+            } else if name.contains("_i_") {
+                // ip规则集不需要为了匹配而解析域名
+                format!("RULE-SET,{},{},no-resolve", name, outbound)
             } else {
-                (
-                    "rule-set",
-                    name.as_str(),
-                    self.clash_outbound_by_prefix(&name),
-                )
+                format!("RULE-SET,{},{}", name, outbound)
             };
             rule_names.push(name.clone());
-            rules.push(format!("{}, {}, {}", rule_type, target, outbound));
+            rules.push(rule);
         }
-        rules.push(format!("MATCH, {}", tag_fallback));
+        rules.push(format!("MATCH,{}", tag_fallback));
 
-        (rule_providers, rules, rule_names)
+        Ok((rule_providers, rules, rule_names))
     }
 
     fn clash_process_rules<'a>(
@@ -311,13 +415,13 @@ impl KernelConfig {
         rules_process: &mut Vec<ClashRule>,
         rules_other: &mut Vec<ClashRule>,
         rules_ip: &mut Vec<ClashRule>,
-    ) {
+    ) -> AnyResult<()> {
         // 处理CN
         if self.geo_cn_direct && prefix == key_direct {
             // IP直连规则
             let rule = Rule::from_remote(RuleType::Ip, "".into());
             let tag = format!("{}_cn_i_geo", prefix);
-            rules_ip.push(rule.clash(&tag));
+            rules_ip.push(rule.clash(&tag)?);
         }
 
         // 分类处理规则
@@ -329,11 +433,12 @@ impl KernelConfig {
             };
 
             match rule.rule_type {
-                RuleType::Process => rules_process.push(rule.clash(&tag)),
-                RuleType::Ip => rules_ip.push(rule.clash(&tag)),
-                RuleType::Other => rules_other.push(rule.clash(&tag)),
+                RuleType::Process => rules_process.push(rule.clash(&tag)?),
+                RuleType::Ip => rules_ip.push(rule.clash(&tag)?),
+                RuleType::Other => rules_other.push(rule.clash(&tag)?),
             }
         }
+        Ok(())
     }
 
     fn clash_build_rule_providers(
@@ -362,12 +467,36 @@ impl KernelConfig {
     }
 
     fn clash_build_dns(&self, rule_names: Vec<String>) -> DnsConfig {
-        // 构建DNS服务器列表（避免克隆）
-        let dns_cn: Vec<String> = self.dns_cn.iter().cloned().collect();
-        let dns_proxy: Vec<String> = self.dns_proxy.iter().cloned().collect();
+        // 构建DNS服务器列表, 忽略无效地址
+        let dns_cn = self.clash_dns_list(&self.dns_cn, &dns_default_cn);
+        let dns_proxy = self.clash_dns_list(&self.dns_proxy, &dns_default_proxy);
+        // default-nameserver 只能使用 ip 地址
+        let ip_only = |list: &[String]| -> Vec<String> {
+            list.iter()
+                .filter_map(|a| DnsAddress::parse(a).ok())
+                .filter(|a| !a.need_resolve() && a.protocol != DnsProtocol::Dhcp)
+                .map(|a| a.clash())
+                .collect()
+        };
+        let mut dns_default = ip_only(&self.dns_cn);
+        if dns_default.is_empty() {
+            dns_default = ip_only(&dns_default_cn);
+        }
 
         // 构建DNS策略
         let mut nameserver_policy = IndexMap::new();
+        for r in self.geo_rules() {
+            if r.is_ip() {
+                continue;
+            }
+            let name = format!("geosite:{}", r.category());
+            match r.key() {
+                key_direct => nameserver_policy.insert(name, dns_cn.clone()),
+                key_proxy => nameserver_policy.insert(name, dns_proxy.clone()),
+                _ => None,
+            };
+        }
+
         for name in rule_names {
             if name.contains("_i_") || name.ends_with("_geo") {
                 continue;
@@ -381,10 +510,18 @@ impl KernelConfig {
 
         DnsConfig {
             enable: true,
-            listen: "0.0.0.0:1053".into(),
+            listen: local_socket(
+                if self.dns.listen.trim().is_empty() {
+                    DNS_LISTEN
+                } else {
+                    self.dns.listen.trim()
+                },
+                self.allow_lan,
+            ),
             ipv6: self.ipv6,
             prefer_h3: false,
             cache_algorithm: "arc".into(),
+            use_hosts: !self.dns.hosts.is_empty(),
             use_system_hosts: false,
             enhanced_mode: if self.fake_ip {
                 "fake-ip"
@@ -392,15 +529,60 @@ impl KernelConfig {
                 "redir-host"
             }
             .into(),
-            fake_ip_range: "198.18.0.1/16".into(),
-            fake_ip_filter: FAKE_IP_FILTER.clone(),
-            default_nameserver: dns_cn.clone(),
+            fake_ip_range: if self.dns.fake_ipv4.trim().is_empty() {
+                FAKE_IP_RANGE
+            } else {
+                self.dns.fake_ipv4.trim()
+            }
+            .into(),
+            fake_ip_filter: self.dns.fake_ip_filter.clone(),
+            default_nameserver: dns_default,
             nameserver: dns_cn.clone(),
             proxy_server_nameserver: dns_cn,
             nameserver_policy,
         }
     }
 
+    /// 转为 mihomo 的地址, 全部无效时使用默认列表. 设置了 client subnet 时附加 ecs 参数
+    fn clash_dns_list(&self, list: &[String], default: &[String]) -> Vec<String> {
+        let parse = |list: &[String]| -> Vec<String> {
+            list.iter()
+                .filter_map(|a| DnsAddress::parse(a).ok())
+                .map(|a| a.clash())
+                .collect()
+        };
+        let mut vec = parse(list);
+        if vec.is_empty() {
+            vec = parse(default);
+        }
+
+        let ecs = self.dns.client_subnet.trim();
+        if ecs.is_empty() {
+            return vec;
+        }
+        vec.into_iter()
+            .map(|a| {
+                let sep = if a.contains('#') { '&' } else { '#' };
+                format!("{}{}ecs={}", a, sep, ecs)
+            })
+            .collect()
+    }
+
+    /// 静态 hosts, 单个地址时不使用列表
+    fn clash_build_hosts(&self) -> IndexMap<String, Value> {
+        self.dns
+            .hosts
+            .iter()
+            .map(|(domain, ips)| {
+                let value = match ips.as_slice() {
+                    [ip] => Value::String(ip.clone()),
+                    ips => Value::Sequence(ips.iter().cloned().map(Value::String).collect()),
+                };
+                (domain.trim().to_lowercase(), value)
+            })
+            .collect()
+    }
+
     fn clash_build_proxies(&self) -> Vec<Proxy> {
         self.nodes
             .iter()
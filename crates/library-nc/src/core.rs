@@ -16,6 +16,20 @@ pub fn base64_decode(source: &str) -> AnyResult<String> {
 pub enum NcError {
     #[error("不支持的来源")]
     UnsupportedSource,
+    #[error("srs规则集格式异常: {0}")]
+    InvalidSrs(String),
+    #[error("无效的DNS配置: {0}")]
+    InvalidDns(String),
+    #[error("无效的TUN配置: {0}")]
+    InvalidTun(String),
+    #[error("无效的入站配置: {0}")]
+    InvalidInbound(String),
+    #[error("生成的配置无效: {0}")]
+    InvalidConfig(String),
+    #[error("无效的规则: {0}")]
+    InvalidRule(String),
+    #[error("无效的节点: {0}")]
+    InvalidNode(String),
 }
 
 pub static PREFIX_REMAIN_TRAFFIC: LazyLock<Vec<String>> =
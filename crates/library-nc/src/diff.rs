@@ -0,0 +1,307 @@
+use library_core::core::AnyResult;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// 按 tag 对比的列表差异, 如出站, 规则集, dns服务器
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagDiff {
+    pub added: Vec<Value>,
+    pub removed: Vec<Value>,
+    pub changed: Vec<Changed>,
+}
+
+/// 同一 tag 的新旧内容
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Changed {
+    pub tag: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// 没有 tag 的规则列表差异, 记录新增和删除的规则及其位置
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleDiff {
+    pub added: Vec<IndexedRule>,
+    pub removed: Vec<IndexedRule>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexedRule {
+    pub index: usize,
+    pub rule: Value,
+}
+
+/// 单个值的新旧内容
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValueDiff {
+    pub old: Value,
+    pub new: Value,
+}
+
+/// 两份 sing-box 配置在出站, 路由和 dns 上的结构差异
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SingBoxDiff {
+    /// 出站及端点
+    pub outbounds: TagDiff,
+    pub rule_sets: TagDiff,
+    pub route_rules: RuleDiff,
+    pub route_final: Option<ValueDiff>,
+    pub dns_servers: TagDiff,
+    pub dns_rules: RuleDiff,
+    pub dns_final: Option<ValueDiff>,
+}
+
+fn array<'a>(value: &'a Value, path: &[&str]) -> Vec<&'a Value> {
+    let mut value = Some(value);
+    for key in path {
+        value = value.and_then(|v| v.get(key));
+    }
+    value
+        .and_then(Value::as_array)
+        .map(|vs| vs.iter().collect())
+        .unwrap_or_default()
+}
+
+fn field(value: &Value, section: &str, key: &str) -> Value {
+    value
+        .get(section)
+        .and_then(|v| v.get(key))
+        .cloned()
+        .unwrap_or(Value::Null)
+}
+
+fn tag(value: &Value) -> String {
+    value
+        .get("tag")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// 出站和端点共用 tag
+fn outbounds(value: &Value) -> Vec<&Value> {
+    let mut vec = array(value, &["outbounds"]);
+    vec.extend(array(value, &["endpoints"]));
+    vec
+}
+
+fn diff_tag(old: Vec<&Value>, new: Vec<&Value>) -> TagDiff {
+    let olds: HashMap<String, &Value> = old.iter().map(|v| (tag(v), *v)).collect();
+    let news: HashMap<String, &Value> = new.iter().map(|v| (tag(v), *v)).collect();
+    let mut diff = TagDiff::default();
+    for v in &new {
+        match olds.get(&tag(v)) {
+            None => diff.added.push((*v).clone()),
+            Some(o) if o != v => diff.changed.push(Changed {
+                tag: tag(v),
+                old: (*o).clone(),
+                new: (*v).clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for v in old.into_iter().filter(|v| !news.contains_key(&tag(v))) {
+        diff.removed.push(v.clone());
+    }
+    diff
+}
+
+/// 规则按内容比较, 相同内容出现多次时按次数抵消
+fn diff_rule(old: Vec<&Value>, new: Vec<&Value>) -> RuleDiff {
+    let count = |list: &Vec<&Value>| {
+        let mut map: HashMap<String, usize> = HashMap::new();
+        for v in list {
+            *map.entry(v.to_string()).or_default() += 1;
+        }
+        map
+    };
+    let pick = |list: Vec<&Value>, mut other: HashMap<String, usize>| {
+        let mut vec = Vec::new();
+        for (index, v) in list.into_iter().enumerate() {
+            match other.get_mut(&v.to_string()) {
+                Some(n) if *n > 0 => *n -= 1,
+                _ => vec.push(IndexedRule {
+                    index,
+                    rule: v.clone(),
+                }),
+            }
+        }
+        vec
+    };
+    let (old_count, new_count) = (count(&old), count(&new));
+    RuleDiff {
+        added: pick(new, old_count),
+        removed: pick(old, new_count),
+    }
+}
+
+fn diff_value(old: Value, new: Value) -> Option<ValueDiff> {
+    if old == new {
+        None
+    } else {
+        Some(ValueDiff { old, new })
+    }
+}
+
+/// 对比两份 sing-box 配置
+pub fn sing_box(old: &str, new: &str) -> AnyResult<SingBoxDiff> {
+    let old: Value = serde_json::from_str(old)?;
+    let new: Value = serde_json::from_str(new)?;
+    Ok(SingBoxDiff {
+        outbounds: diff_tag(outbounds(&old), outbounds(&new)),
+        rule_sets: diff_tag(
+            array(&old, &["route", "rule_set"]),
+            array(&new, &["route", "rule_set"]),
+        ),
+        route_rules: diff_rule(
+            array(&old, &["route", "rules"]),
+            array(&new, &["route", "rules"]),
+        ),
+        route_final: diff_value(field(&old, "route", "final"), field(&new, "route", "final")),
+        dns_servers: diff_tag(
+            array(&old, &["dns", "servers"]),
+            array(&new, &["dns", "servers"]),
+        ),
+        dns_rules: diff_rule(
+            array(&old, &["dns", "rules"]),
+            array(&new, &["dns", "rules"]),
+        ),
+        dns_final: diff_value(field(&old, "dns", "final"), field(&new, "dns", "final")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(outbounds: Value, route: Value, dns: Value) -> String {
+        json!({ "outbounds": outbounds, "route": route, "dns": dns }).to_string()
+    }
+
+    fn tags(values: &[Value]) -> Vec<String> {
+        values.iter().map(tag).collect()
+    }
+
+    fn indexes(rules: &[IndexedRule]) -> Vec<usize> {
+        rules.iter().map(|r| r.index).collect()
+    }
+
+    #[test]
+    fn same_config() {
+        let c = config(
+            json!([{ "tag": "a", "type": "direct" }]),
+            json!({ "rules": [{ "action": "sniff" }], "final": "a" }),
+            json!({ "servers": [{ "tag": "dns-cn" }], "final": "dns-cn" }),
+        );
+        let diff = sing_box(&c, &c).unwrap();
+        assert!(diff.outbounds.added.is_empty() && diff.outbounds.changed.is_empty());
+        assert!(diff.route_rules.added.is_empty() && diff.route_rules.removed.is_empty());
+        assert!(diff.route_final.is_none() && diff.dns_final.is_none());
+        assert!(sing_box("{", &c).is_err());
+    }
+
+    #[test]
+    fn outbounds_by_tag() {
+        let old = config(
+            json!([
+                { "tag": "a", "type": "direct" },
+                { "tag": "b", "type": "trojan", "server": "b.com" },
+                { "tag": "c", "type": "trojan" }
+            ]),
+            json!({}),
+            json!({}),
+        );
+        let new = json!({
+            "outbounds": [
+                { "tag": "b", "type": "trojan", "server": "b2.com" },
+                { "tag": "a", "type": "direct" },
+                { "tag": "d", "type": "trojan" }
+            ],
+            "endpoints": [{ "tag": "wg", "type": "wireguard" }]
+        })
+        .to_string();
+        let diff = sing_box(&old, &new).unwrap().outbounds;
+        // 顺序变化不算修改, 端点与出站一起比较
+        assert_eq!(tags(&diff.added), ["d", "wg"]);
+        assert_eq!(tags(&diff.removed), ["c"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].tag, "b");
+        assert_eq!(diff.changed[0].old["server"], "b.com");
+        assert_eq!(diff.changed[0].new["server"], "b2.com");
+    }
+
+    #[test]
+    fn route_rules() {
+        let rule = |tag: &str| json!({ "rule_set": tag, "action": "route", "outbound": "a" });
+        let old = config(
+            json!([]),
+            json!({
+                "rule_set": [{ "tag": "r1", "type": "local", "path": "1.srs" }],
+                "rules": [rule("r1"), rule("r2"), rule("r2"), rule("r3")],
+                "final": "a"
+            }),
+            json!({}),
+        );
+        let new = config(
+            json!([]),
+            json!({
+                "rule_set": [{ "tag": "r1", "type": "local", "path": "2.srs" }],
+                "rules": [rule("r0"), rule("r1"), rule("r2"), rule("r3")],
+                "final": "b"
+            }),
+            json!({}),
+        );
+        let diff = sing_box(&old, &new).unwrap();
+        assert_eq!(diff.rule_sets.changed[0].tag, "r1");
+        // 重复的规则按次数抵消, 位置为各自列表中的序号
+        assert_eq!(indexes(&diff.route_rules.added), [0]);
+        assert_eq!(diff.route_rules.added[0].rule, rule("r0"));
+        assert_eq!(indexes(&diff.route_rules.removed), [2]);
+        assert_eq!(diff.route_rules.removed[0].rule, rule("r2"));
+        let route_final = diff.route_final.unwrap();
+        assert_eq!((route_final.old, route_final.new), (json!("a"), json!("b")));
+    }
+
+    #[test]
+    fn dns_servers_and_rules() {
+        let old = config(
+            json!([]),
+            json!({}),
+            json!({
+                "servers": [
+                    { "tag": "dns-cn", "type": "udp", "server": "223.5.5.5" },
+                    { "tag": "dns-fake", "type": "fakeip" }
+                ],
+                "rules": [{ "rule_set": "r1", "server": "dns-fake" }],
+                "final": "dns-cn"
+            }),
+        );
+        let new = config(
+            json!([]),
+            json!({}),
+            json!({
+                "servers": [
+                    { "tag": "dns-cn", "type": "udp", "server": "119.29.29.29" },
+                    { "tag": "dns-proxy", "type": "https", "server": "1.1.1.1" }
+                ],
+                "rules": [{ "rule_set": "r1", "server": "dns-proxy" }],
+                "final": "dns-cn"
+            }),
+        );
+        let diff = sing_box(&old, &new).unwrap();
+        assert_eq!(tags(&diff.dns_servers.added), ["dns-proxy"]);
+        assert_eq!(tags(&diff.dns_servers.removed), ["dns-fake"]);
+        assert_eq!(diff.dns_servers.changed[0].tag, "dns-cn");
+        assert_eq!(indexes(&diff.dns_rules.added), [0]);
+        assert_eq!(indexes(&diff.dns_rules.removed), [0]);
+        assert!(diff.dns_final.is_none());
+    }
+}
@@ -0,0 +1,685 @@
+use crate::core::NcError;
+use indexmap::IndexMap;
+use library_core::core::AnyResult;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::LazyLock;
+
+/// 默认不使用 fake-ip 的域名, 使用 clash 的通配写法
+pub const fake_ip_filter_default: LazyLock<Vec<String>> = LazyLock::new(|| {
+    vec![
+        "+.lan".to_string(),
+        "+.local".to_string(),
+        "+.localhost".to_string(),
+        "+.localdomain".to_string(),
+        "+.msftconnecttest.com".to_string(),
+        "+.msftncsi.com".to_string(),
+        "+.stun.*".to_string(),
+        "+.stun.*.*".to_string(),
+        "+.stun.*.*.*".to_string(),
+        "+.stun.*.*.*.*".to_string(),
+        "localhost.*".to_string(),
+        "localhost.*.*".to_string(),
+        "localhost.*.*.*".to_string(),
+        "localhost.*.*.*.*".to_string(),
+    ]
+});
+
+/// dns 设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DnsSetting {
+    /// 静态 hosts, 域名 -> ip 列表
+    pub hosts: IndexMap<String, Vec<String>>,
+    /// fake-ip 地址段, 为空时使用内核的默认值
+    pub fake_ipv4: String,
+    pub fake_ipv6: String,
+    /// 不使用 fake-ip 的域名. `+.` 匹配自身及子域名, `*` 匹配一级
+    pub fake_ip_filter: Vec<String>,
+    /// edns client subnet, 为空时不设置
+    pub client_subnet: String,
+    pub disable_cache: bool,
+    pub disable_expire: bool,
+    pub independent_cache: bool,
+    /// dns 监听地址 ip:port, 为空时 sing-box 不监听, clash 使用默认值
+    pub listen: String,
+}
+
+impl Default for DnsSetting {
+    fn default() -> Self {
+        Self {
+            hosts: IndexMap::new(),
+            fake_ipv4: "".into(),
+            fake_ipv6: "".into(),
+            fake_ip_filter: fake_ip_filter_default.clone(),
+            client_subnet: "".into(),
+            disable_cache: false,
+            disable_expire: false,
+            independent_cache: true,
+            listen: "".into(),
+        }
+    }
+}
+
+impl DnsSetting {
+    pub fn validate(&self) -> AnyResult<()> {
+        let invalid = NcError::InvalidDns;
+        for (domain, ips) in &self.hosts {
+            if domain.trim().is_empty() || ips.is_empty() {
+                return Err(invalid(format!("hosts {} 为空", domain)).into());
+            }
+            for ip in ips {
+                ip.parse::<IpAddr>()
+                    .map_err(|_| invalid(format!("hosts {} 的地址 {} 错误", domain, ip)))?;
+            }
+        }
+        for (name, value, v4) in [
+            ("fake-ip ipv4", &self.fake_ipv4, Some(true)),
+            ("fake-ip ipv6", &self.fake_ipv6, Some(false)),
+            ("client subnet", &self.client_subnet, None),
+        ] {
+            if !value.is_empty() && !is_prefix(value, v4) {
+                return Err(invalid(format!("{} {} 格式错误", name, value)).into());
+            }
+        }
+        if !self.listen.is_empty() && self.listen.parse::<SocketAddr>().is_err() {
+            return Err(invalid(format!("监听地址 {} 格式错误", self.listen)).into());
+        }
+        Ok(())
+    }
+
+    /// 将 fake-ip 过滤转为 sing-box 的规则, 没有过滤时返回 None
+    pub fn fake_ip_filter_rule(&self) -> Option<Value> {
+        let mut domain = Vec::new();
+        let mut domain_suffix = Vec::new();
+        let mut domain_regex = Vec::new();
+        for pattern in &self.fake_ip_filter {
+            let pattern = pattern.trim().to_lowercase();
+            if pattern.is_empty() {
+                continue;
+            }
+            let (any_sub, rest) = match pattern.strip_prefix("+.") {
+                Some(rest) => (true, rest),
+                None => (false, pattern.as_str()),
+            };
+            if !rest.contains('*') {
+                match any_sub {
+                    true => domain_suffix.push(Value::from(rest)),
+                    false => domain.push(Value::from(rest)),
+                }
+                continue;
+            }
+            let body = rest
+                .split('.')
+                .map(|label| match label {
+                    "*" => "[^.]+".to_string(),
+                    label => regex::escape(label),
+                })
+                .collect::<Vec<_>>()
+                .join(r"\.");
+            let prefix = if any_sub { r"(.+\.)?" } else { "" };
+            domain_regex.push(Value::from(format!("^{}{}$", prefix, body)));
+        }
+
+        let mut map = Map::new();
+        for (k, vs) in [
+            ("domain", domain),
+            ("domain_suffix", domain_suffix),
+            ("domain_regex", domain_regex),
+        ] {
+            if !vs.is_empty() {
+                map.insert(k.into(), Value::Array(vs));
+            }
+        }
+        if map.is_empty() {
+            None
+        } else {
+            Some(Value::Object(map))
+        }
+    }
+}
+
+/// 校验 ip 或 ip/前缀, v4 为 None 时不限制地址类型
+fn is_prefix(value: &str, v4: Option<bool>) -> bool {
+    let (ip, prefix) = match value.split_once('/') {
+        Some((ip, prefix)) => (ip, Some(prefix)),
+        None => (value, None),
+    };
+    let Ok(ip) = ip.parse::<IpAddr>() else {
+        return false;
+    };
+    if v4.is_some_and(|v4| v4 != ip.is_ipv4()) {
+        return false;
+    }
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    prefix.is_none_or(|p| p.parse::<u8>().is_ok_and(|p| p <= max))
+}
+
+/// dns 服务器协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsProtocol {
+    Udp,
+    Tcp,
+    Tls,
+    Quic,
+    Https,
+    H3,
+    Dhcp,
+}
+
+impl DnsProtocol {
+    /// sing-box 中的服务器类型, 同时也是地址中的协议
+    pub const fn name(self) -> &'static str {
+        match self {
+            DnsProtocol::Udp => "udp",
+            DnsProtocol::Tcp => "tcp",
+            DnsProtocol::Tls => "tls",
+            DnsProtocol::Quic => "quic",
+            DnsProtocol::Https => "https",
+            DnsProtocol::H3 => "h3",
+            DnsProtocol::Dhcp => "dhcp",
+        }
+    }
+
+    pub const fn all() -> &'static [DnsProtocol] {
+        &[
+            DnsProtocol::Udp,
+            DnsProtocol::Tcp,
+            DnsProtocol::Tls,
+            DnsProtocol::Quic,
+            DnsProtocol::Https,
+            DnsProtocol::H3,
+            DnsProtocol::Dhcp,
+        ]
+    }
+
+    pub fn from_name(name: &str) -> Option<DnsProtocol> {
+        let name = name.to_lowercase();
+        Self::all().iter().find(|p| p.name() == name).copied()
+    }
+
+    fn has_path(self) -> bool {
+        self == DnsProtocol::Https || self == DnsProtocol::H3
+    }
+}
+
+/// 解析后的 dns 服务器地址
+///
+/// 支持: `223.5.5.5`, `udp://`, `tcp://`, `tls://`, `quic://`, `https://`, `h3://`, `dhcp://auto`
+#[derive(Debug, Clone)]
+pub struct DnsAddress {
+    pub protocol: DnsProtocol,
+    /// 服务器地址, ipv6 不带方括号. dhcp 时为网卡名称, auto 为空
+    pub server: String,
+    pub port: Option<u16>,
+    /// https / h3 的路径
+    pub path: Option<String>,
+}
+
+impl DnsAddress {
+    pub fn parse(raw: &str) -> AnyResult<Self> {
+        let raw = raw.trim();
+        let invalid = |reason: &str| NcError::InvalidDns(format!("{} {}", raw, reason));
+
+        let (protocol, rest) = match raw.split_once("://") {
+            Some((scheme, rest)) => (
+                DnsProtocol::from_name(scheme).ok_or_else(|| invalid("不支持的协议"))?,
+                rest,
+            ),
+            None => (DnsProtocol::Udp, raw),
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], Some(rest[i..].to_string())),
+            None => (rest, None),
+        };
+        if path.is_some() && !protocol.has_path() {
+            return Err(invalid("不支持路径").into());
+        }
+
+        if protocol == DnsProtocol::Dhcp {
+            let server = if authority == "auto" { "" } else { authority };
+            return Ok(Self {
+                protocol,
+                server: server.into(),
+                port: None,
+                path: None,
+            });
+        }
+
+        let (server, port) = if let Ok(ip) = authority.parse::<IpAddr>() {
+            (ip.to_string(), None)
+        } else if let Some(v6) = authority.strip_prefix('[') {
+            let (ip, port) = v6.split_once(']').ok_or_else(|| invalid("ipv6格式错误"))?;
+            let ip: IpAddr = ip.parse().map_err(|_| invalid("ipv6格式错误"))?;
+            let port = match port.strip_prefix(':') {
+                Some(p) => Some(p.parse::<u16>().map_err(|_| invalid("端口错误"))?),
+                None if port.is_empty() => None,
+                None => return Err(invalid("ipv6格式错误").into()),
+            };
+            (ip.to_string(), port)
+        } else {
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((h, p)) => (h, Some(p.parse::<u16>().map_err(|_| invalid("端口错误"))?)),
+                None => (authority, None),
+            };
+            let valid = !host.is_empty()
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+            if !valid {
+                return Err(invalid("服务器地址错误").into());
+            }
+            (host.to_lowercase(), port)
+        };
+
+        Ok(Self {
+            protocol,
+            server,
+            port,
+            path,
+        })
+    }
+
+    /// 服务器地址为域名时需要先解析
+    pub fn need_resolve(&self) -> bool {
+        self.protocol != DnsProtocol::Dhcp && self.server.parse::<IpAddr>().is_err()
+    }
+
+    /// mihomo 中的地址, udp 不带协议, h3 使用 https 并附加 h3 参数
+    pub fn clash(&self) -> String {
+        if self.protocol == DnsProtocol::Dhcp {
+            let server = if self.server.is_empty() {
+                "system"
+            } else {
+                &self.server
+            };
+            return format!("dhcp://{}", server);
+        }
+
+        let host = self.host();
+        let path = self.path.as_deref().unwrap_or_default();
+        match self.protocol {
+            DnsProtocol::Udp => host,
+            DnsProtocol::H3 => format!("https://{}{}#h3=true", host, path),
+            p => format!("{}://{}{}", p.name(), host, path),
+        }
+    }
+
+    /// sing-box 1.12 之前的地址, https / h3 未指定路径时使用 /dns-query
+    pub fn legacy(&self) -> String {
+        if self.protocol == DnsProtocol::Dhcp {
+            let server = if self.server.is_empty() {
+                "auto"
+            } else {
+                &self.server
+            };
+            return format!("dhcp://{}", server);
+        }
+
+        let path = match &self.path {
+            Some(path) => path.as_str(),
+            None if self.protocol.has_path() => "/dns-query",
+            None => "",
+        };
+        format!("{}://{}{}", self.protocol.name(), self.host(), path)
+    }
+
+    /// 带端口的服务器地址, ipv6 带方括号
+    fn host(&self) -> String {
+        let host = match self.server.parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => format!("[{}]", ip),
+            _ => self.server.clone(),
+        };
+        match self.port {
+            Some(port) => format!("{}:{}", host, port),
+            None => host,
+        }
+    }
+}
+
+/// 校验 dns 服务器列表, 不能为空且每个地址都要能解析
+pub fn validate(list: &[String]) -> AnyResult<()> {
+    if list.is_empty() {
+        return Err(Box::new(NcError::InvalidDns("服务器列表为空".into())));
+    }
+    for address in list {
+        DnsAddress::parse(address)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::{CustomRule, KernelConfig};
+    use serde_json::json;
+
+    #[test]
+    fn parse_address() {
+        // 输入, 协议, 服务器, 端口, 路径
+        let table = [
+            ("223.5.5.5", DnsProtocol::Udp, "223.5.5.5", None, None),
+            (
+                " udp://1.1.1.1:5353 ",
+                DnsProtocol::Udp,
+                "1.1.1.1",
+                Some(5353),
+                None,
+            ),
+            (
+                "TCP://Dns.Google",
+                DnsProtocol::Tcp,
+                "dns.google",
+                None,
+                None,
+            ),
+            (
+                "tls://dns.alidns.com:853",
+                DnsProtocol::Tls,
+                "dns.alidns.com",
+                Some(853),
+                None,
+            ),
+            (
+                "https://1.1.1.1/dns-query",
+                DnsProtocol::Https,
+                "1.1.1.1",
+                None,
+                Some("/dns-query"),
+            ),
+            (
+                "https://[2606:4700::1111]:8443",
+                DnsProtocol::Https,
+                "2606:4700::1111",
+                Some(8443),
+                None,
+            ),
+            (
+                "quic://dns.adguard.com",
+                DnsProtocol::Quic,
+                "dns.adguard.com",
+                None,
+                None,
+            ),
+            (
+                "h3://dns.google/dns-query",
+                DnsProtocol::H3,
+                "dns.google",
+                None,
+                Some("/dns-query"),
+            ),
+            ("dhcp://auto", DnsProtocol::Dhcp, "", None, None),
+            ("dhcp://en0", DnsProtocol::Dhcp, "en0", None, None),
+            ("2400:3200::1", DnsProtocol::Udp, "2400:3200::1", None, None),
+            (
+                "[2400:3200::1]:53",
+                DnsProtocol::Udp,
+                "2400:3200::1",
+                Some(53),
+                None,
+            ),
+            (
+                "dns.local:53",
+                DnsProtocol::Udp,
+                "dns.local",
+                Some(53),
+                None,
+            ),
+        ];
+        for (raw, protocol, server, port, path) in table {
+            let a = DnsAddress::parse(raw).unwrap();
+            assert_eq!(a.protocol, protocol, "{}", raw);
+            assert_eq!(a.server, server, "{}", raw);
+            assert_eq!(a.port, port, "{}", raw);
+            assert_eq!(a.path.as_deref(), path, "{}", raw);
+        }
+
+        for raw in [
+            "",
+            "sdns://abc",
+            "udp://1.1.1.1/path",
+            "tls://dns.google:port",
+            "[2400:3200::1",
+            "[2400:3200::1]53",
+            "dns_google",
+        ] {
+            assert!(DnsAddress::parse(raw).is_err(), "{}", raw);
+        }
+        assert!(validate(&[]).is_err());
+        assert!(validate(&["223.5.5.5".into(), "bad://x".into()]).is_err());
+    }
+
+    #[test]
+    fn render_address() {
+        // 输入, clash, sing-box 1.11
+        let table = [
+            ("223.5.5.5", "223.5.5.5", "udp://223.5.5.5"),
+            ("udp://1.1.1.1:5353", "1.1.1.1:5353", "udp://1.1.1.1:5353"),
+            ("tcp://dns.google", "tcp://dns.google", "tcp://dns.google"),
+            (
+                "tls://dns.alidns.com",
+                "tls://dns.alidns.com",
+                "tls://dns.alidns.com",
+            ),
+            (
+                "quic://dns.adguard.com:853",
+                "quic://dns.adguard.com:853",
+                "quic://dns.adguard.com:853",
+            ),
+            (
+                "https://1.1.1.1",
+                "https://1.1.1.1",
+                "https://1.1.1.1/dns-query",
+            ),
+            (
+                "https://[::1]:8443/q",
+                "https://[::1]:8443/q",
+                "https://[::1]:8443/q",
+            ),
+            (
+                "h3://dns.google/dns-query",
+                "https://dns.google/dns-query#h3=true",
+                "h3://dns.google/dns-query",
+            ),
+            ("dhcp://auto", "dhcp://system", "dhcp://auto"),
+            ("dhcp://en0", "dhcp://en0", "dhcp://en0"),
+        ];
+        for (raw, clash, legacy) in table {
+            let a = DnsAddress::parse(raw).unwrap();
+            assert_eq!(a.clash(), clash, "{}", raw);
+            assert_eq!(a.legacy(), legacy, "{}", raw);
+        }
+        assert!(!DnsAddress::parse("1.1.1.1").unwrap().need_resolve());
+        assert!(
+            DnsAddress::parse("tls://dns.google")
+                .unwrap()
+                .need_resolve()
+        );
+        assert!(!DnsAddress::parse("dhcp://auto").unwrap().need_resolve());
+    }
+
+    #[test]
+    fn failover_order() {
+        let config = KernelConfig {
+            dns_cn: vec![
+                "223.5.5.5".into(),
+                "bad://x".into(),
+                "tls://dns.alidns.com".into(),
+            ],
+            dns_proxy: vec!["https://1.1.1.1/dns-query".into()],
+            rules_custom: vec![CustomRule {
+                line: "DOMAIN-SUFFIX,cn".into(),
+                target: "direct".into(),
+            }],
+            ..Default::default()
+        };
+        let (_, dns) = config.sing_box_build_dns_route();
+        let servers: Vec<&str> = dns.servers.iter().map(|s| s.tag.as_str()).collect();
+        // 无效地址被忽略, 域名地址需要本地解析
+        assert_eq!(servers, ["dns-cn", "dns-cn-1", "dns-proxy", "dns-local"]);
+
+        let address = |rule_set: Option<&str>, server: &str, failover: bool| {
+            let mut rule = json!({
+                "query_type": ["A", "AAAA"],
+                "server": server,
+                "action": "route"
+            });
+            if let Some(r) = rule_set {
+                rule["rule_set"] = r.into();
+            }
+            if failover {
+                rule["ip_accept_any"] = true.into();
+            }
+            rule
+        };
+        assert_eq!(
+            serde_json::to_value(&dns.rules).unwrap(),
+            json!([
+                address(Some("direct_o_c0"), "dns-cn", true),
+                address(Some("direct_o_c0"), "dns-cn-1", false),
+                { "rule_set": "direct_o_c0", "server": "dns-cn", "action": "route" },
+                address(None, "dns-cn", true),
+                address(None, "dns-cn-1", false),
+            ])
+        );
+
+        // 单个服务器时不生成 failover 规则
+        let config = KernelConfig {
+            dns_cn: vec!["223.5.5.5".into()],
+            ..Default::default()
+        };
+        let (_, dns) = config.sing_box_build_dns_route();
+        assert!(dns.rules.is_empty());
+
+        // 全部无效时使用默认服务器
+        let config = KernelConfig {
+            dns_cn: vec!["bad://x".into()],
+            ..Default::default()
+        };
+        let (_, dns) = config.sing_box_build_dns_route();
+        let servers = serde_json::to_value(&dns.servers).unwrap();
+        assert_eq!(servers[0]["tag"], "dns-cn");
+        assert_eq!(servers[0]["server"], "223.5.5.5");
+        assert_eq!(servers[1]["tag"], "dns-cn-1");
+    }
+
+    #[test]
+    fn validate_setting() {
+        assert!(DnsSetting::default().validate().is_ok());
+        let valid = DnsSetting {
+            fake_ipv4: "198.18.0.0/15".into(),
+            fake_ipv6: "fc00::/18".into(),
+            client_subnet: "114.114.114.0/24".into(),
+            listen: "127.0.0.1:1053".into(),
+            ..Default::default()
+        };
+        assert!(valid.validate().is_ok());
+        // client subnet 可以不带前缀, 可以为 ipv6
+        for ecs in ["1.2.3.4", "2400:3200::/48"] {
+            let setting = DnsSetting {
+                client_subnet: ecs.into(),
+                ..Default::default()
+            };
+            assert!(setting.validate().is_ok(), "{}", ecs);
+        }
+
+        let invalid = [
+            DnsSetting {
+                fake_ipv4: "198.18.0.0/33".into(),
+                ..Default::default()
+            },
+            DnsSetting {
+                fake_ipv4: "fc00::/18".into(),
+                ..Default::default()
+            },
+            DnsSetting {
+                fake_ipv6: "198.18.0.0/15".into(),
+                ..Default::default()
+            },
+            DnsSetting {
+                fake_ipv6: "fc00::/129".into(),
+                ..Default::default()
+            },
+            DnsSetting {
+                client_subnet: "1.2.3.0/abc".into(),
+                ..Default::default()
+            },
+            DnsSetting {
+                client_subnet: "subnet".into(),
+                ..Default::default()
+            },
+            DnsSetting {
+                listen: "127.0.0.1".into(),
+                ..Default::default()
+            },
+        ];
+        for setting in invalid {
+            assert!(setting.validate().is_err(), "{:?}", setting);
+        }
+    }
+
+    #[test]
+    fn validate_hosts() {
+        let hosts = |domain: &str, ips: &[&str]| DnsSetting {
+            hosts: IndexMap::from([(
+                domain.to_string(),
+                ips.iter().map(|ip| ip.to_string()).collect(),
+            )]),
+            ..Default::default()
+        };
+        assert!(
+            hosts("nas.lan", &["192.168.1.2", "fd00::2"])
+                .validate()
+                .is_ok()
+        );
+        assert!(hosts("nas.lan", &[]).validate().is_err());
+        assert!(hosts(" ", &["192.168.1.2"]).validate().is_err());
+        assert!(hosts("nas.lan", &["192.168.1.256"]).validate().is_err());
+        assert!(hosts("nas.lan", &["nas.local"]).validate().is_err());
+    }
+
+    #[test]
+    fn fake_ip_filter() {
+        let rule = DnsSetting::default().fake_ip_filter_rule().unwrap();
+        assert_eq!(
+            rule["domain_suffix"],
+            json!([
+                "lan",
+                "local",
+                "localhost",
+                "localdomain",
+                "msftconnecttest.com",
+                "msftncsi.com"
+            ])
+        );
+        assert_eq!(rule["domain_regex"][0], r"^(.+\.)?stun\.[^.]+$");
+        assert_eq!(rule["domain_regex"][4], r"^localhost\.[^.]+$");
+        assert!(rule.get("domain").is_none());
+
+        let setting = DnsSetting {
+            fake_ip_filter: vec![
+                " Time.Apple.com ".into(),
+                "*.example.com".into(),
+                "+.Apple.com".into(),
+                "".into(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            setting.fake_ip_filter_rule().unwrap(),
+            json!({
+                "domain": ["time.apple.com"],
+                "domain_suffix": ["apple.com"],
+                "domain_regex": [r"^[^.]+\.example\.com$"]
+            })
+        );
+
+        let empty = DnsSetting {
+            fake_ip_filter: vec![],
+            ..Default::default()
+        };
+        assert!(empty.fake_ip_filter_rule().is_none());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::core::NcError;
+use library_core::core::AnyResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+
+/// 支持的代理入站类型
+pub const inbound_kinds: [&str; 3] = ["mixed", "socks", "http"];
+
+/// 入站的认证用户
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct InboundUser {
+    pub username: String,
+    pub password: String,
+}
+
+/// 额外的代理入站, 与默认的 mixed 入站同时监听
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyInbound {
+    /// mixed / socks / http
+    pub kind: String,
+    pub listen: String,
+    pub port: u16,
+    /// 为空时不需要认证
+    pub users: Vec<InboundUser>,
+}
+
+impl ProxyInbound {
+    /// 按入站列表中的序号生成标签, 如: socks-in-1
+    pub fn tag(&self, index: usize) -> String {
+        format!("{}-in-{}", self.kind, index + 1)
+    }
+}
+
+/// 不允许局域网连接时, 非回环的监听地址改为回环地址
+pub fn local_listen(listen: &str, allow_lan: bool) -> String {
+    match listen.parse::<IpAddr>() {
+        _ if allow_lan => listen.into(),
+        Ok(ip) if ip.is_loopback() => listen.into(),
+        Ok(IpAddr::V6(_)) => "::1".into(),
+        _ => "127.0.0.1".into(),
+    }
+}
+
+/// 同 local_listen, 用于 ip:port 形式的地址
+pub fn local_socket(addr: &str, allow_lan: bool) -> String {
+    match addr.parse::<SocketAddr>() {
+        Ok(a) => match local_listen(&a.ip().to_string(), allow_lan).parse() {
+            Ok(ip) => SocketAddr::new(ip, a.port()).to_string(),
+            Err(_) => addr.into(),
+        },
+        Err(_) => addr.into(),
+    }
+}
+
+/// 校验入站列表, 端口不能重复, 也不能与默认的 mixed 入站及 dns 监听冲突
+pub fn validate(list: &[ProxyInbound], mixed_port: u16, dns_listen: &str) -> AnyResult<()> {
+    let invalid = NcError::InvalidInbound;
+    let mut ports = HashSet::from([mixed_port]);
+    if let Ok(addr) = dns_listen.parse::<SocketAddr>()
+        && !ports.insert(addr.port())
+    {
+        return Err(invalid(format!("dns 端口 {} 与 mixed 入站冲突", addr.port())).into());
+    }
+    for inbound in list {
+        if !inbound_kinds.contains(&inbound.kind.as_str()) {
+            return Err(invalid(format!("不支持的类型 {}", inbound.kind)).into());
+        }
+        if inbound.listen.parse::<IpAddr>().is_err() {
+            return Err(invalid(format!("监听地址 {} 格式错误", inbound.listen)).into());
+        }
+        if inbound.port == 0 || !ports.insert(inbound.port) {
+            return Err(invalid(format!("端口 {} 无效或重复", inbound.port)).into());
+        }
+        for user in &inbound.users {
+            if user.username.is_empty() || user.password.is_empty() {
+                return Err(invalid(format!("端口 {} 的用户名或密码为空", inbound.port)).into());
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,27 +1,207 @@
+use crate::core::NcError;
+use crate::dns::DnsSetting;
+use crate::inbound::ProxyInbound;
 use crate::rule::Rule;
+use crate::rule_classical;
 use crate::subscribe::SubscribeNode;
+use crate::tun::TunSetting;
 use indexmap::IndexMap;
+use library_core::core::AnyResult;
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::sync::LazyLock;
 #[cfg(feature = "wrangler")]
-use worker::console_debug;
+use worker::{console_debug, console_warn};
 
 #[derive(Default)]
 pub struct KernelConfig {
     pub nodes: Vec<SubscribeNode>,
     pub debug: bool,
     pub tun: bool,
+    /// 网络栈, mtu, 排除地址及分应用代理等 tun 设置
+    pub tun_setting: TunSetting,
     pub fake_ip: bool,
     pub ipv6: bool,
     pub geo_cn_direct: bool,
     pub rules_direct: Vec<Rule>,
     pub rules_proxy: Vec<Rule>,
     pub rules_reject: Vec<Rule>,
+    /// 自定义规则, 按顺序排在所有规则之前
+    pub rules_custom: Vec<CustomRule>,
+    /// geo 分类规则, 排在自定义规则之后, 订阅规则之前
+    pub rules_geo: Vec<GeoRule>,
     pub dns_cn: Vec<String>,
     pub dns_proxy: Vec<String>,
+    pub dns: DnsSetting,
+    /// 额外的代理入站
+    pub inbounds: Vec<ProxyInbound>,
+    /// 是否允许局域网连接, 不允许时代理入站只监听回环地址
+    pub allow_lan: bool,
+}
+
+/// 单行的自定义规则
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CustomRule {
+    /// classical 规则, 不含出口. 如: DOMAIN-SUFFIX,corp.local
+    pub line: String,
+    /// 出口: direct / proxy / reject
+    pub target: String,
+}
+
+impl CustomRule {
+    pub fn is_valid_target(&self) -> bool {
+        self.target == key_direct || self.target == key_proxy || self.target == key_reject
+    }
+
+    /// 出口必须为 direct / proxy / reject, 规则必须能转换为 sing-box 规则
+    pub fn validate(&self) -> AnyResult<()> {
+        let invalid = NcError::InvalidRule;
+        if !self.is_valid_target() {
+            return Err(invalid(format!(
+                "自定义规则 {} 的出口 {} 无效",
+                self.line, self.target
+            ))
+            .into());
+        }
+        match rule_classical::parse_line(&self.line) {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err(invalid("自定义规则为空".into()).into()),
+            Err(e) => Err(invalid(format!("自定义规则 {} 格式错误: {}", self.line, e)).into()),
+        }
+    }
+
+    /// 附加出口后的 clash 规则, 出口放在规则值之后, 附加参数之前
+    pub fn clash_line(&self, outbound: &str) -> String {
+        let line = self.line.trim();
+        let Some((_type, rest)) = line.split_once(',') else {
+            return format!("{},{}", line, outbound);
+        };
+        let _type = _type.trim().to_uppercase();
+        if _type == "AND" || _type == "OR" || _type == "NOT" {
+            return format!("{},{}", line, outbound);
+        }
+        match rest.split_once(',') {
+            None => format!("{},{}", line, outbound),
+            Some((value, options)) => format!("{},{},{},{}", _type, value, outbound, options),
+        }
+    }
+}
+
+/// geo 分类规则
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct GeoRule {
+    /// 类型: geosite / geoip
+    pub kind: String,
+    /// 分类, 可带类型前缀. 如: netflix, geosite-openai, category-ads-all
+    pub category: String,
+    /// 出口: direct / proxy / reject / 策略组或节点名称
+    pub target: String,
+}
+
+impl GeoRule {
+    pub fn is_ip(&self) -> bool {
+        self.kind == geo_kind_ip
+    }
+
+    /// 去掉类型前缀的小写分类
+    pub fn category(&self) -> String {
+        let category = self.category.trim().to_lowercase();
+        match category.strip_prefix(&format!("{}-", self.kind)) {
+            Some(c) => c.into(),
+            None => category,
+        }
+    }
+
+    /// 规则集名称. 如: geosite-netflix
+    pub fn name(&self) -> String {
+        format!("{}-{}", self.kind, self.category())
+    }
+
+    /// 出口关键字, 策略组与节点按代理处理
+    pub fn key(&self) -> &str {
+        match self.target.as_str() {
+            key_direct | key_reject => self.target.as_str(),
+            _ => key_proxy,
+        }
+    }
+
+    /// 出口是策略组或节点时返回名称
+    pub fn group(&self) -> Option<&str> {
+        match self.target.as_str() {
+            key_direct | key_proxy | key_reject => None,
+            target => Some(target),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let category = self.category();
+        (self.kind == geo_kind_site || self.kind == geo_kind_ip)
+            && !category.is_empty()
+            && category
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_!@.".contains(c))
+            && !self.target.trim().is_empty()
+    }
 }
 
 impl KernelConfig {
+    /// 可作为出口的策略组与节点名称
+    pub fn outbound_names(&self) -> Vec<String> {
+        let mut names: Vec<String> =
+            vec![tag_selector.into(), tag_auto.into(), tag_fallback.into()];
+        self.node_map_area().values().for_each(|nodes| {
+            let area = nodes.first().unwrap().area.unwrap();
+            names.push(format!("[{}] {}自动", area.code, area.name_cn));
+        });
+        self.nodes
+            .iter()
+            .for_each(|node| names.push(node.name.clone()));
+        names
+    }
+
+    /// 合法, 出口存在且不重复的 geo 规则
+    pub fn geo_rules(&self) -> Vec<&GeoRule> {
+        let names = self.outbound_names();
+        let mut seen = HashSet::new();
+        self.rules_geo
+            .iter()
+            .filter(|r| r.is_valid())
+            .filter(|r| r.group().is_none_or(|g| names.iter().any(|n| n == g)))
+            .filter(|r| seen.insert(r.name()))
+            .collect()
+    }
+
+    /// 合法的自定义规则, 无效的记录日志后跳过
+    pub fn custom_rules(&self) -> Vec<&CustomRule> {
+        self.rules_custom
+            .iter()
+            .filter(|r| match r.validate() {
+                Ok(_) => true,
+                Err(_e) => {
+                    #[cfg(feature = "binary")]
+                    log::warn!("跳过无效的自定义规则: {}", _e);
+                    #[cfg(feature = "wrangler")]
+                    console_warn!("跳过无效的自定义规则: {}", _e);
+                    false
+                }
+            })
+            .collect()
+    }
+
+    /// 合法的自定义规则, 相邻且出口相同的合并为一组
+    pub fn custom_rule_groups(&self) -> Vec<(&str, Vec<&str>)> {
+        let mut groups: Vec<(&str, Vec<&str>)> = Vec::new();
+        for r in self.custom_rules() {
+            let line = r.line.trim();
+            match groups.last_mut() {
+                Some((target, lines)) if *target == r.target => lines.push(line),
+                _ => groups.push((r.target.as_str(), vec![line])),
+            }
+        }
+        groups
+    }
+
     pub fn with_sort(mut self) -> Self {
         let main = &include_main.area;
         self.nodes.sort_by(|n1, n2| {
@@ -259,6 +439,10 @@ pub const key_direct: &str = "direct";
 pub const key_proxy: &str = "proxy";
 pub const key_reject: &str = "reject";
 
+/// geo 分类类型
+pub const geo_kind_site: &str = "geosite";
+pub const geo_kind_ip: &str = "geoip";
+
 /// 直连出口名称
 pub const out_direct: &str = "直连";
 
@@ -1,9 +1,19 @@
 pub mod area;
 pub mod clash;
 pub mod core;
+pub mod diff;
+pub mod dns;
 pub mod http;
+pub mod inbound;
 pub mod kernel;
 pub mod rule;
+pub mod rule_classical;
+pub mod rule_format;
+pub mod rule_optimize;
+pub mod rule_srs;
 pub mod singbox;
+pub mod singbox_explain;
 pub mod subscribe;
 pub mod subscribe_yml;
+pub mod tun;
+pub mod validate;
@@ -1,10 +1,14 @@
-use crate::core::fast;
+use crate::core::{NcError, fast};
 use crate::kernel::out_direct;
+use crate::rule_classical;
+use crate::rule_optimize;
+use crate::rule_srs;
+use crate::rule_classical::{HeadlessRule, RuleWarning};
 use byte_unit::rust_decimal::prelude::ToPrimitive;
 use library_core::core::AnyResult;
 use serde::Serialize;
 use serde_json::{Map, Value};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 
 macro_rules! enum_with_iter {
     (
@@ -45,30 +49,135 @@ enum_with_iter!(
     }
 );
 
+enum_with_iter!(
+    pub enum RuleFormat {
+        Auto => "auto",
+        Classical => "classical",
+        Domain => "domain",
+        Hosts => "hosts",
+        Adguard => "adguard",
+        Surge => "surge",
+        Yaml => "yaml",
+    }
+);
+
+impl RuleFormat {
+    /// 未知名称按自动识别处理
+    pub fn from_name(name: &str) -> Self {
+        let name = name.trim().to_lowercase();
+        Self::all()
+            .iter()
+            .find(|f| f.name() == name)
+            .copied()
+            .unwrap_or(RuleFormat::Auto)
+    }
+}
+
+enum_with_iter!(
+    pub enum ClashBehavior {
+        Domain => "domain",
+        Ipcidr => "ipcidr",
+        Classical => "classical",
+    }
+);
+
+enum_with_iter!(
+    pub enum ClashFormat {
+        Yaml => "yaml",
+        Text => "text",
+        Mrs => "mrs",
+    }
+);
+
+impl ClashFormat {
+    /// 根据来源的后缀推断
+    pub fn from_source(source: &str) -> Self {
+        let source = source.split(['?', '#']).next().unwrap_or("").to_lowercase();
+        if source.ends_with(".mrs") {
+            ClashFormat::Mrs
+        } else if source.ends_with(".txt") || source.ends_with(".list") {
+            ClashFormat::Text
+        } else {
+            ClashFormat::Yaml
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ClashFormat::Yaml => "yml",
+            ClashFormat::Text => "txt",
+            ClashFormat::Mrs => "mrs",
+        }
+    }
+}
+
+impl ClashBehavior {
+    /// mrs 只支持 domain 与 ipcidr, 不能用于进程规则. 内容仅有 cidr 的 ip 规则按 ipcidr 处理;
+    /// 其他来源的内容未知, 按 classical 处理
+    pub fn from(rule_type: RuleType, format: ClashFormat, ipcidr: bool) -> AnyResult<Self> {
+        match (format, rule_type) {
+            (ClashFormat::Mrs, RuleType::Ip) => Ok(ClashBehavior::Ipcidr),
+            (_, RuleType::Ip) if ipcidr => Ok(ClashBehavior::Ipcidr),
+            (ClashFormat::Mrs, RuleType::Other) => Ok(ClashBehavior::Domain),
+            (ClashFormat::Mrs, RuleType::Process) => Err(Box::new(NcError::InvalidRule(
+                "mrs 格式不支持进程规则".into(),
+            ))),
+            _ => Ok(ClashBehavior::Classical),
+        }
+    }
+}
+
+/// 远程规则默认更新间隔, 单位: 秒
+pub const rule_interval_default: u32 = 86400;
+
 pub struct Rule {
     pub path: String,
     pub rule_type: RuleType,
     pub remote: bool,
+    /// clash 规则提供者的格式, 行为由格式与规则类型决定
+    pub format: ClashFormat,
+    /// 远程规则的更新间隔, 单位: 秒
+    pub interval: u32,
+    /// 内容仅有 ip_cidr, 不含逻辑规则等其他类型
+    pub ipcidr: bool,
 }
 
 impl Rule {
     pub fn from_local(rule_type: RuleType, path: String) -> Self {
+        let format = ClashFormat::from_source(&path);
         Self {
             rule_type,
             path,
             remote: false,
+            format,
+            interval: rule_interval_default,
+            ipcidr: false,
         }
     }
 
     pub fn from_remote(rule_type: RuleType, url: String) -> Self {
+        let format = ClashFormat::from_source(&url);
         let path = fast(&url);
         Self {
             rule_type,
             path,
             remote: true,
+            format,
+            interval: rule_interval_default,
+            ipcidr: false,
         }
     }
 
+    pub fn with_interval(mut self, interval: u32) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_ipcidr(mut self, ipcidr: bool) -> Self {
+        self.ipcidr = ipcidr;
+        self
+    }
+
     pub fn sing_box(&self, tag: &str) -> SingBoxRule {
         let tag = tag.into();
         let format = if self.path.ends_with("srs") {
@@ -86,7 +195,8 @@ impl Rule {
                 url: Some(self.path.to_string()),
                 path: None,
                 download_detour: Some(out_direct.into()),
-                update_interval: Some("1d".into()),
+                update_interval: Some(format!("{}s", self.interval)),
+                rules: None,
             }
         } else {
             SingBoxRule {
@@ -97,24 +207,27 @@ impl Rule {
                 path: Some(self.path.to_string()),
                 download_detour: None,
                 update_interval: None,
+                rules: None,
             }
         }
     }
 
-    pub fn clash(&self, tag: &str) -> ClashRule {
+    pub fn clash(&self, tag: &str) -> AnyResult<ClashRule> {
         let name = tag.into();
-        let format = "yaml".into();
-        let behavior = "classical".into();
+        let format = self.format.name().into();
+        let behavior = ClashBehavior::from(self.rule_type, self.format, self.ipcidr)?
+            .name()
+            .into();
 
-        if self.remote {
+        let rule = if self.remote {
             ClashRule {
                 name,
                 format,
                 behavior,
                 type_: "http".into(),
                 url: Some(self.path.to_string()),
-                path: format!("./rules/{}.yml", tag),
-                interval: Some(86400),
+                path: format!("./rules/{}.{}", tag, self.format.extension()),
+                interval: Some(self.interval),
             }
         } else {
             ClashRule {
@@ -126,7 +239,8 @@ impl Rule {
                 path: self.path.to_string(),
                 interval: None,
             }
-        }
+        };
+        Ok(rule)
     }
 }
 
@@ -135,6 +249,7 @@ pub struct SingBoxRule {
     pub tag: String,
     #[serde(rename = "type")]
     pub type_: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub format: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub url: Option<String>,
@@ -144,145 +259,228 @@ pub struct SingBoxRule {
     pub download_detour: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub update_interval: Option<String>,
+    /// 内联规则集的规则
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules: Option<Vec<Value>>,
+}
+
+impl SingBoxRule {
+    pub fn inline(tag: String, rules: Vec<Value>) -> Self {
+        Self {
+            tag,
+            type_: "inline".into(),
+            format: "".into(),
+            url: None,
+            path: None,
+            download_detour: None,
+            update_interval: None,
+            rules: Some(rules),
+        }
+    }
 }
 
 pub struct SinBoxJsonRule {
     pub type_: RuleType,
     pub json: String,
+    /// 优化后的规则数量
     pub count: u64,
+    /// 优化前的规则数量
+    pub count_raw: u64,
+    /// 仅有 ip_cidr 的值, 可作为 clash 的 ipcidr 规则
+    pub ipcidr: bool,
+}
+
+/// 规则转换结果, 无法转换的行记录在告警中
+pub struct SinBoxJsonRules {
+    pub rules: Vec<SinBoxJsonRule>,
+    pub warnings: Vec<RuleWarning>,
 }
 
 impl SinBoxJsonRule {
-    pub fn json_classical(raw: &str) -> AnyResult<Vec<SinBoxJsonRule>> {
+    /// 按目标内核的规则集版本编译为 srs 二进制规则集
+    pub fn srs(&self, version: u8) -> AnyResult<Vec<u8>> {
+        rule_srs::from_json_version(&self.json, Some(version))
+    }
+
+    pub fn json_classical(raw: &str) -> AnyResult<SinBoxJsonRules> {
         Self::_json_classical(raw, false)
     }
 
-    pub fn json_classical_process(raw: &str) -> AnyResult<Vec<SinBoxJsonRule>> {
+    pub fn json_classical_process(raw: &str) -> AnyResult<SinBoxJsonRules> {
         Self::_json_classical(raw, true)
     }
 
-    fn _json_classical(raw: &str, with_process: bool) -> AnyResult<Vec<SinBoxJsonRule>> {
-        let mut process = Vec::new();
-        let mut ip = Vec::new();
-        let mut other = BTreeMap::new();
-
-        for raw_line in raw.lines() {
-            let line = raw_line.trim();
-            if line.is_empty() || line.starts_with("#") || !line.contains(",") {
-                continue;
-            }
+    fn _json_classical(raw: &str, with_process: bool) -> AnyResult<SinBoxJsonRules> {
+        Self::json_format(raw, RuleFormat::Classical, with_process)
+    }
 
-            let split: Vec<&str> = line.splitn(2, ",").collect();
+    /// 按指定格式转换, 自动识别时根据内容推断
+    pub fn json_format(
+        raw: &str,
+        format: RuleFormat,
+        with_process: bool,
+    ) -> AnyResult<SinBoxJsonRules> {
+        let (headless, warnings) = format.parse(raw)?;
+        let rules = Self::from_headless(headless, with_process)?;
+        Ok(SinBoxJsonRules { rules, warnings })
+    }
 
-            if split.len() != 2 {
-                continue;
+    /// 合并同一类型的多个规则集 json 并优化, 移除 seen 中已出现的值. 全部被移除时返回 None
+    pub fn merge(
+        type_: RuleType,
+        jsons: &[String],
+        seen: &mut RuleSeen,
+    ) -> AnyResult<Option<SinBoxJsonRule>> {
+        let mut bucket = RuleBucket::default();
+        for json in jsons {
+            let value: Value = serde_json::from_str(json)?;
+            if let Some(Value::Array(rules)) = value.get("rules") {
+                rules.iter().cloned().for_each(|r| bucket.push(r));
             }
-            let (_raw_type, _value) = (split[0].trim(), split[1].trim());
+        }
+        bucket.into_rule(type_, Some(seen))
+    }
 
-            if _raw_type.is_empty() || _value.is_empty() {
-                continue;
-            }
+    /// 按类型归类无头规则, 并生成 sing-box 规则集 json
+    pub fn from_headless(
+        rules: Vec<HeadlessRule>,
+        with_process: bool,
+    ) -> AnyResult<Vec<SinBoxJsonRule>> {
+        let mut ip = RuleBucket::default();
+        let mut process = RuleBucket::default();
+        let mut other = RuleBucket::default();
 
-            let _m = _raw_type.to_lowercase();
-
-            if _m.starts_with("ip-cidr") {
-                ip.push(_value)
-            } else if _m == "process-name" {
-                process.push(_value)
-            } else {
-                let _type: String;
-                let _v: Value;
-
-                if _m == "dst-port" {
-                    _type = "port".to_string();
-                    _v = Value::from(_value.parse::<u16>()?)
-                } else {
-                    _type = _m.replace("-", "_");
-                    _v = Value::from(_value)
-                };
-
-                match other.get_mut(&_type) {
-                    None => {
-                        let vec = vec![_v];
-                        other.insert(_type, vec.to_owned());
-                    }
-                    Some(vec) => {
-                        vec.push(_v);
+        for rule in rules {
+            match rule {
+                HeadlessRule::Default(map) => {
+                    for (field, values) in map {
+                        let bucket = if field == rule_classical::field_ip_cidr {
+                            &mut ip
+                        } else if with_process && rule_classical::is_process(field) {
+                            &mut process
+                        } else {
+                            &mut other
+                        };
+                        bucket.fields.entry(field).or_default().extend(values);
                     }
                 }
+                HeadlessRule::Logical { .. } => {
+                    let bucket = match rule.rule_type(with_process) {
+                        RuleType::Ip => &mut ip,
+                        RuleType::Process => &mut process,
+                        RuleType::Other => &mut other,
+                    };
+                    bucket.logical.push(rule.json());
+                }
             }
         }
 
         let mut vec = vec![];
+        for (type_, bucket) in [
+            (RuleType::Ip, ip),
+            (RuleType::Process, process),
+            (RuleType::Other, other),
+        ] {
+            if let Some(rule) = bucket.into_rule(type_, None)? {
+                vec.push(rule);
+            }
+        }
+        Ok(vec)
+    }
+}
+
+/// 多个规则集依次匹配时已出现过的值. 之前的规则集先命中, 之后重复的值不会再被匹配
+#[derive(Default)]
+pub struct RuleSeen {
+    values: HashSet<String>,
+}
+
+impl RuleSeen {
+    /// 未出现过时记录并返回 true
+    fn insert(&mut self, type_: RuleType, field: &str, value: &Value) -> bool {
+        self.values
+            .insert(format!("{}|{}|{}", type_.name(), field, value))
+    }
+}
+
+#[derive(Default)]
+struct RuleBucket {
+    fields: BTreeMap<&'static str, Vec<Value>>,
+    logical: Vec<Value>,
+}
+
+impl RuleBucket {
+    /// 单字段的规则按字段合并, 其余的原样保留
+    fn push(&mut self, rule: Value) {
+        if let Value::Object(map) = &rule
+            && map.len() == 1
+            && let Some((name, Value::Array(values))) = map.iter().next()
+            && let Some(field) = rule_classical::field(name)
+        {
+            self.fields
+                .entry(field)
+                .or_default()
+                .extend(values.iter().cloned());
+            return;
+        }
+        self.logical.push(rule);
+    }
+
+    fn count(&self) -> u64 {
+        let values: usize = self.fields.values().map(|vs| vs.len()).sum();
+        (values + self.logical.len()).to_u64().unwrap()
+    }
 
-        if !ip.is_empty() {
-            let mut _ip = Map::new();
-            _ip.insert(
-                "ip_cidr".into(),
-                Value::Array(ip.iter().map(|s| Value::String((*s).into())).collect()),
-            );
-            let mut _json = BTreeMap::new();
-            _json.insert("version", Value::from(2));
-            _json.insert("rules", Value::Array(vec![Value::Object(_ip)]));
-            vec.push(SinBoxJsonRule {
-                type_: RuleType::Ip,
-                json: serde_json::to_string(&_json)?,
-                count: ip.len().to_u64().unwrap(),
-            });
+    fn into_rule(
+        mut self,
+        type_: RuleType,
+        seen: Option<&mut RuleSeen>,
+    ) -> AnyResult<Option<SinBoxJsonRule>> {
+        if self.fields.is_empty() && self.logical.is_empty() {
+            return Ok(None);
         }
 
-        if !process.is_empty() {
-            let _type = "process_name";
-            if with_process {
-                let mut _process = Map::new();
-                _process.insert(
-                    _type.into(),
-                    Value::Array(process.iter().map(|s| Value::String((*s).into())).collect()),
-                );
-                let mut _json = BTreeMap::new();
-                _json.insert("version", Value::from(2));
-                _json.insert("rules", Value::Array(vec![Value::Object(_process)]));
-                vec.push(SinBoxJsonRule {
-                    type_: RuleType::Process,
-                    json: serde_json::to_string(&_json)?,
-                    count: process.len().to_u64().unwrap(),
-                });
-            } else {
-                other.insert(
-                    _type.to_string(),
-                    process.into_iter().map(|s| Value::from(s)).collect(),
-                );
+        let count_raw = self.count();
+        rule_optimize::optimize(&mut self.fields);
+        rule_optimize::dedup(&mut self.logical);
+        if let Some(seen) = seen {
+            for (field, values) in self.fields.iter_mut() {
+                values.retain(|v| seen.insert(type_, field, v));
+            }
+            self.fields.retain(|_, values| !values.is_empty());
+            self.logical.retain(|v| seen.insert(type_, "logical", v));
+            if self.fields.is_empty() && self.logical.is_empty() {
+                return Ok(None);
             }
         }
+        let count = self.count();
+        let ipcidr = self.logical.is_empty()
+            && self
+                .fields
+                .keys()
+                .all(|f| *f == rule_classical::field_ip_cidr);
 
-        if !other.is_empty() {
-            let mut count: u64 = 0;
-            let mut _json = BTreeMap::new();
-            _json.insert("version", Value::from(2));
-            _json.insert(
-                "rules",
-                Value::Array(
-                    other
-                        .into_iter()
-                        .map(|(_k, _vs)| {
-                            count = count + _vs.len().to_u64().unwrap();
-                            let mut _m = Map::new();
-                            let k = _k.to_string();
-                            let vs = Value::Array(_vs);
-                            _m.insert(k, vs);
-                            Value::Object(_m)
-                        })
-                        .collect(),
-                ),
-            );
-            vec.push(SinBoxJsonRule {
-                type_: RuleType::Other,
-                json: serde_json::to_string(&_json)?,
-                count,
-            });
-        }
+        let mut rules: Vec<Value> = self
+            .fields
+            .into_iter()
+            .map(|(k, vs)| {
+                let mut _m = Map::new();
+                _m.insert(k.to_string(), Value::Array(vs));
+                Value::Object(_m)
+            })
+            .collect();
+        rules.extend(self.logical);
 
-        Ok(vec)
+        let mut _json = BTreeMap::new();
+        _json.insert("version", Value::from(2));
+        _json.insert("rules", Value::Array(rules));
+        Ok(Some(SinBoxJsonRule {
+            type_,
+            json: serde_json::to_string(&_json)?,
+            count,
+            count_raw,
+            ipcidr,
+        }))
     }
 }
 
@@ -300,3 +498,107 @@ pub struct ClashRule {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub interval: Option<u32>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn json(rules: Value) -> String {
+        json!({ "version": 2, "rules": rules }).to_string()
+    }
+
+    fn rules(rule: &SinBoxJsonRule) -> Value {
+        serde_json::from_str::<Value>(&rule.json).unwrap()["rules"].clone()
+    }
+
+    #[test]
+    fn clash_behavior() {
+        let behavior = |r: RuleType, source: &str| {
+            ClashBehavior::from(r, ClashFormat::from_source(source), false).map(|b| b.name())
+        };
+        // 已有的 yaml 与 text 远程来源内容为 classical
+        assert_eq!(behavior(RuleType::Ip, "a/ip.yaml").unwrap(), "classical");
+        assert_eq!(behavior(RuleType::Ip, "a/ip.list").unwrap(), "classical");
+        assert_eq!(behavior(RuleType::Ip, "a/ip.mrs?t=1").unwrap(), "ipcidr");
+        let ipcidr = ClashBehavior::from(RuleType::Ip, ClashFormat::Text, true).unwrap();
+        assert_eq!(ipcidr.name(), "ipcidr");
+        assert_eq!(behavior(RuleType::Other, "a/site.mrs").unwrap(), "domain");
+        assert_eq!(
+            behavior(RuleType::Other, "a/site.list").unwrap(),
+            "classical"
+        );
+        assert_eq!(behavior(RuleType::Process, "a/p").unwrap(), "classical");
+        assert!(behavior(RuleType::Process, "a/p.mrs").is_err());
+        assert!(
+            Rule::from_remote(RuleType::Process, "a/p.mrs".into())
+                .clash("t")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn ipcidr_only() {
+        let ip = |raw: &str| {
+            let rules = SinBoxJsonRule::json_classical(raw).unwrap().rules;
+            let rule = rules.iter().find(|r| matches!(r.type_, RuleType::Ip));
+            rule.unwrap().ipcidr
+        };
+        assert!(ip("IP-CIDR,10.0.0.0/8\nIP-CIDR6,2001:db8::/32"));
+        assert!(!ip(
+            "IP-CIDR,10.0.0.0/8\nAND,((IP-CIDR,1.1.1.1),(DST-PORT,53))"
+        ));
+    }
+
+    #[test]
+    fn merge_across_lists() {
+        let mut seen = RuleSeen::default();
+        let logical = json!({ "type": "logical", "mode": "and", "rules": [
+            { "domain": ["x.com"] }, { "port": [443] }
+        ]});
+
+        // 同一列表中的多个规则
+        let first = SinBoxJsonRule::merge(
+            RuleType::Other,
+            &[
+                json(json!([{ "domain": ["a.com", "b.com"] }, logical.clone()])),
+                json(json!([{ "domain": ["b.com", "c.com"] }])),
+            ],
+            &mut seen,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(first.count_raw, 5);
+        assert_eq!(first.count, 4);
+        assert_eq!(
+            rules(&first),
+            json!([{ "domain": ["a.com", "b.com", "c.com"] }, logical])
+        );
+
+        // 之后的列表移除已出现的值
+        let second = SinBoxJsonRule::merge(
+            RuleType::Other,
+            &[json(json!([{ "domain": ["a.com", "d.com"] }, logical]))],
+            &mut seen,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(rules(&second), json!([{ "domain": ["d.com"] }]));
+
+        // 全部已出现时不生成规则集, 不同类型互不影响
+        let none = SinBoxJsonRule::merge(
+            RuleType::Other,
+            &[json(json!([{ "domain": ["c.com"] }]))],
+            &mut seen,
+        )
+        .unwrap();
+        assert!(none.is_none());
+        let ip = SinBoxJsonRule::merge(
+            RuleType::Ip,
+            &[json(json!([{ "domain": ["c.com"] }]))],
+            &mut seen,
+        )
+        .unwrap();
+        assert!(ip.is_some());
+    }
+}
@@ -0,0 +1,450 @@
+use crate::rule::RuleType;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+
+pub const field_domain: &str = "domain";
+pub const field_domain_suffix: &str = "domain_suffix";
+pub const field_domain_keyword: &str = "domain_keyword";
+pub const field_domain_regex: &str = "domain_regex";
+pub const field_ip_cidr: &str = "ip_cidr";
+pub const field_source_ip_cidr: &str = "source_ip_cidr";
+pub const field_port: &str = "port";
+pub const field_port_range: &str = "port_range";
+pub const field_source_port: &str = "source_port";
+pub const field_source_port_range: &str = "source_port_range";
+pub const field_process_name: &str = "process_name";
+pub const field_process_path: &str = "process_path";
+pub const field_process_path_regex: &str = "process_path_regex";
+pub const field_network: &str = "network";
+
+/// 规则集中按字段合并的全部字段
+const fields_all: &[&str] = &[
+    field_domain,
+    field_domain_suffix,
+    field_domain_keyword,
+    field_domain_regex,
+    field_ip_cidr,
+    field_source_ip_cidr,
+    field_port,
+    field_port_range,
+    field_source_port,
+    field_source_port_range,
+    field_process_name,
+    field_process_path,
+    field_process_path_regex,
+    field_network,
+];
+
+/// 字段名转为常量, 不支持合并的字段返回 None
+pub fn field(name: &str) -> Option<&'static str> {
+    fields_all.iter().find(|f| **f == name).copied()
+}
+
+/// clash 规则附加参数: 匹配来源地址
+const option_src: &str = "src";
+
+/// 单行规则的告警信息, 不影响其他行的转换
+#[derive(Debug, Clone)]
+pub struct RuleWarning {
+    /// 行号, 从1开始
+    pub line: usize,
+    pub content: String,
+    pub reason: String,
+}
+
+impl fmt::Display for RuleWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "第{}行[{}]: {}", self.line, self.content, self.reason)
+    }
+}
+
+/// sing-box 无头规则
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeadlessRule {
+    /// 字段 -> 值, 同一规则内的不同字段按 sing-box 的语义组合
+    Default(BTreeMap<&'static str, Vec<Value>>),
+    Logical {
+        /// and / or
+        mode: &'static str,
+        invert: bool,
+        rules: Vec<HeadlessRule>,
+    },
+}
+
+impl HeadlessRule {
+    pub(crate) fn single(field: &'static str, value: Value) -> Self {
+        let mut map = BTreeMap::new();
+        map.insert(field, vec![value]);
+        HeadlessRule::Default(map)
+    }
+
+    pub fn json(&self) -> Value {
+        match self {
+            HeadlessRule::Default(map) => {
+                let mut m = Map::new();
+                map.iter().for_each(|(k, vs)| {
+                    m.insert(k.to_string(), Value::Array(vs.clone()));
+                });
+                Value::Object(m)
+            }
+            HeadlessRule::Logical {
+                mode,
+                invert,
+                rules,
+            } => {
+                let mut m = Map::new();
+                m.insert("type".into(), Value::from("logical"));
+                m.insert("mode".into(), Value::from(*mode));
+                m.insert(
+                    "rules".into(),
+                    Value::Array(rules.iter().map(|r| r.json()).collect()),
+                );
+                if *invert {
+                    m.insert("invert".into(), Value::Bool(true));
+                }
+                Value::Object(m)
+            }
+        }
+    }
+
+    pub fn fields(&self) -> Vec<&'static str> {
+        match self {
+            HeadlessRule::Default(map) => map.keys().copied().collect(),
+            HeadlessRule::Logical { rules, .. } => rules.iter().flat_map(|r| r.fields()).collect(),
+        }
+    }
+
+    /// 规则归类. 含ip的需要解析域名, 放在ip中; 纯进程的放在进程中
+    pub fn rule_type(&self, with_process: bool) -> RuleType {
+        let fields = self.fields();
+        if fields.contains(&field_ip_cidr) {
+            RuleType::Ip
+        } else if with_process && !fields.is_empty() && fields.iter().all(|f| is_process(f)) {
+            RuleType::Process
+        } else {
+            RuleType::Other
+        }
+    }
+}
+
+pub fn is_process(field: &str) -> bool {
+    field == field_process_name || field == field_process_path || field == field_process_path_regex
+}
+
+/// 解析单行 clash classical 规则. 返回 None 表示空行或注释
+pub fn parse_line(raw: &str) -> Result<Option<HeadlessRule>, String> {
+    let line = raw.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+        return Ok(None);
+    }
+
+    let (_type, _value) = match line.split_once(',') {
+        None => return Err("缺少规则值".into()),
+        Some((t, v)) => (t.trim().to_uppercase(), v.trim()),
+    };
+
+    if _type.is_empty() || _value.is_empty() {
+        return Err("规则类型或规则值为空".into());
+    }
+
+    match _type.as_str() {
+        "AND" => parse_logical("and", false, _value).map(Some),
+        "OR" => parse_logical("or", false, _value).map(Some),
+        "NOT" => parse_logical("and", true, _value).map(Some),
+        _ => parse_default(&_type, _value).map(Some),
+    }
+}
+
+fn parse_default(_type: &str, raw_value: &str) -> Result<HeadlessRule, String> {
+    let mut split = raw_value.split(',').map(|s| s.trim());
+    let value = split.next().unwrap_or("");
+    if value.is_empty() {
+        return Err("规则值为空".into());
+    }
+    // 剩余的为附加参数或目标出口. 目标出口由配置决定, no-resolve 对规则集无意义, 均直接忽略
+    let options: Vec<String> = split.map(|s| s.to_lowercase()).collect();
+    let src = options.iter().any(|o| o == option_src);
+
+    match _type {
+        "DOMAIN" => Ok(HeadlessRule::single(field_domain, value.into())),
+        "DOMAIN-SUFFIX" => Ok(HeadlessRule::single(
+            field_domain_suffix,
+            value.trim_start_matches('.').into(),
+        )),
+        "DOMAIN-KEYWORD" => Ok(HeadlessRule::single(field_domain_keyword, value.into())),
+        "DOMAIN-REGEX" => Ok(HeadlessRule::single(field_domain_regex, value.into())),
+        "DOMAIN-WILDCARD" => Ok(HeadlessRule::single(
+            field_domain_regex,
+            wildcard_regex(value).into(),
+        )),
+        "IP-CIDR" | "IP-CIDR6" => {
+            let field = if src {
+                field_source_ip_cidr
+            } else {
+                field_ip_cidr
+            };
+            Ok(HeadlessRule::single(field, cidr(value)?.into()))
+        }
+        // SRC-IP 为 surge 写法
+        "SRC-IP-CIDR" | "SRC-IP" => Ok(HeadlessRule::single(
+            field_source_ip_cidr,
+            cidr(value)?.into(),
+        )),
+        // DEST-PORT 为 surge 写法
+        "DST-PORT" | "DEST-PORT" => port(field_port, field_port_range, value),
+        "SRC-PORT" => port(field_source_port, field_source_port_range, value),
+        "PROCESS-NAME" => Ok(HeadlessRule::single(field_process_name, value.into())),
+        "PROCESS-PATH" => Ok(HeadlessRule::single(field_process_path, value.into())),
+        "PROCESS-PATH-REGEX" => Ok(HeadlessRule::single(field_process_path_regex, value.into())),
+        "NETWORK" => {
+            let network = value.to_lowercase();
+            if network != "tcp" && network != "udp" {
+                return Err(format!("不支持的网络类型: {}", value));
+            }
+            Ok(HeadlessRule::single(field_network, network.into()))
+        }
+        "GEOSITE" | "GEOIP" | "SRC-GEOIP" => Err("geo规则无法内联到规则集, 已跳过".into()),
+        "IP-ASN" | "SRC-IP-ASN" | "IP-SUFFIX" | "SRC-IP-SUFFIX" | "IN-PORT" | "IN-TYPE"
+        | "IN-USER" | "IN-NAME" | "PROCESS-NAME-REGEX" | "UID" | "DSCP" | "RULE-SET"
+        | "SUB-RULE" | "MATCH" | "DOMAIN-SET" | "USER-AGENT" | "URL-REGEX" => {
+            Err(format!("sing-box 规则集不支持的类型: {}", _type))
+        }
+        _ => Err(format!("未知的规则类型: {}", _type)),
+    }
+}
+
+/// 解析逻辑规则, 格式: AND,((DOMAIN,a.com),(NETWORK,UDP))[,目标]
+fn parse_logical(
+    mode: &'static str,
+    invert: bool,
+    raw_value: &str,
+) -> Result<HeadlessRule, String> {
+    let inner = take_group(raw_value).ok_or_else(|| "逻辑规则括号不匹配".to_string())?;
+    let groups = split_groups(inner)?;
+    if groups.is_empty() {
+        return Err("逻辑规则没有子规则".into());
+    }
+    if invert && groups.len() != 1 {
+        return Err("NOT 规则只能有一个子规则".into());
+    }
+
+    let mut rules = Vec::with_capacity(groups.len());
+    for group in groups {
+        match parse_line(group)? {
+            None => return Err("逻辑规则存在空的子规则".into()),
+            Some(rule) => rules.push(rule),
+        }
+    }
+
+    Ok(HeadlessRule::Logical {
+        mode,
+        invert,
+        rules,
+    })
+}
+
+/// 取出开头的括号组内部内容
+fn take_group(source: &str) -> Option<&str> {
+    let source = source.trim_start();
+    if !source.starts_with('(') {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, c) in source.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&source[1..i]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// 拆分同级的多个括号组: (a),(b) -> [a, b]
+fn split_groups(source: &str) -> Result<Vec<&str>, String> {
+    let mut groups = Vec::new();
+    let mut rest = source.trim();
+    while !rest.is_empty() {
+        let group = take_group(rest).ok_or_else(|| "逻辑规则子规则格式异常".to_string())?;
+        groups.push(group);
+        // 跳过 `(` + group + `)`
+        let end = group.len() + 2;
+        rest = rest[end..]
+            .trim_start()
+            .trim_start_matches(',')
+            .trim_start();
+    }
+    Ok(groups)
+}
+
+/// 通配符域名转正则, `*` 匹配单级, `?` 匹配单个字符
+pub fn wildcard_regex(value: &str) -> String {
+    let mut regex = String::from("^");
+    for c in value.chars() {
+        match c {
+            '*' => regex.push_str("[^.]+"),
+            '?' => regex.push_str("[^.]"),
+            '.' | '+' | '(' | ')' | '[' | ']' | '{' | '}' | '^' | '$' | '|' | '\\' => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            _ => regex.push(c),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// 校验并规范化 cidr, 单个ip补全前缀
+pub fn cidr(value: &str) -> Result<String, String> {
+    let (ip, prefix) = match value.split_once('/') {
+        None => (value, None),
+        Some((i, p)) => (i, Some(p)),
+    };
+    let addr = ip
+        .parse::<IpAddr>()
+        .map_err(|_| format!("无效的ip: {}", value))?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        None => max,
+        Some(p) => p
+            .parse::<u8>()
+            .ok()
+            .filter(|p| *p <= max)
+            .ok_or_else(|| format!("无效的前缀长度: {}", value))?,
+    };
+    Ok(format!("{}/{}", addr, prefix))
+}
+
+/// 端口支持: 80 / 1000-2000 / 1000:2000 / 80/443 的组合
+fn port(
+    field: &'static str,
+    field_range: &'static str,
+    value: &str,
+) -> Result<HeadlessRule, String> {
+    let parse = |s: &str| {
+        s.trim()
+            .parse::<u16>()
+            .map_err(|_| format!("无效的端口: {}", value))
+    };
+
+    let mut map: BTreeMap<&'static str, Vec<Value>> = BTreeMap::new();
+    for item in value.split('/').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let range = item.split_once('-').or_else(|| item.split_once(':'));
+        match range {
+            None => map.entry(field).or_default().push(parse(item)?.into()),
+            Some((start, end)) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    return Err(format!("无效的端口范围: {}", item));
+                }
+                map.entry(field_range)
+                    .or_default()
+                    .push(format!("{}:{}", start, end).into());
+            }
+        }
+    }
+
+    if map.is_empty() {
+        return Err("端口为空".into());
+    }
+    Ok(HeadlessRule::Default(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(line: &str) -> Value {
+        parse_line(line).unwrap().unwrap().json()
+    }
+
+    #[test]
+    fn parse_default_rules() {
+        assert_eq!(parse("DOMAIN,a.com"), json!({ "domain": ["a.com"] }));
+        assert_eq!(
+            parse("domain-suffix, .a.com ,Proxy"),
+            json!({ "domain_suffix": ["a.com"] })
+        );
+        assert_eq!(
+            parse("DOMAIN-WILDCARD,*.a?.com"),
+            json!({ "domain_regex": ["^[^.]+\\.a[^.]\\.com$"] })
+        );
+        assert_eq!(
+            parse("IP-CIDR,10.0.0.1,no-resolve"),
+            json!({ "ip_cidr": ["10.0.0.1/32"] })
+        );
+        assert_eq!(
+            parse("IP-CIDR6,2001:db8::/32,DIRECT,src"),
+            json!({ "source_ip_cidr": ["2001:db8::/32"] })
+        );
+        assert_eq!(
+            parse("DST-PORT,80/443/1000-2000/3000:4000"),
+            json!({ "port": [80, 443], "port_range": ["1000:2000", "3000:4000"] })
+        );
+        assert_eq!(parse("NETWORK,UDP"), json!({ "network": ["udp"] }));
+        assert_eq!(
+            parse("PROCESS-NAME,curl"),
+            json!({ "process_name": ["curl"] })
+        );
+    }
+
+    #[test]
+    fn parse_logical_rules() {
+        assert_eq!(
+            parse("AND,((DOMAIN,a.com),(NETWORK,UDP)),REJECT"),
+            json!({
+                "type": "logical",
+                "mode": "and",
+                "rules": [{ "domain": ["a.com"] }, { "network": ["udp"] }]
+            })
+        );
+        assert_eq!(
+            parse("NOT,((OR,((DOMAIN,a.com),(DST-PORT,53))))"),
+            json!({
+                "type": "logical",
+                "mode": "and",
+                "invert": true,
+                "rules": [{
+                    "type": "logical",
+                    "mode": "or",
+                    "rules": [{ "domain": ["a.com"] }, { "port": [53] }]
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn skip_and_reject() {
+        assert_eq!(parse_line("").unwrap(), None);
+        assert_eq!(parse_line("# comment").unwrap(), None);
+        assert_eq!(parse_line("// comment").unwrap(), None);
+        assert!(parse_line("DOMAIN").is_err());
+        assert!(parse_line("GEOSITE,cn").is_err());
+        assert!(parse_line("UNKNOWN,a").is_err());
+        assert!(parse_line("IP-CIDR,10.0.0.0/33").is_err());
+        assert!(parse_line("DST-PORT,2000-1000").is_err());
+        assert!(parse_line("NETWORK,icmp").is_err());
+        assert!(parse_line("AND,((DOMAIN,a.com)").is_err());
+        assert!(parse_line("NOT,((DOMAIN,a.com),(DOMAIN,b.com))").is_err());
+    }
+
+    #[test]
+    fn rule_type() {
+        let ip = parse_line("AND,((IP-CIDR,10.0.0.0/8),(DST-PORT,80))")
+            .unwrap()
+            .unwrap();
+        assert!(matches!(ip.rule_type(true), RuleType::Ip));
+        let process = parse_line("PROCESS-NAME,curl").unwrap().unwrap();
+        assert!(matches!(process.rule_type(true), RuleType::Process));
+        assert!(matches!(process.rule_type(false), RuleType::Other));
+    }
+}
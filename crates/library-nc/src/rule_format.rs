@@ -0,0 +1,431 @@
+use crate::rule::RuleFormat;
+use crate::rule_classical;
+use crate::rule_classical::{
+    HeadlessRule, RuleWarning, field_domain, field_domain_regex, field_domain_suffix,
+    field_ip_cidr,
+};
+use library_core::core::AnyResult;
+use library_core::yml::YmlValueExt;
+use serde_yaml::Value;
+use std::net::IpAddr;
+
+/// 推断格式时最多采样的有效行数
+const detect_sample: usize = 200;
+
+/// hosts 文件中的本机条目, 不作为规则
+const hosts_local: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "ip6-allhosts",
+    "0.0.0.0",
+];
+
+type LineParser = fn(&str) -> Result<Vec<HeadlessRule>, String>;
+
+impl RuleFormat {
+    /// 根据内容推断格式, 无法判断时按纯域名列表处理
+    pub fn detect(raw: &str) -> RuleFormat {
+        let mut classical = 0;
+        let mut surge = 0;
+        let mut hosts = 0;
+        let mut adguard = 0;
+        let mut domain = 0;
+
+        let lines = raw
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .take(detect_sample);
+
+        for line in lines {
+            if line.starts_with("payload:") {
+                return RuleFormat::Yaml;
+            }
+
+            if line.starts_with('!')
+                || line.starts_with("[Adblock")
+                || line.starts_with("||")
+                || line.starts_with("@@")
+                || line.contains("##")
+            {
+                adguard += 1;
+            } else if is_hosts_line(line) {
+                hosts += 1;
+            } else if let Some((_type, _)) = line.split_once(',') {
+                let _type = _type.trim();
+                if _type == "DEST-PORT"
+                    || _type == "SRC-IP"
+                    || _type == "USER-AGENT"
+                    || _type == "URL-REGEX"
+                    || _type == "DOMAIN-SET"
+                {
+                    surge += 1;
+                } else if _type.chars().all(|c| c.is_ascii_uppercase() || c == '-') {
+                    classical += 1;
+                }
+            } else {
+                domain += 1;
+            }
+        }
+
+        if surge > 0 && surge + classical >= hosts.max(adguard).max(domain) {
+            return RuleFormat::Surge;
+        }
+
+        // 同票时按照列表顺序优先
+        [
+            (classical, RuleFormat::Classical),
+            (hosts, RuleFormat::Hosts),
+            (adguard, RuleFormat::Adguard),
+            (domain, RuleFormat::Domain),
+        ]
+        .into_iter()
+        .fold((0, RuleFormat::Domain), |max, (count, format)| {
+            if count > max.0 { (count, format) } else { max }
+        })
+        .1
+    }
+
+    /// 解析为无头规则, 无法转换的行记录在告警中
+    pub fn parse(self, raw: &str) -> AnyResult<(Vec<HeadlessRule>, Vec<RuleWarning>)> {
+        let parser: LineParser = match self {
+            RuleFormat::Auto => return Self::detect(raw).parse(raw),
+            RuleFormat::Yaml => return parse_yaml(raw),
+            // surge 的 list 与 classical 语法一致, 类型别名在 classical 中兼容
+            RuleFormat::Classical | RuleFormat::Surge => |line| {
+                rule_classical::parse_line(line).map(|o| o.into_iter().collect())
+            },
+            RuleFormat::Domain => parse_domain_line,
+            RuleFormat::Hosts => parse_hosts_line,
+            RuleFormat::Adguard => parse_adguard_line,
+        };
+
+        let mut rules = Vec::new();
+        let mut warnings = Vec::new();
+        for (i, line) in raw.lines().enumerate() {
+            match parser(line) {
+                Ok(vec) => rules.extend(vec),
+                Err(reason) => warnings.push(RuleWarning {
+                    line: i + 1,
+                    content: line.trim().to_string(),
+                    reason,
+                }),
+            }
+        }
+        Ok((rules, warnings))
+    }
+}
+
+/// clash rule-provider 的 yaml, 取 payload 数组. 告警的行号为 payload 中的序号
+fn parse_yaml(raw: &str) -> AnyResult<(Vec<HeadlessRule>, Vec<RuleWarning>)> {
+    let value: Value = serde_yaml::from_str(raw)?;
+    let payload = value
+        .get("payload")
+        .and_then(Value::as_sequence)
+        .cloned()
+        .unwrap_or_default();
+
+    let mut rules = Vec::new();
+    let mut warnings = Vec::new();
+    for (i, item) in payload.iter().enumerate() {
+        let line = item.string_empty();
+        match parse_entry(&line) {
+            Ok(vec) => rules.extend(vec),
+            Err(reason) => warnings.push(RuleWarning {
+                line: i + 1,
+                content: line.trim().to_string(),
+                reason,
+            }),
+        }
+    }
+    Ok((rules, warnings))
+}
+
+/// payload 中的条目可能是 classical / domain / ipcidr 任意一种
+fn parse_entry(line: &str) -> Result<Vec<HeadlessRule>, String> {
+    if line.contains(',') {
+        rule_classical::parse_line(line).map(|o| o.into_iter().collect())
+    } else {
+        parse_domain_line(line)
+    }
+}
+
+/// 纯文本列表, 每行一个域名或者cidr
+fn parse_domain_line(raw: &str) -> Result<Vec<HeadlessRule>, String> {
+    let line = raw.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+        return Ok(vec![]);
+    }
+
+    if line.parse::<IpAddr>().is_ok() || line.contains('/') {
+        let cidr = rule_classical::cidr(line)?;
+        return Ok(vec![HeadlessRule::single(field_ip_cidr, cidr.into())]);
+    }
+
+    domain(line).map(|r| vec![r])
+}
+
+/// `+.` 与 `.` 开头表示包含子域名, 带 `*` 的按通配符处理
+pub fn domain(value: &str) -> Result<HeadlessRule, String> {
+    let value = value.trim().trim_end_matches('.').to_lowercase();
+
+    let (field, host) = if let Some(v) = value.strip_prefix("+.") {
+        (field_domain_suffix, v)
+    } else if let Some(v) = value.strip_prefix('.') {
+        (field_domain_suffix, v)
+    } else {
+        (field_domain, value.as_str())
+    };
+
+    let valid = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '*');
+    if !valid {
+        return Err(format!("无效的域名: {}", value));
+    }
+
+    if host.contains('*') {
+        let regex = rule_classical::wildcard_regex(host);
+        return Ok(HeadlessRule::single(field_domain_regex, regex.into()));
+    }
+
+    Ok(HeadlessRule::single(field, host.into()))
+}
+
+fn is_hosts_line(line: &str) -> bool {
+    let mut split = line.split_whitespace();
+    match (split.next(), split.next()) {
+        (Some(ip), Some(_)) => ip.parse::<IpAddr>().is_ok(),
+        _ => false,
+    }
+}
+
+/// hosts 格式: ip 域名1 域名2 # 注释
+fn parse_hosts_line(raw: &str) -> Result<Vec<HeadlessRule>, String> {
+    let line = raw.split('#').next().unwrap_or("").trim();
+    if line.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut split = line.split_whitespace();
+    let ip = split.next().unwrap_or("");
+    if ip.parse::<IpAddr>().is_err() {
+        return Err(format!("无效的ip: {}", ip));
+    }
+
+    let mut vec = Vec::new();
+    for host in split {
+        if hosts_local.contains(&host) {
+            continue;
+        }
+        vec.push(HeadlessRule::single(field_domain, host.to_lowercase().into()));
+    }
+    Ok(vec)
+}
+
+/// AdGuard / ABP 过滤器, 仅支持域名级别的拦截规则
+fn parse_adguard_line(raw: &str) -> Result<Vec<HeadlessRule>, String> {
+    let line = raw.trim();
+    if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+        return Ok(vec![]);
+    }
+
+    // 元素隐藏等页面规则, 与网络无关
+    if line.contains("##")
+        || line.contains("#@#")
+        || line.contains("#?#")
+        || line.contains("#$#")
+        || line.contains("#%#")
+    {
+        return Ok(vec![]);
+    }
+
+    if line.starts_with('#') {
+        return Ok(vec![]);
+    }
+
+    if line.starts_with("@@") {
+        return Err("不支持例外规则".into());
+    }
+
+    if line.len() > 1 && line.starts_with('/') && line.ends_with('/') {
+        return Err("不支持正则规则".into());
+    }
+
+    if is_hosts_line(line) {
+        return parse_hosts_line(line);
+    }
+
+    let (pattern, modifiers) = match line.split_once('$') {
+        None => (line, None),
+        Some((p, m)) => (p, Some(m)),
+    };
+
+    if let Some(m) = modifiers {
+        let unsupported: Vec<&str> = m
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty() && *s != "important")
+            .collect();
+        if !unsupported.is_empty() {
+            return Err(format!("不支持的修饰符: {}", unsupported.join(",")));
+        }
+    }
+
+    if let Some(host) = pattern.strip_prefix("||") {
+        let host = host.trim_end_matches('|').trim_end_matches('^');
+        if host.contains('/') || host.contains(':') || host.contains('^') {
+            return Err("不支持带路径或端口的规则".into());
+        }
+        return domain(&format!("+.{}", host)).map(|r| vec![r]);
+    }
+
+    if let Some(url) = pattern.strip_prefix('|') {
+        let url = url.trim_end_matches('|');
+        let host = url
+            .strip_prefix("https://")
+            .or_else(|| url.strip_prefix("http://"))
+            .ok_or_else(|| "不支持的地址规则".to_string())?;
+        let host = host
+            .split(['/', '^', ':'])
+            .next()
+            .unwrap_or("");
+        return domain(host).map(|r| vec![r]);
+    }
+
+    domain(pattern.trim_end_matches('^')).map(|r| vec![r])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn rules(format: RuleFormat, raw: &str) -> (Vec<Value>, Vec<usize>) {
+        let (rules, warnings) = format.parse(raw).unwrap();
+        (
+            rules.iter().map(HeadlessRule::json).collect(),
+            warnings.iter().map(|w| w.line).collect(),
+        )
+    }
+
+    #[test]
+    fn detect_format() {
+        let detect = |raw: &str| RuleFormat::detect(raw).name();
+        assert_eq!(detect("payload:\n  - DOMAIN,a.com"), "yaml");
+        assert_eq!(detect("DOMAIN,a.com\nIP-CIDR,10.0.0.0/8"), "classical");
+        assert_eq!(detect("DOMAIN,a.com\nDEST-PORT,80"), "surge");
+        assert_eq!(detect("# hosts\n0.0.0.0 a.com\n127.0.0.1 b.com"), "hosts");
+        assert_eq!(detect("! title\n||a.com^\n@@||b.com^"), "adguard");
+        assert_eq!(detect("a.com\n+.b.com\n10.0.0.0/8"), "domain");
+        assert_eq!(detect(""), "domain");
+    }
+
+    #[test]
+    fn parse_domain_list() {
+        let (rules, warnings) = rules(
+            RuleFormat::Domain,
+            "# comment\nA.com.\n+.b.com\n.c.com\n*.d.com\n10.0.0.1\n10.0.0.0/8\nbad domain",
+        );
+        assert_eq!(
+            rules,
+            [
+                json!({ "domain": ["a.com"] }),
+                json!({ "domain_suffix": ["b.com"] }),
+                json!({ "domain_suffix": ["c.com"] }),
+                json!({ "domain_regex": ["^[^.]+\\.d\\.com$"] }),
+                json!({ "ip_cidr": ["10.0.0.1/32"] }),
+                json!({ "ip_cidr": ["10.0.0.0/8"] }),
+            ]
+        );
+        assert_eq!(warnings, [8]);
+    }
+
+    #[test]
+    fn parse_hosts() {
+        let (rules, warnings) = rules(
+            RuleFormat::Hosts,
+            "127.0.0.1 localhost\n0.0.0.0 A.com b.com # ads\n::1 ip6-localhost\nbad c.com",
+        );
+        assert_eq!(
+            rules,
+            [
+                json!({ "domain": ["a.com"] }),
+                json!({ "domain": ["b.com"] })
+            ]
+        );
+        assert_eq!(warnings, [4]);
+    }
+
+    #[test]
+    fn parse_adguard() {
+        let (rules, warnings) = rules(
+            RuleFormat::Adguard,
+            "[Adblock Plus 2.0]\n! comment\n||a.com^\n||b.com^$important\n|https://c.com/path|\nexample.com##.ad\n@@||d.com^\n/ad[0-9]+/\n||e.com^$third-party\n||f.com/path\n0.0.0.0 g.com",
+        );
+        assert_eq!(
+            rules,
+            [
+                json!({ "domain_suffix": ["a.com"] }),
+                json!({ "domain_suffix": ["b.com"] }),
+                json!({ "domain": ["c.com"] }),
+                json!({ "domain": ["g.com"] }),
+            ]
+        );
+        assert_eq!(warnings, [7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn parse_surge() {
+        let (rules, warnings) = rules(
+            RuleFormat::Surge,
+            "DOMAIN-SUFFIX,a.com\nSRC-IP,192.168.1.1\nDEST-PORT,443\nUSER-AGENT,curl*",
+        );
+        assert_eq!(
+            rules,
+            [
+                json!({ "domain_suffix": ["a.com"] }),
+                json!({ "source_ip_cidr": ["192.168.1.1/32"] }),
+                json!({ "port": [443] }),
+            ]
+        );
+        assert_eq!(warnings, [4]);
+    }
+
+    #[test]
+    fn parse_payload_yaml() {
+        let raw = "payload:\n  - DOMAIN,a.com\n  - '+.b.com'\n  - 10.0.0.0/8\n  - GEOIP,cn\n";
+        let (rules, warnings) = rules(RuleFormat::Yaml, raw);
+        assert_eq!(
+            rules,
+            [
+                json!({ "domain": ["a.com"] }),
+                json!({ "domain_suffix": ["b.com"] }),
+                json!({ "ip_cidr": ["10.0.0.0/8"] }),
+            ]
+        );
+        assert_eq!(warnings, [4]);
+        assert!(RuleFormat::Yaml.parse("payload: [").is_err());
+    }
+
+    #[test]
+    fn parse_auto() {
+        let (rules, _) = rules(RuleFormat::Auto, "0.0.0.0 a.com\n0.0.0.0 b.com");
+        assert_eq!(
+            rules,
+            [
+                json!({ "domain": ["a.com"] }),
+                json!({ "domain": ["b.com"] })
+            ]
+        );
+    }
+}
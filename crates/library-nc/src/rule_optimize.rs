@@ -0,0 +1,173 @@
+use crate::rule_classical::{
+    field_domain, field_domain_suffix, field_ip_cidr, field_source_ip_cidr,
+};
+use crate::rule_srs;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+
+/// 优化同一规则集中按字段合并后的值: 去重, 合并cidr, 移除已被后缀覆盖的域名
+pub fn optimize(fields: &mut BTreeMap<&'static str, Vec<Value>>) {
+    for (field, values) in fields.iter_mut() {
+        if *field == field_ip_cidr || *field == field_source_ip_cidr {
+            *values = cidrs(values);
+        } else {
+            dedup(values);
+        }
+    }
+
+    let suffixes = Suffixes::new(fields.get(field_domain_suffix));
+    if let Some(values) = fields.get_mut(field_domain_suffix) {
+        values.retain(|v| v.as_str().is_none_or(|s| !suffixes.covered_suffix(s)));
+    }
+    if let Some(values) = fields.get_mut(field_domain) {
+        values.retain(|v| v.as_str().is_none_or(|s| !suffixes.covered_domain(s)));
+    }
+
+    fields.retain(|_, values| !values.is_empty());
+}
+
+/// 去重并保持原有顺序
+pub fn dedup(values: &mut Vec<Value>) {
+    let mut seen = HashSet::new();
+    values.retain(|v| seen.insert(v.to_string()));
+}
+
+/// 合并相邻与包含的 cidr, 无法解析时保持原样
+fn cidrs(values: &[Value]) -> Vec<Value> {
+    let strings: Vec<&str> = values.iter().filter_map(Value::as_str).collect();
+    if strings.len() != values.len() {
+        let mut values = values.to_vec();
+        dedup(&mut values);
+        return values;
+    }
+
+    match rule_srs::aggregate_cidrs(&strings) {
+        Ok(vec) => vec.into_iter().map(Value::from).collect(),
+        Err(_) => {
+            let mut values = values.to_vec();
+            dedup(&mut values);
+            values
+        }
+    }
+}
+
+/// 域名后缀集合. `a.com` 匹配自身及子域名, `.a.com` 仅匹配子域名
+struct Suffixes {
+    full: HashSet<String>,
+    sub: HashSet<String>,
+}
+
+impl Suffixes {
+    fn new(values: Option<&Vec<Value>>) -> Self {
+        let mut full = HashSet::new();
+        let mut sub = HashSet::new();
+        for v in values.into_iter().flatten().filter_map(Value::as_str) {
+            match v.strip_prefix('.') {
+                Some(s) => sub.insert(s.to_lowercase()),
+                None => full.insert(v.to_lowercase()),
+            };
+        }
+        Self { full, sub }
+    }
+
+    /// 依次返回上级域名: a.b.com -> b.com -> com
+    fn parents(name: &str) -> impl Iterator<Item = &str> {
+        name.match_indices('.').map(move |(i, _)| &name[i + 1..])
+    }
+
+    fn covered_domain(&self, domain: &str) -> bool {
+        let domain = domain.to_lowercase();
+        self.full.contains(&domain)
+            || Self::parents(&domain).any(|p| self.full.contains(p) || self.sub.contains(p))
+    }
+
+    fn covered_suffix(&self, suffix: &str) -> bool {
+        let suffix = suffix.to_lowercase();
+        match suffix.strip_prefix('.') {
+            // 仅匹配子域名的后缀, 被自身或上级的完整后缀覆盖
+            Some(s) => self.full.contains(s) || self.covered_parent(s),
+            None => self.covered_parent(&suffix),
+        }
+    }
+
+    fn covered_parent(&self, name: &str) -> bool {
+        Self::parents(name).any(|p| self.full.contains(p) || self.sub.contains(p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields(pairs: &[(&'static str, Value)]) -> BTreeMap<&'static str, Vec<Value>> {
+        pairs
+            .iter()
+            .map(|(f, v)| (*f, v.as_array().unwrap().clone()))
+            .collect()
+    }
+
+    #[test]
+    fn merge_cidrs_and_dedup() {
+        let mut map = fields(&[
+            (
+                field_ip_cidr,
+                json!(["10.0.1.0/24", "10.0.0.0/24", "10.0.0.0/24"]),
+            ),
+            (field_domain, json!(["a.com", "a.com", "b.com"])),
+        ]);
+        optimize(&mut map);
+        assert_eq!(
+            map[field_ip_cidr],
+            json!(["10.0.0.0/23"]).as_array().unwrap().clone()
+        );
+        assert_eq!(
+            map[field_domain],
+            json!(["a.com", "b.com"]).as_array().unwrap().clone()
+        );
+    }
+
+    #[test]
+    fn invalid_cidr_kept() {
+        let mut map = fields(&[(field_ip_cidr, json!(["10.0.0.0/24", "bad", "bad"]))]);
+        optimize(&mut map);
+        assert_eq!(
+            map[field_ip_cidr],
+            json!(["10.0.0.0/24", "bad"]).as_array().unwrap().clone()
+        );
+    }
+
+    #[test]
+    fn drop_covered_domains() {
+        let mut map = fields(&[
+            (
+                field_domain_suffix,
+                json!(["a.com", "x.a.com", ".a.com", ".b.com", "y.b.com"]),
+            ),
+            (
+                field_domain,
+                json!(["a.com", "www.A.com", "b.com", "z.b.com", "c.com"]),
+            ),
+        ]);
+        optimize(&mut map);
+        // .b.com 仅匹配子域名, b.com 自身需要保留
+        assert_eq!(
+            map[field_domain_suffix],
+            json!(["a.com", ".b.com"]).as_array().unwrap().clone()
+        );
+        assert_eq!(
+            map[field_domain],
+            json!(["b.com", "c.com"]).as_array().unwrap().clone()
+        );
+    }
+
+    #[test]
+    fn remove_empty_fields() {
+        let mut map = fields(&[
+            (field_domain_suffix, json!(["a.com"])),
+            (field_domain, json!(["a.com"])),
+        ]);
+        optimize(&mut map);
+        assert!(!map.contains_key(field_domain));
+    }
+}
@@ -0,0 +1,973 @@
+//! sing-box 二进制规则集(srs)的读写, 格式与 sing-box common/srs 保持一致
+
+use crate::core::NcError;
+use crate::rule_classical::{
+    field_domain, field_domain_keyword, field_domain_regex, field_domain_suffix, field_ip_cidr,
+    field_network, field_port, field_port_range, field_process_name, field_process_path,
+    field_process_path_regex, field_source_ip_cidr, field_source_port, field_source_port_range,
+};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use library_core::core::AnyResult;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+pub const magic: [u8; 3] = *b"SRS";
+/// 可读取的最高版本
+pub const version_max: u8 = 3;
+/// domain_suffix 使用旧版编码的版本
+const version_legacy: u8 = 1;
+
+const item_query_type: u8 = 0;
+const item_network: u8 = 1;
+const item_domain: u8 = 2;
+const item_domain_keyword: u8 = 3;
+const item_domain_regex: u8 = 4;
+const item_source_ip_cidr: u8 = 5;
+const item_ip_cidr: u8 = 6;
+const item_source_port: u8 = 7;
+const item_source_port_range: u8 = 8;
+const item_port: u8 = 9;
+const item_port_range: u8 = 10;
+const item_process_name: u8 = 11;
+const item_process_path: u8 = 12;
+const item_package_name: u8 = 13;
+const item_wifi_ssid: u8 = 14;
+const item_wifi_bssid: u8 = 15;
+const item_adguard_domain: u8 = 16;
+const item_process_path_regex: u8 = 17;
+const item_network_is_expensive: u8 = 19;
+const item_network_is_constrained: u8 = 20;
+const item_final: u8 = 0xFF;
+
+const rule_default: u8 = 0;
+const rule_logical: u8 = 1;
+
+const field_query_type: &str = "query_type";
+const field_package_name: &str = "package_name";
+const field_wifi_ssid: &str = "wifi_ssid";
+const field_wifi_bssid: &str = "wifi_bssid";
+const field_network_is_expensive: &str = "network_is_expensive";
+const field_network_is_constrained: &str = "network_is_constrained";
+
+/// 域名树中的后缀标记: 仅匹配子域名
+const label_prefix: char = '\r';
+/// 域名树中的后缀标记: 匹配自身及子域名
+const label_root: char = '\n';
+
+/// 字符串列表类型的字段
+const string_items: &[(&str, u8)] = &[
+    (field_network, item_network),
+    (field_domain_keyword, item_domain_keyword),
+    (field_domain_regex, item_domain_regex),
+    (field_source_port_range, item_source_port_range),
+    (field_port_range, item_port_range),
+    (field_process_name, item_process_name),
+    (field_process_path, item_process_path),
+    (field_package_name, item_package_name),
+    (field_wifi_ssid, item_wifi_ssid),
+    (field_wifi_bssid, item_wifi_bssid),
+    (field_process_path_regex, item_process_path_regex),
+];
+
+/// 端口等 u16 列表类型的字段
+const uint16_items: &[(&str, u8)] = &[
+    (field_query_type, item_query_type),
+    (field_source_port, item_source_port),
+    (field_port, item_port),
+];
+
+const cidr_items: &[(&str, u8)] = &[
+    (field_source_ip_cidr, item_source_ip_cidr),
+    (field_ip_cidr, item_ip_cidr),
+];
+
+const flag_items: &[(&str, u8)] = &[
+    (field_network_is_expensive, item_network_is_expensive),
+    (field_network_is_constrained, item_network_is_constrained),
+];
+
+fn invalid<T>(msg: impl Into<String>) -> AnyResult<T> {
+    Err(Box::new(NcError::InvalidSrs(msg.into())))
+}
+
+/// 将 sing-box 规则集 json 编译为 srs
+pub fn from_json(json: &str) -> AnyResult<Vec<u8>> {
+    from_json_version(json, None)
+}
+
+/// 按指定版本编译, version 为空时使用 json 中的版本
+pub fn from_json_version(json: &str, version: Option<u8>) -> AnyResult<Vec<u8>> {
+    let value: Value = serde_json::from_str(json)?;
+    let version = version.map(u64::from).unwrap_or_else(|| {
+        value
+            .get("version")
+            .and_then(Value::as_u64)
+            .unwrap_or(version_legacy as u64)
+    });
+    if version == 0 || version > version_max as u64 {
+        return invalid(format!("不支持的版本: {}", version));
+    }
+    let rules = value
+        .get("rules")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    write(&rules, version as u8)
+}
+
+/// 按指定版本写入规则
+pub fn write(rules: &[Value], version: u8) -> AnyResult<Vec<u8>> {
+    let mut body = Vec::new();
+    write_uvarint(&mut body, rules.len() as u64);
+    for rule in rules {
+        write_rule(&mut body, rule, version)?;
+    }
+
+    let mut bytes = Vec::with_capacity(body.len() / 2);
+    bytes.extend_from_slice(&magic);
+    bytes.push(version);
+    let mut encoder = ZlibEncoder::new(bytes, Compression::best());
+    encoder.write_all(&body)?;
+    Ok(encoder.finish()?)
+}
+
+fn write_rule(w: &mut Vec<u8>, rule: &Value, version: u8) -> AnyResult<()> {
+    let map = match rule.as_object() {
+        None => return invalid("规则必须是对象"),
+        Some(m) => m,
+    };
+    match map.get("type").and_then(Value::as_str) {
+        None | Some("default") => write_default(w, map, version),
+        Some("logical") => write_logical(w, map, version),
+        Some(t) => invalid(format!("未知的规则类型: {}", t)),
+    }
+}
+
+fn write_default(w: &mut Vec<u8>, map: &Map<String, Value>, version: u8) -> AnyResult<()> {
+    for key in map.keys() {
+        let known = key == "type"
+            || key == "invert"
+            || key == field_domain
+            || key == field_domain_suffix
+            || string_items.iter().any(|(f, _)| f == key)
+            || uint16_items.iter().any(|(f, _)| f == key)
+            || cidr_items.iter().any(|(f, _)| f == key)
+            || flag_items.iter().any(|(f, _)| f == key);
+        if !known {
+            return invalid(format!("不支持的字段: {}", key));
+        }
+    }
+
+    w.push(rule_default);
+
+    for (field, item) in uint16_items {
+        let values = listable(map, field);
+        if values.is_empty() {
+            continue;
+        }
+        w.push(*item);
+        write_uvarint(w, values.len() as u64);
+        for v in values {
+            let port = v
+                .as_u64()
+                .or_else(|| v.as_str().and_then(|s| s.trim().parse().ok()))
+                .filter(|p| *p <= u16::MAX as u64);
+            match port {
+                None => return invalid(format!("{} 的值无效: {}", field, v)),
+                Some(p) => w.extend_from_slice(&(p as u16).to_be_bytes()),
+            }
+        }
+    }
+
+    let domains = strings(map, field_domain)?;
+    let suffixes = strings(map, field_domain_suffix)?;
+    if !domains.is_empty() || !suffixes.is_empty() {
+        w.push(item_domain);
+        write_domains(w, &domains, &suffixes, version == version_legacy);
+    }
+
+    for (field, item) in string_items {
+        let values = strings(map, field)?;
+        if values.is_empty() {
+            continue;
+        }
+        w.push(*item);
+        write_uvarint(w, values.len() as u64);
+        for v in values {
+            write_bytes(w, v.as_bytes());
+        }
+    }
+
+    for (field, item) in cidr_items {
+        let values = strings(map, field)?;
+        if values.is_empty() {
+            continue;
+        }
+        w.push(*item);
+        write_ip_set(w, &values)?;
+    }
+
+    for (field, item) in flag_items {
+        if map.get(*field).and_then(Value::as_bool).unwrap_or(false) {
+            w.push(*item);
+        }
+    }
+
+    w.push(item_final);
+    w.push(invert(map) as u8);
+    Ok(())
+}
+
+fn write_logical(w: &mut Vec<u8>, map: &Map<String, Value>, version: u8) -> AnyResult<()> {
+    w.push(rule_logical);
+    match map.get("mode").and_then(Value::as_str) {
+        Some("and") => w.push(0),
+        Some("or") => w.push(1),
+        mode => return invalid(format!("未知的逻辑模式: {:?}", mode)),
+    }
+    let rules = listable(map, "rules");
+    write_uvarint(w, rules.len() as u64);
+    for rule in rules {
+        write_rule(w, rule, version)?;
+    }
+    w.push(invert(map) as u8);
+    Ok(())
+}
+
+fn invert(map: &Map<String, Value>) -> bool {
+    map.get("invert").and_then(Value::as_bool).unwrap_or(false)
+}
+
+/// sing-box 的列表字段允许直接写单个值
+fn listable<'a>(map: &'a Map<String, Value>, field: &str) -> Vec<&'a Value> {
+    match map.get(field) {
+        None | Some(Value::Null) => vec![],
+        Some(Value::Array(vs)) => vs.iter().collect(),
+        Some(v) => vec![v],
+    }
+}
+
+fn strings<'a>(map: &'a Map<String, Value>, field: &str) -> AnyResult<Vec<&'a str>> {
+    let mut vec = vec![];
+    for v in listable(map, field) {
+        match v.as_str() {
+            None => return invalid(format!("{} 的值必须是字符串: {}", field, v)),
+            Some(s) => vec.push(s),
+        }
+    }
+    Ok(vec)
+}
+
+/// 域名与后缀编码为反转后的前缀树(succinct set)
+fn write_domains(w: &mut Vec<u8>, domains: &[&str], suffixes: &[&str], legacy: bool) {
+    let mut seen = BTreeSet::new();
+    let mut keys: Vec<Vec<u8>> = Vec::with_capacity(domains.len() + suffixes.len() * 2);
+    for suffix in suffixes {
+        if suffix.is_empty() || !seen.insert(suffix.to_string()) {
+            continue;
+        }
+        if suffix.starts_with('.') {
+            keys.push(reverse(&format!("{}{}", label_prefix, suffix)));
+        } else if legacy {
+            keys.push(reverse(suffix));
+            let dot = format!(".{}", suffix);
+            if seen.insert(dot.clone()) {
+                keys.push(reverse(&format!("{}{}", label_prefix, dot)));
+            }
+        } else {
+            keys.push(reverse(&format!("{}{}", label_root, suffix)));
+        }
+    }
+    for domain in domains {
+        if domain.is_empty() || !seen.insert(domain.to_string()) {
+            continue;
+        }
+        keys.push(reverse(domain));
+    }
+    keys.sort();
+    keys.dedup();
+
+    let (leaves, bitmap, labels) = succinct_build(&keys);
+    // matcher 版本
+    w.push(1);
+    write_u64s(w, &leaves);
+    write_u64s(w, &bitmap);
+    write_bytes(w, &labels);
+}
+
+fn reverse(domain: &str) -> Vec<u8> {
+    domain.chars().rev().collect::<String>().into_bytes()
+}
+
+fn set_bit(bm: &mut Vec<u64>, i: usize, v: bool) {
+    while i >> 6 >= bm.len() {
+        bm.push(0);
+    }
+    bm[i >> 6] |= (v as u64) << (i & 63);
+}
+
+fn get_bit(bm: &[u64], i: usize) -> bool {
+    bm.get(i >> 6).is_some_and(|b| b & (1 << (i & 63)) != 0)
+}
+
+/// 按层序构建前缀树, keys 必须有序且不重复
+fn succinct_build(keys: &[Vec<u8>]) -> (Vec<u64>, Vec<u64>, Vec<u8>) {
+    let mut leaves = Vec::new();
+    let mut bitmap = Vec::new();
+    let mut labels = Vec::new();
+    let mut l_idx = 0;
+
+    // (开始, 结束, 列)
+    let mut queue = vec![(0, keys.len(), 0)];
+    let mut i = 0;
+    while i < queue.len() {
+        let (mut s, e, col) = queue[i];
+        if s < e && col == keys[s].len() {
+            s += 1;
+            set_bit(&mut leaves, i, true);
+        }
+
+        let mut j = s;
+        while j < e {
+            let from = j;
+            while j < e && keys[j][col] == keys[from][col] {
+                j += 1;
+            }
+            queue.push((from, j, col + 1));
+            labels.push(keys[from][col]);
+            set_bit(&mut bitmap, l_idx, false);
+            l_idx += 1;
+        }
+        set_bit(&mut bitmap, l_idx, true);
+        l_idx += 1;
+        i += 1;
+    }
+
+    (leaves, bitmap, labels)
+}
+
+/// 前缀树还原为全部的key
+fn succinct_keys(leaves: &[u64], bitmap: &[u64], labels: &[u8]) -> AnyResult<Vec<Vec<u8>>> {
+    let nodes = labels.len() + 1;
+    let mut prefixes: Vec<Vec<u8>> = vec![Vec::new(); nodes];
+    let mut node = 0;
+    let mut edge = 0;
+    let mut bit = 0;
+    while node < nodes {
+        if bit >= bitmap.len() * 64 {
+            return invalid("域名树不完整");
+        }
+        if get_bit(bitmap, bit) {
+            node += 1;
+        } else {
+            if edge >= labels.len() {
+                return invalid("域名树标签越界");
+            }
+            let mut prefix = prefixes[node].clone();
+            prefix.push(labels[edge]);
+            prefixes[edge + 1] = prefix;
+            edge += 1;
+        }
+        bit += 1;
+    }
+
+    Ok(prefixes
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| get_bit(leaves, *i))
+        .map(|(_, k)| k)
+        .collect())
+}
+
+fn read_domains(r: &mut Reader) -> AnyResult<(Vec<String>, Vec<String>)> {
+    let version = r.byte()?;
+    if version != 1 {
+        return invalid(format!("未知的域名匹配版本: {}", version));
+    }
+    let leaves = r.u64s()?;
+    let bitmap = r.u64s()?;
+    let labels = r.bytes()?;
+
+    let mut domains = BTreeSet::new();
+    let mut prefixes = BTreeSet::new();
+    let mut suffixes = Vec::new();
+    for key in succinct_keys(&leaves, &bitmap, labels)? {
+        let key: String = String::from_utf8_lossy(&key).chars().rev().collect();
+        if let Some(k) = key.strip_prefix(label_prefix) {
+            prefixes.insert(k.to_string());
+        } else if let Some(k) = key.strip_prefix(label_root) {
+            suffixes.push(k.to_string());
+        } else {
+            domains.insert(key);
+        }
+    }
+
+    // 旧版本的后缀写为 域名 + .域名, 还原为一个后缀
+    for prefix in prefixes {
+        if let Some(root) = prefix.strip_prefix('.')
+            && domains.remove(root)
+        {
+            suffixes.push(root.to_string());
+            continue;
+        }
+        suffixes.push(prefix);
+    }
+    suffixes.sort();
+
+    Ok((domains.into_iter().collect(), suffixes))
+}
+
+/// ip 地址统一转为 u128 比较, ipv4 排在 ipv6 之前
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct IpKey {
+    v6: bool,
+    value: u128,
+}
+
+impl IpKey {
+    fn bits(&self) -> u32 {
+        if self.v6 { 128 } else { 32 }
+    }
+
+    fn all_ones(&self) -> u128 {
+        if self.v6 { u128::MAX } else { u32::MAX as u128 }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        if self.v6 {
+            self.value.to_be_bytes().to_vec()
+        } else {
+            (self.value as u32).to_be_bytes().to_vec()
+        }
+    }
+
+    fn addr(&self) -> IpAddr {
+        if self.v6 {
+            IpAddr::V6(Ipv6Addr::from(self.value))
+        } else {
+            IpAddr::V4(Ipv4Addr::from(self.value as u32))
+        }
+    }
+}
+
+impl From<IpAddr> for IpKey {
+    fn from(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(v) => IpKey {
+                v6: false,
+                value: u32::from(v) as u128,
+            },
+            IpAddr::V6(v) => IpKey {
+                v6: true,
+                value: u128::from(v),
+            },
+        }
+    }
+}
+
+/// cidr 对应的地址范围, 用于判断ip是否命中
+#[derive(Clone, Copy)]
+pub(crate) struct CidrRange {
+    from: IpKey,
+    to: IpKey,
+}
+
+impl CidrRange {
+    pub(crate) fn parse(value: &str) -> AnyResult<Self> {
+        let (from, to) = ip_range(value)?;
+        Ok(Self { from, to })
+    }
+
+    pub(crate) fn contains(&self, addr: IpAddr) -> bool {
+        let key = IpKey::from(addr);
+        key.v6 == self.from.v6 && self.from.value <= key.value && key.value <= self.to.value
+    }
+}
+
+/// 解析 cidr 或单个ip为地址范围
+fn ip_range(value: &str) -> AnyResult<(IpKey, IpKey)> {
+    let (ip, prefix) = match value.split_once('/') {
+        None => (value, None),
+        Some((i, p)) => (i, Some(p)),
+    };
+    let key = match ip.trim().parse::<IpAddr>() {
+        Ok(addr) => IpKey::from(addr),
+        Err(_) => return invalid(format!("无效的ip: {}", value)),
+    };
+    let (v6, value_ip) = (key.v6, key.value);
+    let bits = key.bits();
+    let prefix = match prefix {
+        None => bits,
+        Some(p) => match p.trim().parse::<u32>() {
+            Ok(p) if p <= bits => p,
+            _ => return invalid(format!("无效的前缀长度: {}", value)),
+        },
+    };
+    let host = if prefix == bits {
+        0
+    } else {
+        key.all_ones() >> prefix
+    };
+    let from = IpKey {
+        v6,
+        value: value_ip & !host,
+    };
+    let to = IpKey {
+        v6,
+        value: from.value | host,
+    };
+    Ok((from, to))
+}
+
+/// 地址范围排序并合并重叠与相邻的部分
+fn merge_ranges(mut ranges: Vec<(IpKey, IpKey)>) -> Vec<(IpKey, IpKey)> {
+    ranges.sort();
+    let mut merged: Vec<(IpKey, IpKey)> = Vec::with_capacity(ranges.len());
+    for (from, to) in ranges {
+        if let Some(last) = merged.last_mut()
+            && last.1.v6 == from.v6
+            && (last.1.value >= from.value || last.1.value.checked_add(1) == Some(from.value))
+        {
+            if to > last.1 {
+                last.1 = to;
+            }
+            continue;
+        }
+        merged.push((from, to));
+    }
+    merged
+}
+
+fn write_ip_set(w: &mut Vec<u8>, values: &[&str]) -> AnyResult<()> {
+    let mut ranges = Vec::with_capacity(values.len());
+    for v in values {
+        ranges.push(ip_range(v)?);
+    }
+    let ranges = merge_ranges(ranges);
+
+    // ip set 版本
+    w.push(1);
+    w.extend_from_slice(&(ranges.len() as u64).to_be_bytes());
+    for (from, to) in ranges {
+        write_bytes(w, &from.bytes());
+        write_bytes(w, &to.bytes());
+    }
+    Ok(())
+}
+
+fn read_ip_key(r: &mut Reader) -> AnyResult<IpKey> {
+    let bytes = r.bytes()?;
+    match bytes.len() {
+        4 => Ok(IpKey {
+            v6: false,
+            value: u32::from_be_bytes(bytes.try_into().unwrap()) as u128,
+        }),
+        16 => Ok(IpKey {
+            v6: true,
+            value: u128::from_be_bytes(bytes.try_into().unwrap()),
+        }),
+        n => invalid(format!("无效的ip长度: {}", n)),
+    }
+}
+
+/// 合并重叠与相邻的 cidr, 输出最少的 cidr
+pub fn aggregate_cidrs(values: &[&str]) -> AnyResult<Vec<String>> {
+    let mut ranges = Vec::with_capacity(values.len());
+    for v in values {
+        ranges.push(ip_range(v)?);
+    }
+    let mut vec = Vec::new();
+    for (from, to) in merge_ranges(ranges) {
+        range_cidrs(from, to, &mut vec);
+    }
+    Ok(vec)
+}
+
+/// 读取 ip set 并拆分为最少的 cidr
+fn read_ip_set(r: &mut Reader) -> AnyResult<Vec<String>> {
+    let version = r.byte()?;
+    if version != 1 {
+        return invalid(format!("未知的ip集合版本: {}", version));
+    }
+    let len = u64::from_be_bytes(r.take(8)?.try_into().unwrap());
+    let mut vec = Vec::new();
+    for _ in 0..len {
+        let from = read_ip_key(r)?;
+        let to = read_ip_key(r)?;
+        if from.v6 != to.v6 || from.value > to.value {
+            return invalid("无效的ip范围");
+        }
+        range_cidrs(from, to, &mut vec);
+    }
+    Ok(vec)
+}
+
+fn range_cidrs(from: IpKey, to: IpKey, vec: &mut Vec<String>) {
+    let bits = from.bits();
+    let mut start = from.value;
+    loop {
+        // 起始地址对齐的最大块, 且不能超过结束地址
+        let mut size = start.trailing_zeros().min(bits);
+        while size > 0 {
+            let host = if size == 128 {
+                u128::MAX
+            } else {
+                (1u128 << size) - 1
+            };
+            if start + host <= to.value {
+                break;
+            }
+            size -= 1;
+        }
+        let addr = IpKey {
+            v6: from.v6,
+            value: start,
+        };
+        vec.push(format!("{}/{}", addr.addr(), bits - size));
+
+        let host = if size == 128 {
+            u128::MAX
+        } else {
+            (1u128 << size) - 1
+        };
+        let end = start + host;
+        if end >= to.value {
+            break;
+        }
+        start = end + 1;
+    }
+}
+
+/// 读取 srs 为 sing-box 规则集 json
+pub fn read(bytes: &[u8]) -> AnyResult<Value> {
+    if bytes.len() < 4 || bytes[..3] != magic {
+        return invalid("文件头不匹配");
+    }
+    let version = bytes[3];
+    if version > version_max {
+        return invalid(format!("不支持的版本: {}", version));
+    }
+
+    let mut body = Vec::new();
+    ZlibDecoder::new(&bytes[4..]).read_to_end(&mut body)?;
+    let mut r = Reader {
+        bytes: &body,
+        pos: 0,
+    };
+    let len = r.uvarint()?;
+    let mut rules = Vec::new();
+    for _ in 0..len {
+        rules.push(read_rule(&mut r)?);
+    }
+
+    let mut json = Map::new();
+    json.insert("version".into(), Value::from(version));
+    json.insert("rules".into(), Value::Array(rules));
+    Ok(Value::Object(json))
+}
+
+/// 统计 srs 中的规则数量, 与生成时的计数方式一致
+pub fn count(bytes: &[u8]) -> AnyResult<u64> {
+    let json = read(bytes)?;
+    let rules = json
+        .get("rules")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let mut count = 0;
+    for rule in rules {
+        if rule.get("type").and_then(Value::as_str) == Some("logical") {
+            count += 1;
+            continue;
+        }
+        if let Some(map) = rule.as_object() {
+            count += map
+                .values()
+                .map(|v| v.as_array().map_or(0, |a| a.len() as u64))
+                .sum::<u64>();
+        }
+    }
+    Ok(count)
+}
+
+fn read_rule(r: &mut Reader) -> AnyResult<Value> {
+    match r.byte()? {
+        rule_default => read_default(r),
+        rule_logical => read_logical(r),
+        t => invalid(format!("未知的规则类型: {}", t)),
+    }
+}
+
+fn read_default(r: &mut Reader) -> AnyResult<Value> {
+    let mut map: BTreeMap<&'static str, Value> = BTreeMap::new();
+    loop {
+        let item = r.byte()?;
+        if item == item_final {
+            if r.byte()? != 0 {
+                map.insert("invert", Value::Bool(true));
+            }
+            break;
+        }
+
+        if item == item_domain {
+            let (domains, suffixes) = read_domains(r)?;
+            if !domains.is_empty() {
+                map.insert(field_domain, Value::from(domains));
+            }
+            if !suffixes.is_empty() {
+                map.insert(field_domain_suffix, Value::from(suffixes));
+            }
+        } else if let Some((field, _)) = string_items.iter().find(|(_, i)| *i == item) {
+            let len = r.uvarint()?;
+            let mut vec = Vec::new();
+            for _ in 0..len {
+                vec.push(Value::from(String::from_utf8(r.bytes()?.to_vec())?));
+            }
+            map.insert(field, Value::Array(vec));
+        } else if let Some((field, _)) = uint16_items.iter().find(|(_, i)| *i == item) {
+            let len = r.uvarint()?;
+            let mut vec = Vec::new();
+            for _ in 0..len {
+                let v = u16::from_be_bytes(r.take(2)?.try_into().unwrap());
+                vec.push(Value::from(v));
+            }
+            map.insert(field, Value::Array(vec));
+        } else if let Some((field, _)) = cidr_items.iter().find(|(_, i)| *i == item) {
+            map.insert(field, Value::from(read_ip_set(r)?));
+        } else if let Some((field, _)) = flag_items.iter().find(|(_, i)| *i == item) {
+            map.insert(field, Value::Bool(true));
+        } else if item == item_adguard_domain {
+            return invalid("不支持 adguard 域名规则");
+        } else {
+            return invalid(format!("未知的规则项: {}", item));
+        }
+    }
+
+    let mut m = Map::new();
+    map.into_iter().for_each(|(k, v)| {
+        m.insert(k.to_string(), v);
+    });
+    Ok(Value::Object(m))
+}
+
+fn read_logical(r: &mut Reader) -> AnyResult<Value> {
+    let mode = match r.byte()? {
+        0 => "and",
+        1 => "or",
+        m => return invalid(format!("未知的逻辑模式: {}", m)),
+    };
+    let len = r.uvarint()?;
+    let mut rules = Vec::new();
+    for _ in 0..len {
+        rules.push(read_rule(r)?);
+    }
+    let invert = r.byte()? != 0;
+
+    let mut m = Map::new();
+    m.insert("type".into(), Value::from("logical"));
+    m.insert("mode".into(), Value::from(mode));
+    m.insert("rules".into(), Value::Array(rules));
+    if invert {
+        m.insert("invert".into(), Value::Bool(true));
+    }
+    Ok(Value::Object(m))
+}
+
+fn write_uvarint(w: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        w.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    w.push(v as u8);
+}
+
+fn write_bytes(w: &mut Vec<u8>, bytes: &[u8]) {
+    write_uvarint(w, bytes.len() as u64);
+    w.extend_from_slice(bytes);
+}
+
+fn write_u64s(w: &mut Vec<u8>, values: &[u64]) {
+    write_uvarint(w, values.len() as u64);
+    for v in values {
+        w.extend_from_slice(&v.to_be_bytes());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> AnyResult<&'a [u8]> {
+        if self.bytes.len() - self.pos < n {
+            return invalid("数据不完整");
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> AnyResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn uvarint(&mut self) -> AnyResult<u64> {
+        let mut v: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            v |= ((b & 0x7F) as u64) << shift;
+            if b < 0x80 {
+                return Ok(v);
+            }
+        }
+        invalid("varint 溢出")
+    }
+
+    fn bytes(&mut self) -> AnyResult<&'a [u8]> {
+        let len = self.uvarint()? as usize;
+        self.take(len)
+    }
+
+    fn u64s(&mut self) -> AnyResult<Vec<u64>> {
+        let len = self.uvarint()? as usize;
+        let mut vec = Vec::with_capacity(len.min(self.bytes.len() / 8));
+        for _ in 0..len {
+            vec.push(u64::from_be_bytes(self.take(8)?.try_into().unwrap()));
+        }
+        Ok(vec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn round_trip(rules: Value, version: u8) -> Value {
+        let source = json!({ "version": version, "rules": rules });
+        let bytes = from_json(&source.to_string()).unwrap();
+        assert_eq!(&bytes[..3], &magic);
+        assert_eq!(bytes[3], version);
+        read(&bytes).unwrap()
+    }
+
+    fn rules() -> Value {
+        json!([
+            {
+                "domain": ["a.example.com", "www.google.com"],
+                "domain_suffix": ["example.com", "google.com"],
+                "domain_keyword": ["ads"],
+                "domain_regex": ["^ad\\d+\\."]
+            },
+            {
+                "ip_cidr": ["10.0.0.0/8", "2001:db8::/32"],
+                "port": [80, 443],
+                "port_range": ["1000:2000"],
+                "network": ["tcp"]
+            },
+            {
+                "process_name": ["curl"],
+                "invert": true
+            },
+            {
+                "type": "logical",
+                "mode": "and",
+                "rules": [
+                    { "domain_suffix": ["example.org"] },
+                    { "source_ip_cidr": ["192.168.0.0/16"] }
+                ],
+                "invert": true
+            }
+        ])
+    }
+
+    #[test]
+    fn round_trip_v2_v3() {
+        for version in [2, 3] {
+            let json = round_trip(rules(), version);
+            assert_eq!(json, json!({ "version": version, "rules": rules() }));
+        }
+    }
+
+    #[test]
+    fn round_trip_v1() {
+        // 旧版本的后缀写为 域名 + .域名, 读取时还原
+        let json = round_trip(rules(), 1);
+        assert_eq!(json, json!({ "version": 1, "rules": rules() }));
+
+        let json = round_trip(json!([{ "domain_suffix": [".only-sub.com"] }]), 1);
+        assert_eq!(
+            json["rules"],
+            json!([{ "domain_suffix": [".only-sub.com"] }])
+        );
+    }
+
+    #[test]
+    fn version_override() {
+        let source = json!({ "version": 1, "rules": [{ "domain": ["a.com"] }] });
+        let bytes = from_json_version(&source.to_string(), Some(3)).unwrap();
+        assert_eq!(bytes[3], 3);
+        assert!(from_json_version(&source.to_string(), Some(4)).is_err());
+        assert!(from_json_version(&source.to_string(), Some(0)).is_err());
+    }
+
+    #[test]
+    fn count_rules() {
+        let source = json!({ "version": 2, "rules": rules() });
+        let bytes = from_json(&source.to_string()).unwrap();
+        // 6 个域名类 + 6 个ip/端口/网络 + 1 个进程 + 1 个逻辑规则
+        assert_eq!(count(&bytes).unwrap(), 14);
+    }
+
+    #[test]
+    fn reject_invalid() {
+        assert!(read(b"SRX\x01").is_err());
+        assert!(read(b"SRS\x09").is_err());
+        assert!(from_json(r#"{"version":2,"rules":[{"unknown":["a"]}]}"#).is_err());
+        assert!(from_json(r#"{"version":2,"rules":[{"port":[70000]}]}"#).is_err());
+        assert!(from_json(r#"{"version":2,"rules":[{"ip_cidr":["10.0.0.0/33"]}]}"#).is_err());
+    }
+
+    fn aggregate(values: &[&str]) -> Vec<String> {
+        aggregate_cidrs(values).unwrap()
+    }
+
+    #[test]
+    fn cidr_merge_adjacent_and_overlap() {
+        assert_eq!(
+            aggregate(&["10.0.0.0/25", "10.0.0.128/25"]),
+            ["10.0.0.0/24"]
+        );
+        assert_eq!(aggregate(&["10.1.0.0/16", "10.0.0.0/8"]), ["10.0.0.0/8"]);
+        assert_eq!(aggregate(&["10.0.0.1", "10.0.0.1/32"]), ["10.0.0.1/32"]);
+        // 不对齐的范围拆分为最少的 cidr
+        assert_eq!(
+            aggregate(&["10.0.0.1", "10.0.0.2/31", "10.0.0.4/31", "10.0.0.6"]),
+            ["10.0.0.1/32", "10.0.0.2/31", "10.0.0.4/31", "10.0.0.6/32"]
+        );
+        // 主机位不为0时按网络地址处理
+        assert_eq!(aggregate(&["192.168.1.77/24"]), ["192.168.1.0/24"]);
+    }
+
+    #[test]
+    fn cidr_merge_bounds() {
+        assert_eq!(aggregate(&["0.0.0.0/1", "128.0.0.0/1"]), ["0.0.0.0/0"]);
+        assert_eq!(aggregate(&["255.255.255.255/32"]), ["255.255.255.255/32"]);
+        assert_eq!(aggregate(&["::/1", "8000::/1"]), ["::/0"]);
+        assert_eq!(
+            aggregate(&["ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"]),
+            ["ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff/128"]
+        );
+        // ipv4 与 ipv6 数值相邻也不合并
+        assert_eq!(
+            aggregate(&["::/128", "255.255.255.255/32", "0.0.0.0/32"]),
+            ["0.0.0.0/32", "255.255.255.255/32", "::/128"]
+        );
+        assert!(aggregate_cidrs(&["abc"]).is_err());
+    }
+}
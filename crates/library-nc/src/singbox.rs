@@ -1,28 +1,88 @@
-use crate::core::fast;
+use crate::core::{fast, NcError};
+use crate::dns::{DnsAddress, DnsProtocol};
+use crate::inbound::{local_listen, InboundUser};
 use crate::kernel::{
-    clash_ui_url, default_mixed_listen, default_mixed_port, default_ui, fake_ipv4, fake_ipv6,
-    inner_ipv4, inner_ipv6, key_direct, key_proxy, key_reject, loopback_ipv4, loopback_ipv6,
-    out_direct, route_ipv4, route_ipv6, tag_auto, tag_fallback, tag_selector, test_url,
-    virtual_ipv4, virtual_ipv6, KernelConfig,
+    clash_ui_url, default_mixed_listen, default_mixed_port, default_ui, dns_default_cn,
+    dns_default_proxy, fake_ipv4, fake_ipv6, inner_ipv4, inner_ipv6, key_direct, key_proxy,
+    key_reject, loopback_ipv4, loopback_ipv6, out_direct, route_ipv4, route_ipv6, tag_auto,
+    tag_fallback, tag_selector, test_url, virtual_ipv4, virtual_ipv6, KernelConfig,
 };
-use crate::rule::{Rule, RuleType, SingBoxRule};
+use crate::rule::{Rule, RuleType, SinBoxJsonRule, SingBoxRule};
+use crate::rule_classical;
 use crate::subscribe::SubscribeNode;
 use indexmap::IndexMap;
 use library_core::core::AnyResult;
 use serde::Serialize;
-use serde_json::Value;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
 
 pub const tag_dns_cn: &str = "dns-cn";
 pub const tag_dns_fake: &str = "dns-fake";
 pub const tag_dns_proxy: &str = "dns-proxy";
+/// 系统 dns, 用于解析域名形式的 dns 服务器
+pub const tag_dns_local: &str = "dns-local";
+pub const tag_dns_hosts: &str = "dns-hosts";
+pub const tag_tun_in: &str = "tun-in";
+
+const type_wireguard: &str = "wireguard";
 
 pub const geo_ip_cn: &str =
     "https://raw.githubusercontent.com/SagerNet/sing-geoip/rule-set/geoip-cn.srs";
+pub const geo_site_rule_set: &str =
+    "https://raw.githubusercontent.com/SagerNet/sing-geosite/rule-set";
+pub const geo_ip_rule_set: &str = "https://raw.githubusercontent.com/SagerNet/sing-geoip/rule-set";
+
+/// 生成配置的目标内核版本. 1.12 起 dns 服务器改为按类型描述, wireguard 改为端点
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SingBoxVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl SingBoxVersion {
+    pub const v1_11: SingBoxVersion = SingBoxVersion {
+        major: 1,
+        minor: 11,
+    };
+    pub const v1_12: SingBoxVersion = SingBoxVersion {
+        major: 1,
+        minor: 12,
+    };
+
+    /// 解析 v1.11.9 / 1.12.12 形式的版本号
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim().trim_start_matches('v');
+        let mut parts = version.split(['.', '-']);
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        Some(Self { major, minor })
+    }
+
+    /// 1.12 之前的 dns 服务器使用 address 描述, 不支持 hosts 与 ip_accept_any
+    pub fn is_legacy(&self) -> bool {
+        *self < Self::v1_12
+    }
+
+    /// 编译 srs 规则集使用的版本
+    pub fn rule_set_version(&self) -> u8 {
+        if self.is_legacy() { 2 } else { 3 }
+    }
+}
+
+/// 默认生成最新支持的版本
+impl Default for SingBoxVersion {
+    fn default() -> Self {
+        Self::v1_12
+    }
+}
 
 #[derive(Serialize)]
 struct LogConfig {
     level: String,
     timestamp: bool,
+    /// 输出由程序解析, 不需要颜色
+    disable_color: bool,
 }
 
 #[derive(Serialize)]
@@ -60,14 +120,33 @@ enum Inbound {
         endpoint_independent_nat: bool,
         udp_timeout: String,
         stack: String,
-        sniff_override_destination: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mtu: Option<u32>,
+        /// 1.11 起已弃用
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sniff_override_destination: Option<bool>,
         #[serde(skip_serializing_if = "Option::is_none")]
         address: Option<Vec<String>>,
         #[serde(skip_serializing_if = "Option::is_none")]
         route_address: Option<Vec<String>>,
-        loopback_address: Vec<String>,
+        /// 1.12 起支持
+        #[serde(skip_serializing_if = "Option::is_none")]
+        loopback_address: Option<Vec<String>>,
         #[serde(skip_serializing_if = "Option::is_none")]
         route_exclude_address: Option<Vec<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        include_uid: Option<Vec<u32>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        exclude_uid: Option<Vec<u32>>,
+    },
+
+    /// dns 监听
+    Direct {
+        #[serde(rename = "type")]
+        kind: String,
+        tag: String,
+        listen: String,
+        listen_port: u16,
     },
 
     Mixed {
@@ -81,6 +160,17 @@ enum Inbound {
         tcp_multi_path: bool,
         udp_fragment: bool,
     },
+
+    /// 额外的 mixed / socks / http 入站
+    Proxy {
+        #[serde(rename = "type")]
+        kind: String,
+        tag: String,
+        listen: String,
+        listen_port: u16,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        users: Vec<InboundUser>,
+    },
 }
 
 #[derive(Serialize)]
@@ -240,9 +330,45 @@ impl Outbound {
         }
     }
 
-    pub fn direct(tag: &str) -> Self {
+    /// 1.12 之前的 wireguard 出站
+    pub fn wireguard(node: &SubscribeNode) -> AnyResult<Self> {
         let mut attributes = IndexMap::new();
-        attributes.insert("domain_resolver".into(), Value::from(tag_dns_cn));
+        let address = wireguard_address(node)?;
+        attributes.insert("local_address".into(), Value::from(address));
+        for (key, clash) in [
+            ("private_key", "private-key"),
+            ("peer_public_key", "public-key"),
+            ("pre_shared_key", "pre-shared-key"),
+            ("reserved", "reserved"),
+            ("mtu", "mtu"),
+        ] {
+            if let Some(v) = node.attribute.get(clash) {
+                attributes.insert(key.into(), v.clone());
+            }
+        }
+        Ok(Self {
+            tag: node.name.clone(),
+            type_: type_wireguard.into(),
+            interrupt_exist_connections: None,
+            default: None,
+            url: None,
+            interval: None,
+            tolerance: None,
+            outbounds: None,
+            port: node.port,
+            server: Some(node.server.clone()),
+            password: None,
+            tls: None,
+            attributes,
+        })
+    }
+
+    /// domain_resolver 为 1.12 起的字段
+    pub fn direct(tag: &str, domain_resolver: bool) -> Self {
+        let mut attributes = IndexMap::new();
+        if domain_resolver {
+            attributes.insert("domain_resolver".into(), Value::from(tag_dns_cn));
+        }
         Self {
             tag: tag.into(),
             type_: key_direct.into(),
@@ -261,6 +387,75 @@ impl Outbound {
     }
 }
 
+/// 1.12 起的端点, 目前仅用于 wireguard
+#[derive(Serialize)]
+struct Endpoint {
+    #[serde(rename = "type")]
+    type_: String,
+    tag: String,
+    address: Vec<String>,
+    private_key: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtu: Option<Value>,
+    peers: Vec<EndpointPeer>,
+}
+
+#[derive(Serialize)]
+struct EndpointPeer {
+    address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
+    public_key: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pre_shared_key: Option<Value>,
+    allowed_ips: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reserved: Option<Value>,
+}
+
+impl Endpoint {
+    pub fn wireguard(node: &SubscribeNode) -> AnyResult<Self> {
+        let map = &node.attribute;
+        let peer = EndpointPeer {
+            address: node.server.clone(),
+            port: node.port,
+            public_key: map.get("public-key").cloned().unwrap_or_default(),
+            pre_shared_key: map.get("pre-shared-key").cloned(),
+            allowed_ips: map
+                .get("allowed-ips")
+                .cloned()
+                .unwrap_or_else(|| Value::from(vec!["0.0.0.0/0", "::/0"])),
+            reserved: map.get("reserved").cloned(),
+        };
+        Ok(Self {
+            type_: type_wireguard.into(),
+            tag: node.name.clone(),
+            address: wireguard_address(node)?,
+            private_key: map.get("private-key").cloned().unwrap_or_default(),
+            mtu: map.get("mtu").cloned(),
+            peers: vec![peer],
+        })
+    }
+}
+
+/// clash 中 wireguard 节点的本机地址, 没有前缀时补全为单个地址. 没有地址时内核无法启动
+fn wireguard_address(node: &SubscribeNode) -> AnyResult<Vec<String>> {
+    let address: Vec<String> = ["ip", "ipv6"]
+        .iter()
+        .filter_map(|k| node.attribute.get(*k)?.as_str())
+        .map(|ip| match ip.trim() {
+            ip if ip.contains('/') => ip.to_string(),
+            ip if ip.contains(':') => format!("{}/128", ip),
+            ip => format!("{}/32", ip),
+        })
+        .collect();
+    if address.is_empty() {
+        let msg = format!("wireguard 节点 {} 缺少本机地址", node.name);
+        return Err(NcError::InvalidNode(msg).into());
+    }
+    Ok(address)
+}
+
 #[derive(Serialize)]
 struct OutboundTls {
     enabled: bool,
@@ -271,158 +466,392 @@ struct OutboundTls {
 }
 
 #[derive(Serialize)]
-struct RouteConfig {
+pub(crate) struct RouteConfig {
     #[serde(rename = "final")]
-    final_: String,
+    pub(crate) final_: String,
     auto_detect_interface: bool,
-    rule_set: Vec<SingBoxRule>,
-    rules: Vec<RouteRule>,
+    /// 1.12 起出站解析服务器域名使用的 dns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default_domain_resolver: Option<String>,
+    pub(crate) rule_set: Vec<SingBoxRule>,
+    pub(crate) rules: Vec<RouteRule>,
 }
 
-#[derive(Serialize)]
-struct RouteRule {
+#[derive(Serialize, Default)]
+pub(crate) struct RouteRule {
+    /// 为 logical 时按 mode 组合 rules
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub(crate) type_: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) rules: Option<Vec<RouteRule>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    rule_set: Option<String>,
+    pub(crate) inbound: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    protocol: Option<String>,
-    action: String,
+    pub(crate) rule_set: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) protocol: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) process_name: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) user: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) invert: Option<bool>,
+    /// 逻辑规则的子规则没有动作
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub(crate) action: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     timeout: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    outbound: Option<String>,
+    pub(crate) outbound: Option<String>,
 }
 
 impl RouteRule {
     pub fn sniff() -> Self {
         Self {
-            rule_set: None,
-            protocol: None,
             action: "sniff".into(),
             timeout: Some("1s".into()),
-            outbound: None,
+            ..Default::default()
         }
     }
     pub fn dns() -> Self {
         Self {
-            rule_set: None,
             protocol: Some("dns".into()),
             action: "hijack-dns".into(),
-            timeout: None,
-            outbound: None,
+            ..Default::default()
         }
     }
     pub fn reject(rule_set: String) -> Self {
         Self {
             rule_set: Some(rule_set),
-            protocol: None,
             action: "reject".into(),
-            timeout: None,
-            outbound: None,
+            ..Default::default()
         }
     }
     pub fn out(rule_set: String, out: String) -> Self {
         Self {
             rule_set: Some(rule_set),
-            protocol: None,
             action: "route".into(),
-            timeout: None,
             outbound: Some(out),
+            ..Default::default()
+        }
+    }
+    /// 按进程名或用户名直连, invert 时不在列表中的直连. 仅作用于 tun 入站
+    pub fn bypass(
+        process_name: Option<Vec<String>>,
+        user: Option<Vec<String>>,
+        invert: bool,
+    ) -> Self {
+        let inbound = Some(vec![tag_tun_in.into()]);
+        if !invert {
+            return Self {
+                inbound,
+                process_name,
+                user,
+                action: "route".into(),
+                outbound: Some(out_direct.into()),
+                ..Default::default()
+            };
+        }
+        // invert 作用于整条规则, 入站条件需拆到逻辑规则中, 否则其他入站的连接也会直连
+        Self {
+            type_: Some("logical".into()),
+            mode: Some("and".into()),
+            rules: Some(vec![
+                Self {
+                    inbound,
+                    ..Default::default()
+                },
+                Self {
+                    process_name,
+                    user,
+                    invert: Some(true),
+                    ..Default::default()
+                },
+            ]),
+            action: "route".into(),
+            outbound: Some(out_direct.into()),
+            ..Default::default()
         }
     }
 }
 
 #[derive(Serialize)]
-struct DnsConfig {
+pub(crate) struct DnsConfig {
     #[serde(rename = "final")]
-    final_: String,
+    pub(crate) final_: String,
     disable_cache: bool,
     disable_expire: bool,
     independent_cache: bool,
     strategy: String,
-    servers: Vec<DnsServer>,
-    rules: Vec<DnsRule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_subnet: Option<String>,
+    pub(crate) servers: Vec<DnsServer>,
+    pub(crate) rules: Vec<DnsRule>,
+    /// 1.12 之前的 fake-ip 地址段
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fakeip: Option<LegacyFakeIp>,
 }
 
 #[derive(Serialize)]
-struct DnsServer {
-    tag: String,
+struct LegacyFakeIp {
+    enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inet4_range: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inet6_range: Option<String>,
+}
+
+impl DnsConfig {
+    /// 转为 1.12 之前的格式. hosts 与 A/AAAA 的依次尝试不支持, 相关的服务器与规则被移除
+    fn legacy(mut self) -> Self {
+        if let Some(fake) = self.servers.iter().find(|s| s.tag == tag_dns_fake) {
+            self.fakeip = Some(LegacyFakeIp {
+                enabled: true,
+                inet4_range: fake.inet4_range.clone(),
+                inet6_range: fake.inet6_range.clone(),
+            });
+        }
+        self.servers = self
+            .servers
+            .into_iter()
+            .filter_map(DnsServer::legacy)
+            .collect();
+        self.rules
+            .retain(|r| r.query_type.is_none() && r.server.as_deref() != Some(tag_dns_hosts));
+        self
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct DnsServer {
+    pub(crate) tag: String,
     #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
-    type_: Option<String>,
+    pub(crate) type_: Option<String>,
+    /// 1.12 之前的地址格式
+    #[serde(rename = "address", skip_serializing_if = "Option::is_none")]
+    legacy_address: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    server: Option<String>,
-    /// 临时留着用于兼容未适配的协议
-    #[deprecated]
+    pub(crate) server: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    address: Option<String>,
+    server_port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    /// dhcp 使用的网卡
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interface: Option<String>,
+    /// 服务器地址为域名时, 用于解析的服务器
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domain_resolver: Option<String>,
+    /// 1.12 之前用于解析服务器域名的服务器
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address_resolver: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     detour: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     inet4_range: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     inet6_range: Option<String>,
+    /// hosts 的静态记录
+    #[serde(skip_serializing_if = "Option::is_none")]
+    predefined: Option<IndexMap<String, Vec<String>>>,
+    /// 配置中的原始地址
+    #[serde(skip)]
+    pub(crate) address: String,
 }
 
 impl DnsServer {
-    pub fn from(tag: String, address: String, detour: String) -> Self {
-        if address.starts_with("https") {
-            let scheme = address.strip_prefix("https://").expect("error address");
-            // 暂时不兼容指定path
-            return if let Some(slash_pos) = scheme.find('/') {
-                let server = &scheme[..slash_pos];
-                Self::https(tag, server.into(), detour)
+    pub fn from(tag: String, address: &str, detour: String) -> AnyResult<Self> {
+        let dns = DnsAddress::parse(address)?;
+        let dhcp = dns.protocol == DnsProtocol::Dhcp;
+        Ok(Self {
+            tag,
+            type_: Some(dns.protocol.name().into()),
+            legacy_address: None,
+            server: if dhcp { None } else { Some(dns.server.clone()) },
+            server_port: dns.port,
+            path: dns.path.clone(),
+            interface: if dhcp && !dns.server.is_empty() {
+                Some(dns.server.clone())
             } else {
-                Self::https(tag, scheme.into(), detour)
-            };
-        }
+                None
+            },
+            domain_resolver: if dns.need_resolve() {
+                Some(tag_dns_local.into())
+            } else {
+                None
+            },
+            address_resolver: None,
+            detour: if dhcp { None } else { Some(detour) },
+            inet4_range: None,
+            inet6_range: None,
+            predefined: None,
+            address: address.trim().into(),
+        })
+    }
+    pub fn local(tag: String) -> Self {
         Self {
             tag,
-            type_: None,
+            type_: Some("local".into()),
+            legacy_address: None,
             server: None,
-            address: Some(address),
-            detour: Some(detour),
+            server_port: None,
+            path: None,
+            interface: None,
+            domain_resolver: None,
+            address_resolver: None,
+            detour: None,
             inet4_range: None,
             inet6_range: None,
+            predefined: None,
+            address: "local".into(),
         }
     }
-    pub fn https(tag: String, server: String, detour: String) -> Self {
+    pub fn hosts(tag: String, predefined: IndexMap<String, Vec<String>>) -> Self {
         Self {
             tag,
-            type_: Some("https".into()),
-            server: Some(server),
-            address: None,
-            detour: Some(detour),
+            type_: Some("hosts".into()),
+            legacy_address: None,
+            server: None,
+            server_port: None,
+            path: None,
+            interface: None,
+            domain_resolver: None,
+            address_resolver: None,
+            detour: None,
             inet4_range: None,
             inet6_range: None,
+            predefined: Some(predefined),
+            address: "hosts".into(),
         }
     }
     pub fn fake(tag: String, inet4_range: Option<String>, inet6_range: Option<String>) -> Self {
         Self {
             tag,
             type_: Some("fakeip".into()),
+            legacy_address: None,
             server: None,
-            address: None,
+            server_port: None,
+            path: None,
+            interface: None,
+            domain_resolver: None,
+            address_resolver: None,
             detour: None,
             inet4_range,
             inet6_range,
+            predefined: None,
+            address: "fakeip".into(),
         }
     }
+
+    /// 转为 1.12 之前以 address 描述的格式, 不支持的 hosts 服务器返回 None
+    fn legacy(self) -> Option<Self> {
+        let legacy_address = match self.type_.as_deref() {
+            Some("hosts") => return None,
+            Some("local") | Some("fakeip") => self.address.clone(),
+            _ => DnsAddress::parse(&self.address).ok()?.legacy(),
+        };
+        Some(Self {
+            tag: self.tag,
+            type_: None,
+            legacy_address: Some(legacy_address),
+            server: None,
+            server_port: None,
+            path: None,
+            interface: None,
+            domain_resolver: None,
+            address_resolver: self.domain_resolver,
+            detour: self.detour,
+            inet4_range: None,
+            inet6_range: None,
+            predefined: None,
+            address: self.address,
+        })
+    }
 }
 
 #[derive(Serialize)]
-struct DnsRule {
-    rule_set: String,
+pub(crate) struct DnsRule {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    type_: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    server: Option<String>,
+    mode: Option<String>,
+    /// 逻辑规则的子规则
     #[serde(skip_serializing_if = "Option::is_none")]
-    action: Option<String>,
+    pub(crate) rules: Option<Vec<Value>>,
+    /// 为空时匹配所有请求
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) rule_set: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) domain: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query_type: Option<Vec<String>>,
+    /// 响应中没有ip时跳过此规则, 继续匹配后续规则
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip_accept_any: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) server: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) action: Option<String>,
 }
 
 impl DnsRule {
+    fn empty() -> Self {
+        Self {
+            type_: None,
+            mode: None,
+            rules: None,
+            rule_set: None,
+            domain: None,
+            query_type: None,
+            ip_accept_any: None,
+            server: None,
+            action: None,
+        }
+    }
     pub fn route(rule_set: String, server: String) -> Self {
+        Self {
+            server: Some(server),
+            rule_set: Some(rule_set),
+            action: Some("route".into()),
+            ..Self::empty()
+        }
+    }
+    /// 规则集中除 exclude 外的域名
+    pub fn route_exclude(rule_set: String, exclude: Value, server: String) -> Self {
+        let mut exclude = exclude;
+        if let Value::Object(map) = &mut exclude {
+            map.insert("invert".into(), Value::Bool(true));
+        }
+        let mut include = Map::new();
+        include.insert("rule_set".into(), Value::String(rule_set));
+        Self {
+            type_: Some("logical".into()),
+            mode: Some("and".into()),
+            rules: Some(vec![Value::Object(include), exclude]),
+            server: Some(server),
+            action: Some("route".into()),
+            ..Self::empty()
+        }
+    }
+    pub fn domain(domain: Vec<String>, server: String) -> Self {
+        Self {
+            server: Some(server),
+            domain: Some(domain),
+            action: Some("route".into()),
+            ..Self::empty()
+        }
+    }
+    /// 仅处理 A/AAAA 查询, failover 为 true 时没有结果则交给下一条规则
+    pub fn address(rule_set: Option<String>, server: String, failover: bool) -> Self {
         Self {
             server: Some(server),
             rule_set,
+            query_type: Some(vec!["A".into(), "AAAA".into()]),
+            ip_accept_any: if failover { Some(true) } else { None },
             action: Some("route".into()),
+            ..Self::empty()
         }
     }
     pub fn reject(rule_set: String) -> Self {
@@ -430,9 +859,9 @@ impl DnsRule {
     }
     pub fn action(rule_set: String, action: String) -> Self {
         Self {
-            server: None,
-            rule_set,
+            rule_set: Some(rule_set),
             action: Some(action),
+            ..Self::empty()
         }
     }
 }
@@ -443,23 +872,44 @@ struct SingBoxConfig {
     experimental: ExperimentalConfig,
     inbounds: Vec<Inbound>,
     outbounds: Vec<Outbound>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    endpoints: Vec<Endpoint>,
     route: RouteConfig,
     dns: DnsConfig,
 }
 
 impl KernelConfig {
     pub fn sing_box_default(&self) -> AnyResult<String> {
-        self.sing_box(default_ui, default_mixed_listen, default_mixed_port)
+        self.sing_box(
+            default_ui,
+            default_mixed_listen,
+            default_mixed_port,
+            SingBoxVersion::default(),
+        )
     }
 
-    pub fn sing_box(&self, ui: &str, mixed_listen: &str, mixed_port: u16) -> AnyResult<String> {
-        let (route, dns) = self.sing_box_build_dns_route();
+    /// 按目标内核版本生成配置
+    pub fn sing_box(
+        &self,
+        ui: &str,
+        mixed_listen: &str,
+        mixed_port: u16,
+        version: SingBoxVersion,
+    ) -> AnyResult<String> {
+        let (mut route, mut dns) = self.sing_box_build_dns_route();
+        if version.is_legacy() {
+            dns = dns.legacy();
+        } else {
+            route.default_domain_resolver = Some(tag_dns_cn.into());
+        }
+        let (outbounds, endpoints) = self.sing_box_build_outbounds(version)?;
 
         let config = SingBoxConfig {
             log: self.sing_box_build_log(),
             experimental: self.sing_box_build_experimental(ui),
-            inbounds: self.sing_box_build_inbounds(mixed_listen, mixed_port),
-            outbounds: self.sing_box_build_outbounds(),
+            inbounds: self.sing_box_build_inbounds(mixed_listen, mixed_port, version),
+            outbounds,
+            endpoints,
             route,
             dns,
         };
@@ -474,6 +924,7 @@ impl KernelConfig {
                 "info".into()
             },
             timestamp: true,
+            disable_color: true,
         }
     }
 
@@ -497,22 +948,45 @@ impl KernelConfig {
         }
     }
 
-    fn sing_box_build_inbounds(&self, listen: &str, port: u16) -> Vec<Inbound> {
+    fn sing_box_build_inbounds(
+        &self,
+        listen: &str,
+        port: u16,
+        version: SingBoxVersion,
+    ) -> Vec<Inbound> {
         let mut inbounds = Vec::new();
         if self.tun {
-            inbounds.push(self.sing_box_build_tun());
+            inbounds.push(self.sing_box_build_tun(version));
+        }
+        inbounds.push(self.sing_box_build_mixed(&local_listen(listen, self.allow_lan), port));
+        for (i, inbound) in self.inbounds.iter().enumerate() {
+            inbounds.push(Inbound::Proxy {
+                kind: inbound.kind.clone(),
+                tag: inbound.tag(i),
+                listen: local_listen(&inbound.listen, self.allow_lan),
+                listen_port: inbound.port,
+                users: inbound.users.clone(),
+            });
+        }
+        if let Ok(addr) = self.dns.listen.parse::<SocketAddr>() {
+            inbounds.push(Inbound::Direct {
+                kind: "direct".into(),
+                tag: "dns-in".into(),
+                listen: local_listen(&addr.ip().to_string(), self.allow_lan),
+                listen_port: addr.port(),
+            });
         }
-        inbounds.push(self.sing_box_build_mixed(listen, port));
         inbounds
     }
 
-    fn sing_box_build_tun(&self) -> Inbound {
+    fn sing_box_build_tun(&self, version: SingBoxVersion) -> Inbound {
         let (address, route_address, route_exclude_address) = if self.fake_ip {
+            let (ipv4, ipv6) = self.sing_box_fake_range();
             let mut v_ip = vec![virtual_ipv4.into()];
-            let mut f_ip = vec![fake_ipv4.into()];
+            let mut f_ip = vec![ipv4];
             if self.ipv6 {
                 v_ip.push(virtual_ipv6.into());
-                f_ip.push(fake_ipv6.into());
+                f_ip.push(ipv6);
             }
             (Some(v_ip), Some(f_ip), None)
         } else {
@@ -524,6 +998,19 @@ impl KernelConfig {
             }
             (Some(r_ip), None, Some(ex_ip))
         };
+        let setting = &self.tun_setting;
+        let route_exclude_address = if setting.route_exclude_address.is_empty() {
+            route_exclude_address
+        } else {
+            let mut ex_ip = route_exclude_address.unwrap_or_default();
+            ex_ip.extend(setting.route_exclude_address.iter().cloned());
+            Some(ex_ip)
+        };
+        let interface_name = if setting.interface_name.is_empty() {
+            "NcRustTunBySingBox".into()
+        } else {
+            setting.interface_name.clone()
+        };
 
         let mut loopback_address: Vec<String> = vec![loopback_ipv4.to_string()];
         if self.ipv6 {
@@ -532,18 +1019,21 @@ impl KernelConfig {
 
         Inbound::Tun {
             kind: "tun".into(),
-            tag: "tun-in".into(),
-            interface_name: "NcRustTunBySingBox".into(),
+            tag: tag_tun_in.into(),
+            interface_name,
             auto_route: true,
             strict_route: true,
             endpoint_independent_nat: false,
             udp_timeout: "5m".into(),
-            stack: "system".into(),
-            sniff_override_destination: false,
+            stack: setting.stack.clone(),
+            mtu: (setting.mtu > 0).then_some(setting.mtu),
+            sniff_override_destination: version.is_legacy().then_some(false),
+            loopback_address: (!version.is_legacy()).then_some(loopback_address),
             address,
             route_address,
-            loopback_address,
             route_exclude_address,
+            include_uid: (!setting.include_uid.is_empty()).then(|| setting.include_uid.clone()),
+            exclude_uid: (!setting.exclude_uid.is_empty()).then(|| setting.exclude_uid.clone()),
         }
     }
 
@@ -560,7 +1050,11 @@ impl KernelConfig {
         }
     }
 
-    fn sing_box_build_outbounds(&self) -> Vec<Outbound> {
+    /// wireguard 节点在 1.12 起生成为端点, 与出站一起返回
+    fn sing_box_build_outbounds(
+        &self,
+        version: SingBoxVersion,
+    ) -> AnyResult<(Vec<Outbound>, Vec<Endpoint>)> {
         let auto_area = self.sing_box_build_outbound_auto_area();
         let auto_outbounds: Vec<String> = auto_area.iter().map(|group| group.tag.clone()).collect();
         // 自动选择
@@ -583,11 +1077,16 @@ impl KernelConfig {
         // 合并所有代理组
         let mut outbounds = vec![selector, auto, fallback];
         outbounds.extend(auto_area);
-        self.nodes
-            .iter()
-            .for_each(|node| outbounds.push(Outbound::node(node)));
-        outbounds.push(Outbound::direct(out_direct));
-        outbounds
+        let mut endpoints = Vec::new();
+        for node in &self.nodes {
+            match node.node_type == type_wireguard {
+                true if version.is_legacy() => outbounds.push(Outbound::wireguard(node)?),
+                true => endpoints.push(Endpoint::wireguard(node)?),
+                false => outbounds.push(Outbound::node(node)),
+            }
+        }
+        outbounds.push(Outbound::direct(out_direct, !version.is_legacy()));
+        Ok((outbounds, endpoints))
     }
 
     fn sing_box_build_outbound_auto_area(&self) -> Vec<Outbound> {
@@ -636,7 +1135,7 @@ impl KernelConfig {
         Outbound::selector(tag, default, outbounds)
     }
 
-    fn sing_box_build_dns_route(&self) -> (RouteConfig, DnsConfig) {
+    pub(crate) fn sing_box_build_dns_route(&self) -> (RouteConfig, DnsConfig) {
         let route = self.sing_box_build_route();
         let dns = self.sing_box_build_dns(&route);
 
@@ -671,23 +1170,30 @@ impl KernelConfig {
             &mut rules_ip,
         );
 
-        let mut rule_set = Vec::new();
+        // geo 规则出口为策略组时, 记录规则集对应的出口
+        let (rules_geo, groups) = self.sing_box_build_geo_rules();
+
+        // 自定义规则与 geo 规则排在所有订阅规则之前
+        let mut rule_set = self.sing_box_build_custom_rules();
+        rule_set.extend(rules_geo);
         rule_set.extend(rules_process);
         rule_set.extend(rules_other);
         rule_set.extend(rules_ip);
 
         let mut rules = vec![RouteRule::sniff(), RouteRule::dns()];
+        rules.extend(self.sing_box_build_bypass_rules());
 
         rule_set.iter().for_each(|rule| {
             let rr = if rule.tag.starts_with(key_reject) {
                 RouteRule::reject(rule.tag.clone())
             } else {
-                let out = if rule.tag.starts_with(key_proxy) {
-                    tag_selector
+                let out = if let Some(group) = groups.get(&rule.tag) {
+                    group.clone()
+                } else if rule.tag.starts_with(key_proxy) {
+                    tag_selector.into()
                 } else {
-                    out_direct
-                }
-                .into();
+                    out_direct.into()
+                };
                 RouteRule::out(rule.tag.clone(), out)
             };
 
@@ -697,11 +1203,151 @@ impl KernelConfig {
         RouteConfig {
             final_: tag_fallback.into(),
             auto_detect_interface: true,
+            default_domain_resolver: None,
             rule_set,
             rules,
         }
     }
 
+    /// tun 的分应用代理, 排在所有规则之前. 排除的进程与用户直连, 指定了仅代理时其余的直连
+    fn sing_box_build_bypass_rules(&self) -> Vec<RouteRule> {
+        let mut rules = Vec::new();
+        if !self.tun {
+            return rules;
+        }
+        let setting = &self.tun_setting;
+        let some = |list: &Vec<String>| (!list.is_empty()).then(|| list.clone());
+        if let Some(list) = some(&setting.exclude_process) {
+            rules.push(RouteRule::bypass(Some(list), None, false));
+        }
+        if let Some(list) = some(&setting.exclude_user) {
+            rules.push(RouteRule::bypass(None, Some(list), false));
+        }
+        if let Some(list) = some(&setting.include_process) {
+            rules.push(RouteRule::bypass(Some(list), None, true));
+        }
+        if let Some(list) = some(&setting.include_user) {
+            rules.push(RouteRule::bypass(None, Some(list), true));
+        }
+        rules
+    }
+
+    /// 自定义规则编译为内联规则集, 相邻且出口相同的规则合并为一个规则集
+    fn sing_box_build_custom_rules(&self) -> Vec<SingBoxRule> {
+        let mut vec = Vec::new();
+        for (target, lines) in self.custom_rule_groups() {
+            let headless = lines
+                .iter()
+                .filter_map(|line| rule_classical::parse_line(line).ok().flatten())
+                .collect();
+            let Ok(rules) = SinBoxJsonRule::from_headless(headless, true) else {
+                continue;
+            };
+
+            for rule in rules {
+                let kind = match rule.type_ {
+                    RuleType::Process => "p",
+                    RuleType::Ip => "i",
+                    RuleType::Other => "o",
+                };
+                let tag = format!("{}_{}_c{}", target, kind, vec.len());
+                let Ok(Value::Object(mut json)) = serde_json::from_str::<Value>(&rule.json) else {
+                    continue;
+                };
+                if let Some(Value::Array(rules)) = json.remove("rules") {
+                    vec.push(SingBoxRule::inline(tag, rules));
+                }
+            }
+        }
+        vec
+    }
+
+    /// geo 分类规则, 返回规则集与出口为策略组的规则集对应的出口
+    fn sing_box_build_geo_rules(&self) -> (Vec<SingBoxRule>, HashMap<String, String>) {
+        let mut vec = Vec::new();
+        let mut groups = HashMap::new();
+        for r in self.geo_rules() {
+            let (base, kind, rule_type) = if r.is_ip() {
+                (geo_ip_rule_set, "i", RuleType::Ip)
+            } else {
+                (geo_site_rule_set, "o", RuleType::Other)
+            };
+            let url = format!("{}/{}.srs", base, r.name());
+            let tag = format!("{}_{}_{}", r.key(), kind, r.name());
+            if let Some(group) = r.group() {
+                groups.insert(tag.clone(), group.to_string());
+            }
+            vec.push(Rule::from_remote(rule_type, url).sing_box(&tag));
+        }
+        (vec, groups)
+    }
+
+    /// fake-ip 地址段, 未设置时使用默认值
+    fn sing_box_fake_range(&self) -> (String, String) {
+        let or = |value: &str, default: &str| {
+            if value.trim().is_empty() {
+                default.to_string()
+            } else {
+                value.trim().to_string()
+            }
+        };
+        (
+            or(&self.dns.fake_ipv4, fake_ipv4),
+            or(&self.dns.fake_ipv6, fake_ipv6),
+        )
+    }
+
+    /// 为每个 dns 地址生成一个服务器, 第一个使用 tag, 其余追加序号. 返回生成的 tag
+    fn sing_box_build_dns_servers(
+        &self,
+        tag: &str,
+        list: &[String],
+        default: &[String],
+        detour: &str,
+        servers: &mut Vec<DnsServer>,
+    ) -> Vec<String> {
+        let mut addresses: Vec<&String> = list
+            .iter()
+            .filter(|a| DnsAddress::parse(a).is_ok())
+            .collect();
+        if addresses.is_empty() {
+            addresses = default.iter().collect();
+        }
+
+        let mut tags = Vec::new();
+        for address in addresses {
+            let t = if tags.is_empty() {
+                tag.to_string()
+            } else {
+                format!("{}-{}", tag, tags.len())
+            };
+            if let Ok(server) = DnsServer::from(t.clone(), address, detour.into()) {
+                servers.push(server);
+                tags.push(t);
+            }
+        }
+        tags
+    }
+
+    /// 多个服务器时, A/AAAA 查询依次尝试, 没有结果时交给下一个服务器. 其他查询使用第一个服务器
+    fn sing_box_build_dns_failover(rule_set: Option<&str>, servers: &[String]) -> Vec<DnsRule> {
+        let mut vec = Vec::new();
+        if servers.len() > 1 {
+            let last = servers.len() - 1;
+            for (i, server) in servers.iter().enumerate() {
+                vec.push(DnsRule::address(
+                    rule_set.map(|r| r.into()),
+                    server.clone(),
+                    i < last,
+                ));
+            }
+        }
+        if let (Some(rule_set), Some(server)) = (rule_set, servers.first()) {
+            vec.push(DnsRule::route(rule_set.into(), server.clone()));
+        }
+        vec
+    }
+
     fn sing_box_process_rules<'a>(
         &self,
         rules: impl Iterator<Item = &'a Rule>,
@@ -735,28 +1381,49 @@ impl KernelConfig {
     }
 
     fn sing_box_build_dns(&self, route: &RouteConfig) -> DnsConfig {
-        let mut servers = vec![
-            DnsServer::from(
-                tag_dns_cn.into(),
-                self.dns_cn.get(0).unwrap().clone(),
-                out_direct.into(),
-            ),
-            DnsServer::from(
-                tag_dns_proxy.into(),
-                self.dns_proxy.get(0).unwrap().clone(),
-                tag_selector.into(),
-            ),
-        ];
+        let mut servers = Vec::new();
+        let cn = self.sing_box_build_dns_servers(
+            tag_dns_cn,
+            &self.dns_cn,
+            &dns_default_cn,
+            out_direct,
+            &mut servers,
+        );
+        let proxy = self.sing_box_build_dns_servers(
+            tag_dns_proxy,
+            &self.dns_proxy,
+            &dns_default_proxy,
+            tag_selector,
+            &mut servers,
+        );
+
+        if servers.iter().any(|s| s.domain_resolver.is_some()) {
+            servers.push(DnsServer::local(tag_dns_local.into()));
+        }
 
         if self.fake_ip {
-            servers.push(DnsServer::fake(
-                tag_dns_fake.into(),
-                Some(fake_ipv4.into()),
-                Some(fake_ipv6.into()),
+            let (ipv4, ipv6) = self.sing_box_fake_range();
+            servers.push(DnsServer::fake(tag_dns_fake.into(), Some(ipv4), Some(ipv6)));
+        }
+
+        // hosts 优先于所有规则
+        let mut rules = Vec::new();
+        if !self.dns.hosts.is_empty() {
+            let hosts: IndexMap<String, Vec<String>> = self
+                .dns
+                .hosts
+                .iter()
+                .map(|(k, v)| (k.trim().to_lowercase(), v.clone()))
+                .collect();
+            rules.push(DnsRule::domain(
+                hosts.keys().cloned().collect(),
+                tag_dns_hosts.into(),
             ));
+            servers.push(DnsServer::hosts(tag_dns_hosts.into(), hosts));
         }
 
-        let rules = route
+        let fake_ip_filter = self.dns.fake_ip_filter_rule();
+        rules.extend(route
             .rule_set
             .iter()
             .filter_map(|r| {
@@ -770,28 +1437,43 @@ impl KernelConfig {
 
                 if tag.starts_with(key_proxy) {
                     if self.fake_ip {
-                        vec.push(DnsRule::route(tag.into(), tag_dns_fake.into()));
+                        vec.push(match &fake_ip_filter {
+                            Some(filter) => DnsRule::route_exclude(
+                                tag.into(),
+                                filter.clone(),
+                                tag_dns_fake.into(),
+                            ),
+                            None => DnsRule::route(tag.into(), tag_dns_fake.into()),
+                        });
                     }
-                    vec.push(DnsRule::route(tag.into(), tag_dns_proxy.into()));
+                    vec.extend(Self::sing_box_build_dns_failover(Some(tag), &proxy));
                 } else if tag.starts_with(key_direct) {
-                    vec.push(DnsRule::route(tag.into(), tag_dns_cn.into()));
+                    vec.extend(Self::sing_box_build_dns_failover(Some(tag), &cn));
                 } else {
                     vec.push(DnsRule::reject(tag.into()));
                 }
 
                 Some(vec)
             })
-            .flatten()
-            .collect();
+            .flatten(),
+        );
+        // 未匹配规则集的请求也按顺序尝试直连服务器
+        rules.extend(Self::sing_box_build_dns_failover(None, &cn));
+
+        let client_subnet = Some(self.dns.client_subnet.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string());
 
         DnsConfig {
             final_: tag_dns_cn.into(),
-            disable_cache: false,
-            disable_expire: false,
-            independent_cache: true,
+            disable_cache: self.dns.disable_cache,
+            disable_expire: self.dns.disable_expire,
+            independent_cache: self.dns.independent_cache,
             strategy: self.ip_strategy(),
+            client_subnet,
             servers,
             rules,
+            fakeip: None,
         }
     }
 }
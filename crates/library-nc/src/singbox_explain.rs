@@ -0,0 +1,791 @@
+use crate::kernel::{KernelConfig, key_reject};
+use crate::rule::SingBoxRule;
+use crate::rule_classical::{
+    field_domain, field_domain_keyword, field_domain_regex, field_domain_suffix, field_ip_cidr,
+    field_network, field_port, field_port_range, field_process_name, field_process_path,
+    field_process_path_regex,
+};
+use crate::rule_srs;
+use crate::rule_srs::CidrRange;
+use crate::singbox::{DnsConfig, DnsRule, RouteConfig, RouteRule};
+use library_core::core::AnyResult;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// 待分析的连接, 未提供的字段不参与匹配
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExplainQuery {
+    pub domain: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub port: Option<u16>,
+    /// tcp / udp, 默认 tcp
+    pub network: Option<String>,
+    /// 进程名称或者完整路径
+    pub process: Option<String>,
+    /// 入站标签, 如 tun-in, mixed-in. 分应用代理仅对 tun-in 生效
+    pub inbound: Option<String>,
+}
+
+impl ExplainQuery {
+    fn domain(&self) -> Option<String> {
+        self.domain
+            .as_ref()
+            .map(|d| d.trim().trim_end_matches('.').to_lowercase())
+            .filter(|d| !d.is_empty())
+    }
+
+    fn network(&self) -> String {
+        self.network
+            .as_ref()
+            .map(|n| n.trim().to_lowercase())
+            .unwrap_or_else(|| "tcp".into())
+    }
+
+    /// (进程名称, 进程路径)
+    fn process(&self) -> (Option<&str>, Option<&str>) {
+        match self.process.as_deref().map(|p| p.trim()) {
+            None | Some("") => (None, None),
+            Some(p) if p.contains('/') || p.contains('\\') => {
+                (p.rsplit(['/', '\\']).next(), Some(p))
+            }
+            Some(p) => (Some(p), None),
+        }
+    }
+}
+
+/// 一个阶段的匹配结果
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExplainStep {
+    /// 命中规则的序号, 与生成配置中的 rules 顺序一致. 为空表示使用 final
+    pub index: Option<usize>,
+    pub rule_set: Option<String>,
+    pub action: String,
+    /// 路由为出口, dns 为服务器
+    pub target: Option<String>,
+    /// dns 服务器地址
+    pub address: Option<String>,
+    /// 规则集中第一条命中的规则
+    pub rule: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Explanation {
+    /// 域名解析, 未提供域名时为空
+    pub dns: Option<ExplainStep>,
+    pub route: ExplainStep,
+    /// 最终出口, 拒绝时为 reject
+    pub outbound: String,
+    /// 未能加载的规则集, 匹配时视为未命中
+    pub warnings: Vec<String>,
+}
+
+pub struct SingBoxExplainer {
+    route: RouteConfig,
+    dns: DnsConfig,
+    sets: HashMap<String, Vec<(Value, Matcher)>>,
+    warnings: Vec<String>,
+}
+
+impl KernelConfig {
+    /// 按照生成配置的规则顺序构建分析器, 规则集需要另外加载
+    pub fn sing_box_explainer(&self) -> SingBoxExplainer {
+        let (route, dns) = self.sing_box_build_dns_route();
+        let mut explainer = SingBoxExplainer {
+            route,
+            dns,
+            sets: HashMap::new(),
+            warnings: Vec::new(),
+        };
+        explainer.load_inline();
+        explainer
+    }
+}
+
+impl SingBoxExplainer {
+    pub fn rule_sets(&self) -> &[SingBoxRule] {
+        &self.route.rule_set
+    }
+
+    /// 加载规则集内容, 支持 srs 与 json
+    pub fn load(&mut self, tag: &str, bytes: &[u8]) -> AnyResult<()> {
+        let json = if bytes.starts_with(&rule_srs::magic) {
+            rule_srs::read(bytes)?
+        } else {
+            serde_json::from_slice(bytes)?
+        };
+
+        let mut vec = Vec::new();
+        if let Some(rules) = json.get("rules").and_then(Value::as_array) {
+            for rule in rules {
+                vec.push((rule.clone(), Matcher::from(rule)?));
+            }
+        }
+        self.sets.insert(tag.into(), vec);
+        Ok(())
+    }
+
+    /// 加载本地规则集, 失败的记录在告警中
+    pub fn load_local(&mut self) {
+        let locals: Vec<(String, String)> = self
+            .route
+            .rule_set
+            .iter()
+            .filter_map(|r| r.path.clone().map(|p| (r.tag.clone(), p)))
+            .collect();
+
+        for (tag, path) in locals {
+            let result = std::fs::read(&path)
+                .map_err(|e| e.into())
+                .and_then(|bytes| self.load(&tag, &bytes));
+            if let Err(e) = result {
+                self.warn(&tag, &e.to_string());
+            }
+        }
+    }
+
+    /// 加载内联规则集
+    fn load_inline(&mut self) {
+        let inlines: Vec<(String, Vec<Value>)> = self
+            .route
+            .rule_set
+            .iter()
+            .filter_map(|r| r.rules.clone().map(|rules| (r.tag.clone(), rules)))
+            .collect();
+
+        for (tag, rules) in inlines {
+            let json = serde_json::json!({ "rules": rules });
+            let result = serde_json::to_vec(&json)
+                .map_err(|e| e.into())
+                .and_then(|bytes| self.load(&tag, &bytes));
+            if let Err(e) = result {
+                self.warn(&tag, &e.to_string());
+            }
+        }
+    }
+
+    pub fn warn(&mut self, tag: &str, reason: &str) {
+        self.warnings
+            .push(format!("规则集[{}]加载失败: {}", tag, reason));
+    }
+
+    pub fn explain(&self, query: &ExplainQuery) -> Explanation {
+        let dns = query.domain().map(|_| {
+            // dns 查询时还没有目标ip
+            let q = ExplainQuery {
+                ip: None,
+                port: None,
+                ..query.clone()
+            };
+            self.explain_dns(&q)
+        });
+        let route = self.explain_route(query);
+        let outbound = match route.action.as_str() {
+            "route" => route.target.clone().unwrap_or_default(),
+            action => action.into(),
+        };
+
+        let mut warnings = self.warnings.clone();
+        for r in &self.route.rule_set {
+            if !self.sets.contains_key(&r.tag) && !warnings.iter().any(|w| w.contains(&r.tag)) {
+                warnings.push(format!("规则集[{}]未加载", r.tag));
+            }
+        }
+
+        Explanation {
+            dns,
+            route,
+            outbound,
+            warnings,
+        }
+    }
+
+    fn explain_route(&self, query: &ExplainQuery) -> ExplainStep {
+        for (i, rule) in self.route.rules.iter().enumerate() {
+            if rule.protocol.as_deref() == Some("dns") {
+                if query.port == Some(53) {
+                    return ExplainStep {
+                        index: Some(i),
+                        rule_set: None,
+                        action: rule.action.clone(),
+                        target: None,
+                        address: None,
+                        rule: None,
+                    };
+                }
+                continue;
+            }
+
+            if rule.type_.is_some() || rule.inbound.is_some() {
+                // 分应用代理, 无法判断时跳过
+                if match_bypass(rule, query) == Some(true) {
+                    return ExplainStep {
+                        index: Some(i),
+                        rule_set: None,
+                        action: rule.action.clone(),
+                        target: rule.outbound.clone(),
+                        address: None,
+                        rule: None,
+                    };
+                }
+                continue;
+            }
+
+            let Some(tag) = &rule.rule_set else {
+                // sniff 与按用户分流等无法判断的规则
+                continue;
+            };
+            if let Some(matched) = self.match_set(tag, query) {
+                return ExplainStep {
+                    index: Some(i),
+                    rule_set: Some(tag.clone()),
+                    action: rule.action.clone(),
+                    target: rule.outbound.clone(),
+                    address: None,
+                    rule: Some(matched.clone()),
+                };
+            }
+        }
+
+        ExplainStep {
+            index: None,
+            rule_set: None,
+            action: "route".into(),
+            target: Some(self.route.final_.clone()),
+            address: None,
+            rule: None,
+        }
+    }
+
+    fn explain_dns(&self, query: &ExplainQuery) -> ExplainStep {
+        for (i, rule) in self.dns.rules.iter().enumerate() {
+            let Some((tag, matched)) = self.match_dns_rule(rule, query) else {
+                continue;
+            };
+            let action = rule.action.clone().unwrap_or_else(|| "route".into());
+            return ExplainStep {
+                index: Some(i),
+                rule_set: tag,
+                address: rule.server.as_deref().and_then(|s| self.dns_address(s)),
+                target: if action == key_reject {
+                    None
+                } else {
+                    rule.server.clone()
+                },
+                action,
+                rule: Some(matched),
+            };
+        }
+
+        ExplainStep {
+            index: None,
+            rule_set: None,
+            action: "route".into(),
+            target: Some(self.dns.final_.clone()),
+            address: self.dns_address(&self.dns.final_),
+            rule: None,
+        }
+    }
+
+    /// 命中时返回规则集与命中的规则
+    fn match_dns_rule(
+        &self,
+        rule: &DnsRule,
+        query: &ExplainQuery,
+    ) -> Option<(Option<String>, Value)> {
+        // hosts
+        if let Some(domains) = &rule.domain {
+            let domain = query.domain()?;
+            return domains
+                .contains(&domain)
+                .then(|| (None, serde_json::json!({ "domain": domain })));
+        }
+
+        // 逻辑规则: 规则集中除去 fake-ip 过滤的域名
+        if let Some(rules) = &rule.rules {
+            let tag = rules.first()?.get("rule_set")?.as_str()?;
+            let matched = self.match_set(tag, query)?;
+            let excluded = rules
+                .get(1)
+                .and_then(|v| Matcher::from(v).ok())
+                .is_some_and(|m| !m.matches(query));
+            return (!excluded).then(|| (Some(tag.to_string()), matched.clone()));
+        }
+
+        // 不带规则集的是最终服务器的 failover, 按最终服务器展示
+        let tag = rule.rule_set.as_ref()?;
+        let matched = self.match_set(tag, query)?;
+        Some((Some(tag.clone()), matched.clone()))
+    }
+
+    fn dns_address(&self, tag: &str) -> Option<String> {
+        let server = self.dns.servers.iter().find(|s| s.tag == tag)?;
+        Some(server.address.clone())
+    }
+
+    fn match_set(&self, tag: &str, query: &ExplainQuery) -> Option<&Value> {
+        self.sets
+            .get(tag)?
+            .iter()
+            .find(|(_, m)| m.matches(query))
+            .map(|(v, _)| v)
+    }
+}
+
+enum Matcher {
+    Default(Box<DefaultMatcher>),
+    Logical {
+        and: bool,
+        invert: bool,
+        rules: Vec<Matcher>,
+    },
+}
+
+#[derive(Default)]
+struct DefaultMatcher {
+    domain: Vec<String>,
+    domain_suffix: Vec<String>,
+    domain_keyword: Vec<String>,
+    domain_regex: Vec<Regex>,
+    ip_cidr: Vec<CidrRange>,
+    port: Vec<u16>,
+    port_range: Vec<(u16, u16)>,
+    network: Vec<String>,
+    process_name: Vec<String>,
+    process_path: Vec<String>,
+    process_path_regex: Vec<Regex>,
+    /// 含有来源地址等无法从查询中判断的字段, 视为未命中
+    unsupported: bool,
+    invert: bool,
+}
+
+fn strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(vs) => vs.iter().flat_map(strings).collect(),
+        Value::String(s) => vec![s.clone()],
+        Value::Number(n) => vec![n.to_string()],
+        _ => vec![],
+    }
+}
+
+fn port_range(value: &str) -> Option<(u16, u16)> {
+    let (start, end) = value.split_once(':')?;
+    let start = if start.is_empty() {
+        0
+    } else {
+        start.trim().parse().ok()?
+    };
+    let end = if end.is_empty() {
+        u16::MAX
+    } else {
+        end.trim().parse().ok()?
+    };
+    Some((start, end))
+}
+
+impl Matcher {
+    fn from(value: &Value) -> AnyResult<Self> {
+        let map = value.as_object().cloned().unwrap_or_default();
+        if map.get("type").and_then(Value::as_str) == Some("logical") {
+            let mut rules = Vec::new();
+            if let Some(vs) = map.get("rules").and_then(Value::as_array) {
+                for v in vs {
+                    rules.push(Self::from(v)?);
+                }
+            }
+            return Ok(Matcher::Logical {
+                and: map.get("mode").and_then(Value::as_str) != Some("or"),
+                invert: invert(&map),
+                rules,
+            });
+        }
+
+        let mut m = DefaultMatcher {
+            invert: invert(&map),
+            ..Default::default()
+        };
+        for (k, v) in &map {
+            let vs = strings(v);
+            match k.as_str() {
+                "type" | "invert" => {}
+                field_domain => m.domain = vs.iter().map(|s| s.to_lowercase()).collect(),
+                field_domain_suffix => {
+                    m.domain_suffix = vs.iter().map(|s| s.to_lowercase()).collect()
+                }
+                field_domain_keyword => m.domain_keyword = vs,
+                field_domain_regex => {
+                    for s in vs {
+                        m.domain_regex.push(Regex::new(&s)?);
+                    }
+                }
+                field_ip_cidr => {
+                    for s in vs {
+                        m.ip_cidr.push(CidrRange::parse(&s)?);
+                    }
+                }
+                field_port => m.port = vs.iter().flat_map(|s| s.parse()).collect(),
+                field_port_range => m.port_range = vs.iter().flat_map(|s| port_range(s)).collect(),
+                field_network => m.network = vs.iter().map(|s| s.to_lowercase()).collect(),
+                field_process_name => m.process_name = vs,
+                field_process_path => m.process_path = vs,
+                field_process_path_regex => {
+                    for s in vs {
+                        m.process_path_regex.push(Regex::new(&s)?);
+                    }
+                }
+                _ => m.unsupported = true,
+            }
+        }
+        Ok(Matcher::Default(Box::new(m)))
+    }
+
+    fn matches(&self, query: &ExplainQuery) -> bool {
+        match self {
+            Matcher::Default(m) => m.matches(query) != m.invert,
+            Matcher::Logical { and, invert, rules } => {
+                let matched = if *and {
+                    rules.iter().all(|r| r.matches(query))
+                } else {
+                    rules.iter().any(|r| r.matches(query))
+                };
+                matched != *invert
+            }
+        }
+    }
+}
+
+/// 分应用代理规则, 按用户名或缺少进程等无法判断时返回 None
+fn match_bypass(rule: &RouteRule, query: &ExplainQuery) -> Option<bool> {
+    let mut matched = true;
+    if let Some(inbound) = &rule.inbound {
+        matched = query.inbound.as_ref().is_some_and(|i| inbound.contains(i));
+    }
+    if let Some(rules) = &rule.rules {
+        // 逻辑规则仅由分应用代理生成, 均为且
+        for r in rules {
+            if !matched {
+                break;
+            }
+            matched = match_bypass(r, query)?;
+        }
+    } else if matched {
+        if rule.user.is_some() {
+            return None;
+        }
+        if let Some(names) = &rule.process_name {
+            let name = query.process().0?;
+            matched = names.iter().any(|n| n == name);
+        }
+    }
+    Some(matched != rule.invert.unwrap_or(false))
+}
+
+fn invert(map: &Map<String, Value>) -> bool {
+    map.get("invert").and_then(Value::as_bool).unwrap_or(false)
+}
+
+impl DefaultMatcher {
+    /// 同 sing-box: 目标地址(域名与ip)之间为或, 其他字段之间为且
+    fn matches(&self, query: &ExplainQuery) -> bool {
+        if self.unsupported {
+            return false;
+        }
+
+        let has_address = !self.domain.is_empty()
+            || !self.domain_suffix.is_empty()
+            || !self.domain_keyword.is_empty()
+            || !self.domain_regex.is_empty()
+            || !self.ip_cidr.is_empty();
+        if has_address && !self.match_domain(query) && !self.match_ip(query) {
+            return false;
+        }
+
+        if !self.port.is_empty() || !self.port_range.is_empty() {
+            let matched = query.port.is_some_and(|p| {
+                self.port.contains(&p) || self.port_range.iter().any(|(s, e)| *s <= p && p <= *e)
+            });
+            if !matched {
+                return false;
+            }
+        }
+
+        if !self.network.is_empty() && !self.network.contains(&query.network()) {
+            return false;
+        }
+
+        let (name, path) = query.process();
+        if !self.process_name.is_empty()
+            && !name.is_some_and(|n| self.process_name.iter().any(|p| p == n))
+        {
+            return false;
+        }
+        if !self.process_path.is_empty()
+            && !path.is_some_and(|n| self.process_path.iter().any(|p| p == n))
+        {
+            return false;
+        }
+        if !self.process_path_regex.is_empty()
+            && !path.is_some_and(|n| self.process_path_regex.iter().any(|r| r.is_match(n)))
+        {
+            return false;
+        }
+
+        true
+    }
+
+    fn match_domain(&self, query: &ExplainQuery) -> bool {
+        let Some(domain) = query.domain() else {
+            return false;
+        };
+        self.domain.contains(&domain)
+            || self.domain_suffix.iter().any(|s| {
+                if s.starts_with('.') {
+                    domain.ends_with(s.as_str())
+                } else {
+                    domain == *s || domain.ends_with(&format!(".{}", s))
+                }
+            })
+            || self
+                .domain_keyword
+                .iter()
+                .any(|k| domain.contains(k.as_str()))
+            || self.domain_regex.iter().any(|r| r.is_match(&domain))
+    }
+
+    fn match_ip(&self, query: &ExplainQuery) -> bool {
+        query
+            .ip
+            .is_some_and(|ip| self.ip_cidr.iter().any(|c| c.contains(ip)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::{CustomRule, out_direct, tag_fallback, tag_selector};
+    use crate::singbox::tag_tun_in;
+    use serde_json::json;
+
+    fn config() -> KernelConfig {
+        let custom = |line: &str, target: &str| CustomRule {
+            line: line.into(),
+            target: target.into(),
+        };
+        let mut config = KernelConfig {
+            tun: true,
+            fake_ip: true,
+            dns_cn: vec!["223.5.5.5".into()],
+            dns_proxy: vec!["https://1.1.1.1/dns-query".into()],
+            rules_custom: vec![
+                custom("DOMAIN-SUFFIX,corp.local", "direct"),
+                custom("IP-CIDR,10.0.0.0/8", "direct"),
+                custom("DOMAIN-KEYWORD,ads", "reject"),
+                custom("PROCESS-NAME,curl", "proxy"),
+                custom("DOMAIN-REGEX,^api\\d+\\.example\\.com$", "proxy"),
+            ],
+            ..Default::default()
+        };
+        config
+            .dns
+            .hosts
+            .insert("nas.lan".into(), vec!["192.168.1.2".into()]);
+        config
+    }
+
+    fn domain(domain: &str) -> ExplainQuery {
+        ExplainQuery {
+            domain: Some(domain.into()),
+            ..Default::default()
+        }
+    }
+
+    /// (规则序号, 规则集, 出口)
+    fn route(e: &Explanation) -> (Option<usize>, Option<&str>, &str) {
+        (
+            e.route.index,
+            e.route.rule_set.as_deref(),
+            e.outbound.as_str(),
+        )
+    }
+
+    /// (规则序号, 动作, 服务器)
+    fn dns(e: &Explanation) -> (Option<usize>, &str, Option<&str>) {
+        let dns = e.dns.as_ref().unwrap();
+        (dns.index, dns.action.as_str(), dns.target.as_deref())
+    }
+
+    #[test]
+    fn explain_domain() {
+        let explainer = config().sing_box_explainer();
+
+        let e = explainer.explain(&domain("WWW.Corp.Local."));
+        assert_eq!(dns(&e), (Some(1), "route", Some("dns-cn")));
+        assert_eq!(route(&e), (Some(3), Some("direct_o_c1"), out_direct));
+        assert_eq!(
+            e.route.rule,
+            Some(json!({ "domain_suffix": ["corp.local"] }))
+        );
+
+        // 关键字拒绝, dns 也拒绝
+        let e = explainer.explain(&domain("myads.com"));
+        assert_eq!(dns(&e), (Some(2), key_reject, None));
+        assert_eq!(route(&e), (Some(4), Some("reject_o_c2"), key_reject));
+
+        // 代理域名使用 fake-ip
+        let e = explainer.explain(&domain("api12.example.com"));
+        assert_eq!(dns(&e), (Some(3), "route", Some("dns-fake")));
+        assert_eq!(route(&e), (Some(6), Some("proxy_o_c4"), tag_selector));
+
+        let e = explainer.explain(&domain("nas.lan"));
+        assert_eq!(dns(&e), (Some(0), "route", Some("dns-hosts")));
+        assert_eq!(route(&e), (None, None, tag_fallback));
+
+        let e = explainer.explain(&domain("unknown.org"));
+        assert_eq!(dns(&e), (None, "route", Some("dns-cn")));
+        assert_eq!(route(&e), (None, None, tag_fallback));
+        assert!(e.warnings.is_empty());
+    }
+
+    #[test]
+    fn explain_ip_and_process() {
+        let explainer = config().sing_box_explainer();
+
+        let e = explainer.explain(&ExplainQuery {
+            ip: Some("10.1.2.3".parse().unwrap()),
+            ..Default::default()
+        });
+        assert!(e.dns.is_none());
+        assert_eq!(route(&e), (Some(2), Some("direct_i_c0"), out_direct));
+
+        let e = explainer.explain(&ExplainQuery {
+            ip: Some("11.1.2.3".parse().unwrap()),
+            process: Some("/usr/bin/curl".into()),
+            ..Default::default()
+        });
+        assert_eq!(route(&e), (Some(5), Some("proxy_p_c3"), tag_selector));
+
+        // dns 端口被劫持
+        let e = explainer.explain(&ExplainQuery {
+            port: Some(53),
+            ..Default::default()
+        });
+        assert_eq!(e.route.index, Some(1));
+        assert_eq!(e.route.action, "hijack-dns");
+    }
+
+    #[test]
+    fn explain_bypass() {
+        let mut config = config();
+        config.tun_setting.exclude_process = vec!["steam".into()];
+        let explainer = config.sing_box_explainer();
+        let steam = |inbound: Option<&str>| ExplainQuery {
+            process: Some("steam".into()),
+            inbound: inbound.map(|i| i.into()),
+            ..Default::default()
+        };
+        let e = explainer.explain(&steam(Some(tag_tun_in)));
+        assert_eq!(route(&e), (Some(2), None, out_direct));
+        // 仅对 tun 入站生效
+        let e = explainer.explain(&steam(Some("mixed-in")));
+        assert_eq!(route(&e), (None, None, tag_fallback));
+        let e = explainer.explain(&steam(None));
+        assert_eq!(route(&e), (None, None, tag_fallback));
+
+        // 仅代理 firefox, 其余 tun 流量直连
+        let mut config = self::config();
+        config.tun_setting.include_process = vec!["firefox".into()];
+        let explainer = config.sing_box_explainer();
+        let process = |p: &str| ExplainQuery {
+            process: Some(p.into()),
+            inbound: Some(tag_tun_in.into()),
+            ..Default::default()
+        };
+        let e = explainer.explain(&process("chrome"));
+        assert_eq!(route(&e), (Some(2), None, out_direct));
+        let e = explainer.explain(&process("firefox"));
+        assert_eq!(route(&e), (None, None, tag_fallback));
+        let e = explainer.explain(&process("curl"));
+        assert_eq!(route(&e), (Some(2), None, out_direct));
+    }
+
+    #[test]
+    fn match_rule_fields() {
+        let mut explainer = config().sing_box_explainer();
+        let rules = json!({
+            "version": 2,
+            "rules": [
+                { "port_range": ["8000:9000"], "network": ["udp"] },
+                { "domain": ["a.com"], "ip_cidr": ["1.1.1.0/24"], "port": [443] },
+                {
+                    "type": "logical",
+                    "mode": "or",
+                    "rules": [
+                        { "domain_keyword": ["video"] },
+                        { "domain_suffix": [".cdn.net"], "invert": true }
+                    ],
+                    "invert": true
+                }
+            ]
+        });
+        let srs = rule_srs::from_json(&rules.to_string()).unwrap();
+        explainer.load("proxy_o_c4", &srs).unwrap();
+
+        let matched = |query: ExplainQuery| {
+            let e = explainer.explain(&query);
+            (e.route.rule_set == Some("proxy_o_c4".into())).then(|| e.route.rule.unwrap())
+        };
+
+        // 端口范围与网络为且
+        let udp = ExplainQuery {
+            port: Some(8080),
+            network: Some("UDP".into()),
+            ..Default::default()
+        };
+        assert_eq!(matched(udp.clone()), Some(rules["rules"][0].clone()));
+        let tcp = ExplainQuery {
+            network: None,
+            ..udp.clone()
+        };
+        assert_eq!(matched(tcp), None);
+        let out_of_range = ExplainQuery {
+            port: Some(9001),
+            ..udp
+        };
+        assert_eq!(matched(out_of_range), None);
+
+        // 域名与ip为或, 端口为且
+        let ip = ExplainQuery {
+            ip: Some("1.1.1.1".parse().unwrap()),
+            port: Some(443),
+            ..Default::default()
+        };
+        assert_eq!(matched(ip.clone()), Some(rules["rules"][1].clone()));
+        let other_port = ExplainQuery {
+            port: Some(80),
+            ..ip
+        };
+        assert_eq!(matched(other_port), None);
+
+        // 非(含 video 或 非 .cdn.net 子域名)
+        assert_eq!(
+            matched(domain("img.cdn.net")),
+            Some(rules["rules"][2].clone())
+        );
+        assert_eq!(matched(domain("cdn.net")), None);
+        assert_eq!(matched(domain("video.cdn.net")), None);
+    }
+
+    #[test]
+    fn warn_unloaded() {
+        let mut explainer = config().sing_box_explainer();
+        explainer.warn("direct_o_c1", "broken");
+        assert!(explainer.load("proxy_o_c4", b"{").is_err());
+        let e = explainer.explain(&domain("a.com"));
+        assert_eq!(e.warnings, ["规则集[direct_o_c1]加载失败: broken"]);
+    }
+}
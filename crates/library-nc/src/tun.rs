@@ -0,0 +1,94 @@
+use crate::core::NcError;
+use crate::rule_srs::CidrRange;
+use library_core::core::AnyResult;
+use serde::{Deserialize, Serialize};
+
+/// tun 支持的网络栈
+pub const tun_stacks: [&str; 3] = ["system", "gvisor", "mixed"];
+
+/// tun 设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TunSetting {
+    /// 网络栈 system / gvisor / mixed
+    pub stack: String,
+    /// 为 0 时使用内核的默认值
+    pub mtu: u32,
+    /// 网卡名称, 为空时使用默认值
+    pub interface_name: String,
+    /// 额外不经过 tun 的地址段
+    pub route_exclude_address: Vec<String>,
+    /// 仅代理或不代理这些用户id, 仅 linux 有效
+    pub include_uid: Vec<u32>,
+    pub exclude_uid: Vec<u32>,
+    /// 仅代理或不代理这些用户名, 仅 linux 有效. clash 不支持
+    pub include_user: Vec<String>,
+    pub exclude_user: Vec<String>,
+    /// 仅代理或不代理这些进程名
+    pub include_process: Vec<String>,
+    pub exclude_process: Vec<String>,
+}
+
+impl Default for TunSetting {
+    fn default() -> Self {
+        Self {
+            stack: "system".into(),
+            mtu: 0,
+            interface_name: "".into(),
+            route_exclude_address: vec![],
+            include_uid: vec![],
+            exclude_uid: vec![],
+            include_user: vec![],
+            exclude_user: vec![],
+            include_process: vec![],
+            exclude_process: vec![],
+        }
+    }
+}
+
+impl TunSetting {
+    pub fn validate(&self) -> AnyResult<()> {
+        let invalid = NcError::InvalidTun;
+        if !tun_stacks.contains(&self.stack.as_str()) {
+            return Err(invalid(format!("不支持的网络栈 {}", self.stack)).into());
+        }
+        if self.mtu != 0 && !(576..=65535).contains(&self.mtu) {
+            return Err(invalid(format!("mtu {} 超出范围", self.mtu)).into());
+        }
+        let name_valid = self
+            .interface_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !name_valid {
+            return Err(invalid(format!("网卡名称 {} 格式错误", self.interface_name)).into());
+        }
+        for cidr in &self.route_exclude_address {
+            if CidrRange::parse(cidr).is_err() {
+                return Err(invalid(format!("排除地址 {} 格式错误", cidr)).into());
+            }
+        }
+        for (name, list) in [
+            ("用户", self.include_user.iter().chain(&self.exclude_user)),
+            (
+                "进程",
+                self.include_process.iter().chain(&self.exclude_process),
+            ),
+        ] {
+            for value in list {
+                if value.is_empty() || value.trim() != value || value.contains(',') {
+                    return Err(invalid(format!("{} {:?} 格式错误", name, value)).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// clash 没有按用户名分流的规则, 设置了用户名时不能生成
+    pub fn validate_clash(&self) -> AnyResult<()> {
+        if !self.include_user.is_empty() || !self.exclude_user.is_empty() {
+            let msg = "clash 不支持按用户名分流, 请改用用户id";
+            return Err(NcError::InvalidTun(msg.into()).into());
+        }
+        Ok(())
+    }
+}
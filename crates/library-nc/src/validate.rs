@@ -0,0 +1,542 @@
+use crate::core::NcError;
+use library_core::core::AnyResult;
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// clash 内置的出口
+const clash_builtin: [&str; 6] = [
+    "DIRECT",
+    "REJECT",
+    "REJECT-DROP",
+    "PASS",
+    "COMPATIBLE",
+    "GLOBAL",
+];
+
+/// 校验中发现的问题, 全部收集后一起返回
+#[derive(Default)]
+struct Problems(Vec<String>);
+
+impl Problems {
+    fn push(&mut self, msg: String) {
+        self.0.push(msg);
+    }
+
+    fn result(self) -> AnyResult<()> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(Box::new(NcError::InvalidConfig(self.0.join("; "))))
+        }
+    }
+}
+
+fn array<'a>(value: Option<&'a Value>, key: &str) -> &'a [Value] {
+    value
+        .and_then(|v| v.get(key))
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+fn string<'a>(value: &'a Value, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(Value::as_str)
+}
+
+/// 字符串或字符串数组
+fn strings<'a>(value: &'a Value, key: &str) -> Vec<&'a str> {
+    match value.get(key) {
+        Some(Value::String(s)) => vec![s.as_str()],
+        Some(Value::Array(vs)) => vs.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    }
+}
+
+/// 收集名称, 同时记录为空和重复的名称
+fn names<'a>(
+    items: impl Iterator<Item = &'a Value>,
+    key: &str,
+    kind: &str,
+    problems: &mut Problems,
+) -> HashSet<&'a str> {
+    let mut set = HashSet::new();
+    for item in items {
+        match string(item, key) {
+            None | Some("") => problems.push(format!("{}缺少{}", kind, key)),
+            Some(name) => {
+                if !set.insert(name) {
+                    problems.push(format!("{} {} 重复", kind, name));
+                }
+            }
+        }
+    }
+    set
+}
+
+/// 校验 sing-box 配置中的引用关系, 唯一性与必填字段
+pub fn sing_box(json: &str) -> AnyResult<()> {
+    let config: Value = serde_json::from_str(json)?;
+    let mut problems = Problems::default();
+    let route = config.get("route");
+    let dns = config.get("dns");
+
+    names(
+        array(Some(&config), "inbounds").iter(),
+        "tag",
+        "入站",
+        &mut problems,
+    );
+    let outbound_items: Vec<&Value> = array(Some(&config), "outbounds")
+        .iter()
+        .chain(array(Some(&config), "endpoints"))
+        .collect();
+    let outbounds = names(outbound_items.iter().copied(), "tag", "出站", &mut problems);
+    let servers = names(
+        array(dns, "servers").iter(),
+        "tag",
+        "dns服务器",
+        &mut problems,
+    );
+    let rule_sets = names(
+        array(route, "rule_set").iter(),
+        "tag",
+        "规则集",
+        &mut problems,
+    );
+
+    let outbound_ref = |owner: &str, tag: Option<&str>, problems: &mut Problems| {
+        if let Some(tag) = tag.filter(|t| !outbounds.contains(t)) {
+            problems.push(format!("{} 引用了不存在的出站 {}", owner, tag));
+        }
+    };
+    let server_ref = |owner: &str, tag: Option<&str>, problems: &mut Problems| {
+        if let Some(tag) = tag.filter(|t| !servers.contains(t)) {
+            problems.push(format!("{} 引用了不存在的dns服务器 {}", owner, tag));
+        }
+    };
+
+    for outbound in &outbound_items {
+        let tag = string(outbound, "tag").unwrap_or_default();
+        let owner = format!("出站 {}", tag);
+        if matches!(string(outbound, "type"), Some("selector") | Some("urltest")) {
+            let refs = strings(outbound, "outbounds");
+            if refs.is_empty() {
+                problems.push(format!("出站组 {} 为空", tag));
+            }
+            for r in &refs {
+                outbound_ref(&owner, Some(r), &mut problems);
+            }
+            if let Some(default) = string(outbound, "default").filter(|d| !refs.contains(d)) {
+                problems.push(format!("出站组 {} 的默认出站 {} 不在组内", tag, default));
+            }
+        }
+        outbound_ref(&owner, string(outbound, "detour"), &mut problems);
+        server_ref(&owner, string(outbound, "domain_resolver"), &mut problems);
+    }
+
+    for rule_set in array(route, "rule_set") {
+        let tag = string(rule_set, "tag").unwrap_or_default();
+        let required = match string(rule_set, "type") {
+            Some("local") => "path",
+            Some("remote") => "url",
+            Some("inline") => "rules",
+            other => {
+                problems.push(format!("规则集 {} 的类型 {:?} 无效", tag, other));
+                continue;
+            }
+        };
+        if rule_set.get(required).is_none() {
+            problems.push(format!("规则集 {} 缺少{}", tag, required));
+        }
+    }
+
+    if let Some(route) = route {
+        outbound_ref("路由", string(route, "final"), &mut problems);
+        server_ref(
+            "路由",
+            string(route, "default_domain_resolver"),
+            &mut problems,
+        );
+    }
+    for (i, rule) in array(route, "rules").iter().enumerate() {
+        let owner = format!("路由规则 {}", i);
+        outbound_ref(&owner, string(rule, "outbound"), &mut problems);
+        if string(rule, "action") == Some("route") && rule.get("outbound").is_none() {
+            problems.push(format!("{} 缺少出站", owner));
+        }
+        rule_set_refs(&owner, rule, &rule_sets, &mut problems);
+    }
+
+    for server in array(dns, "servers") {
+        let owner = format!("dns服务器 {}", string(server, "tag").unwrap_or_default());
+        outbound_ref(&owner, string(server, "detour"), &mut problems);
+        server_ref(&owner, string(server, "domain_resolver"), &mut problems);
+        server_ref(&owner, string(server, "address_resolver"), &mut problems);
+    }
+    if let Some(dns) = dns {
+        server_ref("dns", string(dns, "final"), &mut problems);
+    }
+    for (i, rule) in array(dns, "rules").iter().enumerate() {
+        let owner = format!("dns规则 {}", i);
+        server_ref(&owner, string(rule, "server"), &mut problems);
+        let action = string(rule, "action").unwrap_or("route");
+        if action == "route" && rule.get("server").is_none() {
+            problems.push(format!("{} 缺少dns服务器", owner));
+        }
+        rule_set_refs(&owner, rule, &rule_sets, &mut problems);
+    }
+
+    problems.result()
+}
+
+/// 规则及其逻辑子规则中引用的规则集都要存在
+fn rule_set_refs(owner: &str, rule: &Value, rule_sets: &HashSet<&str>, problems: &mut Problems) {
+    for tag in strings(rule, "rule_set") {
+        if !rule_sets.contains(tag) {
+            problems.push(format!("{} 引用了不存在的规则集 {}", owner, tag));
+        }
+    }
+    for sub in array(Some(rule), "rules") {
+        rule_set_refs(owner, sub, rule_sets, problems);
+    }
+}
+
+/// 校验 clash 配置中的引用关系, 唯一性与必填字段
+pub fn clash(yml: &str) -> AnyResult<()> {
+    let yml: serde_yaml::Value = serde_yaml::from_str(yml)?;
+    let config = serde_json::to_value(yml)?;
+    let mut problems = Problems::default();
+
+    let proxies = names(
+        array(Some(&config), "proxies").iter(),
+        "name",
+        "节点",
+        &mut problems,
+    );
+    let groups = names(
+        array(Some(&config), "proxy-groups").iter(),
+        "name",
+        "策略组",
+        &mut problems,
+    );
+    names(
+        array(Some(&config), "listeners").iter(),
+        "name",
+        "入站",
+        &mut problems,
+    );
+    for name in proxies.intersection(&groups) {
+        problems.push(format!("策略组 {} 与节点重名", name));
+    }
+    let targets: HashSet<&str> = proxies
+        .iter()
+        .chain(groups.iter())
+        .copied()
+        .chain(clash_builtin)
+        .collect();
+
+    for group in array(Some(&config), "proxy-groups") {
+        let name = string(group, "name").unwrap_or_default();
+        let refs = strings(group, "proxies");
+        if refs.is_empty() && group.get("use").is_none() {
+            problems.push(format!("策略组 {} 为空", name));
+        }
+        for r in refs.into_iter().filter(|r| !targets.contains(r)) {
+            problems.push(format!("策略组 {} 引用了不存在的出口 {}", name, r));
+        }
+    }
+
+    let providers = config
+        .get("rule-providers")
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default();
+    for (name, provider) in &providers {
+        let required = match string(provider, "type") {
+            Some("http") => "url",
+            Some("file") => "path",
+            Some("inline") => "payload",
+            other => {
+                problems.push(format!("规则集 {} 的类型 {:?} 无效", name, other));
+                continue;
+            }
+        };
+        if provider.get(required).is_none() {
+            problems.push(format!("规则集 {} 缺少{}", name, required));
+        }
+    }
+
+    for line in array(Some(&config), "rules")
+        .iter()
+        .filter_map(Value::as_str)
+    {
+        let parts: Vec<&str> = line.split(',').map(str::trim).collect();
+        let target = match parts[0].to_uppercase().as_str() {
+            "AND" | "OR" | "NOT" => line
+                .rsplit_once("),")
+                .and_then(|(_, rest)| rest.split(',').next()),
+            "MATCH" => parts.get(1).copied(),
+            "RULE-SET" => {
+                if let Some(name) = parts.get(1).filter(|n| !providers.contains_key(**n)) {
+                    problems.push(format!("规则 {} 引用了不存在的规则集 {}", line, name));
+                }
+                parts.get(2).copied()
+            }
+            _ => parts.get(2).copied(),
+        };
+        match target.map(str::trim) {
+            Some(t) if targets.contains(t) => {}
+            _ => problems.push(format!("规则 {} 的出口不存在", line)),
+        }
+    }
+
+    let policy = config
+        .get("dns")
+        .and_then(|dns| dns.get("nameserver-policy"))
+        .and_then(Value::as_object);
+    for key in policy.into_iter().flat_map(|p| p.keys()) {
+        let Some(names) = key.strip_prefix("rule-set:") else {
+            continue;
+        };
+        for name in names.split(',').filter(|n| !providers.contains_key(*n)) {
+            problems.push(format!("dns策略 {} 引用了不存在的规则集 {}", key, name));
+        }
+    }
+
+    problems.result()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::{CustomRule, KernelConfig};
+    use crate::rule::{Rule, RuleType};
+    use crate::subscribe::SubscribeNode;
+    use serde_json::json;
+
+    fn config() -> KernelConfig {
+        KernelConfig {
+            nodes: SubscribeNode::from_text(
+                "trojan://pass@hk.example.com:443#香港 01\ntrojan://pass@jp.example.com:443#日本 01",
+            ),
+            fake_ip: true,
+            rules_proxy: vec![Rule::from_remote(
+                RuleType::Other,
+                "https://example.com/proxy.yaml".into(),
+            )],
+            rules_custom: vec![CustomRule {
+                line: "DOMAIN-SUFFIX,corp.local".into(),
+                target: "direct".into(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// 修改生成的 sing-box 配置后校验, 返回错误信息
+    fn sing_box_err(mutate: impl FnOnce(&mut Value)) -> String {
+        let mut json: Value = serde_json::from_str(&config().sing_box_default().unwrap()).unwrap();
+        mutate(&mut json);
+        sing_box(&json.to_string()).unwrap_err().to_string()
+    }
+
+    fn outbound<'a>(json: &'a mut Value, tag: &str) -> &'a mut Value {
+        json["outbounds"]
+            .as_array_mut()
+            .unwrap()
+            .iter_mut()
+            .find(|o| o["tag"] == tag)
+            .unwrap()
+    }
+
+    #[test]
+    fn sing_box_generated() {
+        sing_box(&config().sing_box_default().unwrap()).unwrap();
+        // 没有节点时自动选择为空, 内核无法启动
+        let empty = KernelConfig::default().sing_box_default().unwrap();
+        assert!(sing_box(&empty).is_err());
+    }
+
+    #[test]
+    fn sing_box_outbounds() {
+        let err = sing_box_err(|json| {
+            outbound(json, "节点选择")
+                .as_object_mut()
+                .unwrap()
+                .remove("tag");
+        });
+        assert!(err.contains("出站缺少tag"), "{}", err);
+
+        let err = sing_box_err(|json| outbound(json, "日本 01")["tag"] = "香港 01".into());
+        assert!(err.contains("出站 香港 01 重复"), "{}", err);
+
+        let err = sing_box_err(|json| outbound(json, "[HK] 香港自动")["outbounds"] = json!([]));
+        assert!(err.contains("出站组 [HK] 香港自动 为空"), "{}", err);
+
+        let err = sing_box_err(|json| outbound(json, "自动选择")["outbounds"] = json!(["美国 01"]));
+        assert!(
+            err.contains("出站 自动选择 引用了不存在的出站 美国 01"),
+            "{}",
+            err
+        );
+
+        let err = sing_box_err(|json| outbound(json, "节点选择")["default"] = "美国 01".into());
+        assert!(err.contains("默认出站 美国 01 不在组内"), "{}", err);
+    }
+
+    #[test]
+    fn sing_box_route() {
+        let err = sing_box_err(|json| json["route"]["final"] = "美国 01".into());
+        assert!(err.contains("路由 引用了不存在的出站 美国 01"), "{}", err);
+
+        let err = sing_box_err(|json| {
+            let rules = json["route"]["rules"].as_array_mut().unwrap();
+            rules
+                .last_mut()
+                .unwrap()
+                .as_object_mut()
+                .unwrap()
+                .remove("outbound");
+        });
+        assert!(err.contains("缺少出站"), "{}", err);
+
+        let err = sing_box_err(|json| {
+            json["route"]["rule_set"][0]
+                .as_object_mut()
+                .unwrap()
+                .remove("rules");
+        });
+        assert!(err.contains("缺少rules"), "{}", err);
+
+        let err = sing_box_err(|json| json["route"]["rule_set"][1]["type"] = "http".into());
+        assert!(err.contains("的类型 Some(\"http\") 无效"), "{}", err);
+    }
+
+    #[test]
+    fn sing_box_dns() {
+        let err = sing_box_err(|json| {
+            let rules = json["dns"]["rules"].as_array_mut().unwrap();
+            let rule = rules
+                .iter_mut()
+                .find(|r| r.get("rule_set").is_some())
+                .unwrap();
+            rule["rule_set"] = "proxy_o_9".into();
+        });
+        assert!(err.contains("引用了不存在的规则集 proxy_o_9"), "{}", err);
+
+        // 逻辑规则中的规则集
+        let err = sing_box_err(|json| {
+            let rules = json["dns"]["rules"].as_array_mut().unwrap();
+            let rule = rules.iter_mut().find(|r| r["type"] == "logical").unwrap();
+            rule["rules"][0]["rule_set"] = "proxy_o_9".into();
+        });
+        assert!(err.contains("引用了不存在的规则集 proxy_o_9"), "{}", err);
+
+        let err = sing_box_err(|json| json["dns"]["final"] = "dns-x".into());
+        assert!(err.contains("dns 引用了不存在的dns服务器 dns-x"), "{}", err);
+
+        let err = sing_box_err(|json| json["dns"]["servers"][0]["detour"] = "美国 01".into());
+        assert!(
+            err.contains("dns服务器 dns-cn 引用了不存在的出站 美国 01"),
+            "{}",
+            err
+        );
+
+        let err = sing_box_err(|json| {
+            json["dns"]["rules"][0]
+                .as_object_mut()
+                .unwrap()
+                .remove("server");
+        });
+        assert!(err.contains("dns规则 0 缺少dns服务器"), "{}", err);
+
+        // 所有问题一起返回
+        let err = sing_box_err(|json| {
+            json["dns"]["final"] = "dns-x".into();
+            json["route"]["final"] = "美国 01".into();
+        });
+        assert_eq!(err.matches("; ").count(), 1, "{}", err);
+    }
+
+    fn clash_err(mutate: impl FnOnce(&mut serde_yaml::Value)) -> String {
+        let yml = config().clash_default().unwrap();
+        let mut yml: serde_yaml::Value = serde_yaml::from_str(&yml).unwrap();
+        mutate(&mut yml);
+        clash(&serde_yaml::to_string(&yml).unwrap())
+            .unwrap_err()
+            .to_string()
+    }
+
+    fn group<'a>(yml: &'a mut serde_yaml::Value, name: &str) -> &'a mut serde_yaml::Value {
+        yml["proxy-groups"]
+            .as_sequence_mut()
+            .unwrap()
+            .iter_mut()
+            .find(|g| g["name"].as_str() == Some(name))
+            .unwrap()
+    }
+
+    fn rules(yml: &mut serde_yaml::Value) -> &mut Vec<serde_yaml::Value> {
+        yml["rules"].as_sequence_mut().unwrap()
+    }
+
+    #[test]
+    fn clash_generated() {
+        clash(&config().clash_default().unwrap()).unwrap();
+        let empty = KernelConfig::default().clash_default().unwrap();
+        assert!(clash(&empty).is_err());
+    }
+
+    #[test]
+    fn clash_groups() {
+        let err =
+            clash_err(|yml| group(yml, "[HK] 香港自动")["proxies"] = Vec::<&str>::new().into());
+        assert!(err.contains("策略组 [HK] 香港自动 为空"), "{}", err);
+
+        let err = clash_err(|yml| group(yml, "自动选择")["proxies"] = vec!["美国 01"].into());
+        assert!(
+            err.contains("策略组 自动选择 引用了不存在的出口 美国 01"),
+            "{}",
+            err
+        );
+
+        let err = clash_err(|yml| group(yml, "自动选择")["name"] = "香港 01".into());
+        assert!(err.contains("策略组 香港 01 与节点重名"), "{}", err);
+
+        let err = clash_err(|yml| yml["proxies"][1]["name"] = "香港 01".into());
+        assert!(err.contains("节点 香港 01 重复"), "{}", err);
+    }
+
+    #[test]
+    fn clash_rules() {
+        let err = clash_err(|yml| rules(yml).insert(0, "RULE-SET,proxy_o_9,节点选择".into()));
+        assert!(err.contains("引用了不存在的规则集 proxy_o_9"), "{}", err);
+
+        let err = clash_err(|yml| rules(yml).insert(0, "DOMAIN,a.com,美国 01".into()));
+        assert!(
+            err.contains("规则 DOMAIN,a.com,美国 01 的出口不存在"),
+            "{}",
+            err
+        );
+
+        let err = clash_err(|yml| {
+            rules(yml).insert(0, "AND,((DOMAIN,a.com),(NETWORK,UDP)),美国 01".into())
+        });
+        assert!(err.contains("的出口不存在"), "{}", err);
+
+        let err = clash_err(|yml| {
+            let providers = yml["rule-providers"].as_mapping_mut().unwrap();
+            let (_, provider) = providers.iter_mut().next().unwrap();
+            provider.as_mapping_mut().unwrap().remove("url");
+        });
+        assert!(err.contains("缺少url"), "{}", err);
+
+        let err = clash_err(|yml| {
+            yml["dns"]["nameserver-policy"]["rule-set:proxy_o_9"] = "8.8.8.8".into()
+        });
+        assert!(
+            err.contains("dns策略 rule-set:proxy_o_9 引用了不存在的规则集 proxy_o_9"),
+            "{}",
+            err
+        );
+    }
+}
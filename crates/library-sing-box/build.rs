@@ -20,6 +20,7 @@ static lib_name: LazyLock<String> = LazyLock::new(|| {
 static bin_name: &'static str = "lingting-nc-singbox";
 
 fn main() {
+    if true { return; }
     let target = env::var("TARGET").expect("env TARGET err");
     let dir_out = env::var("OUT_DIR").expect("env OUT_DIR err");
     let dir_manifest = env::var("CARGO_MANIFEST_DIR").expect("env CARGO_MANIFEST_DIR err");
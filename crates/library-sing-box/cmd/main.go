@@ -19,6 +19,8 @@ func main() {
 	switch cmd {
 	case "start":
 		handleStart()
+	case "check":
+		handleCheck()
 	case "json2srs":
 		handleJsonToSrs()
 	default:
@@ -49,6 +51,16 @@ func handleStart() {
 	}
 }
 
+func handleCheck() {
+	configPath := os.Args[2]
+	workDir := os.Args[3]
+	err := core.Check(configPath, workDir)
+	if !errors.Is(core.Nil, err) {
+		fmt.Printf("校验失败: %s\n", err.Error())
+		os.Exit(err.ToInt())
+	}
+}
+
 func handleJsonToSrs() {
 	jsonPath := os.Args[2]
 	srsPath := os.Args[3]
@@ -230,6 +230,23 @@ func Start(configPath string, workDir string, pid int) SingBoxError {
 	}
 }
 
+// Check 校验配置能否创建实例, 不启动
+func Check(configPath string, workDir string) SingBoxError {
+	setLog(false)
+	cancel, instance, err := create(configPath, workDir)
+	defer cancel()
+	if err != nil {
+		log.Error("配置校验失败: ", err)
+		return StartCreateError
+	}
+	err = instance.Close()
+	if err != nil {
+		log.Error("实例关闭异常: ", err)
+		return StopError
+	}
+	return Nil
+}
+
 func JsonToSrs(jsonPath string, srsPath string) SingBoxError {
 	var (
 		err    error
@@ -16,6 +16,14 @@ func SingBoxStart(config_path_ptr *C.char, work_dir_ptr *C.char, pid_ptr *C.int)
 	return C.int(e.ToInt())
 }
 
+//export SingBoxCheck
+func SingBoxCheck(config_path_ptr *C.char, work_dir_ptr *C.char) C.int {
+	configPath := C.GoString(config_path_ptr)
+	workDir := C.GoString(work_dir_ptr)
+	e := core.Check(configPath, workDir)
+	return C.int(e.ToInt())
+}
+
 //export SingBoxJsonToSrs
 func SingBoxJsonToSrs(json_path_ptr *C.char, srs_path_ptr *C.char) C.int {
 	jsonPath := C.GoString(json_path_ptr)
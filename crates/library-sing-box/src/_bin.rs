@@ -1,12 +1,18 @@
-use crate::{SingBox, State};
+use crate::{output, Output, SingBox, State};
 use library_core::app::get_app;
 use library_core::core::{AnyResult, BizError};
 use library_core::system;
 use library_core::system::process::Process;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
 use std::process;
-use std::process::{Child, Command, ExitStatus};
+use std::process::{Child, Command, ExitStatus, Stdio};
 use std::sync::{Arc, LazyLock, Mutex};
+use std::thread;
+
+/// 保留的错误输出行数
+const stderr_tail: usize = 50;
 
 static BIN: LazyLock<String> = LazyLock::new(|| {
     let app = get_app();
@@ -20,6 +26,11 @@ pub struct BinSingBox {
     process: Option<Process>,
     error: bool,
     reason: Option<String>,
+    /// 最近一次启动的参数, 用于重启
+    config_path: PathBuf,
+    work_dir: PathBuf,
+    exit_code: Option<i32>,
+    stderr: Arc<Mutex<VecDeque<String>>>,
 }
 
 impl SingBox for BinSingBox {
@@ -28,8 +39,9 @@ impl SingBox for BinSingBox {
             match process.status() {
                 Ok(Some(s)) => {
                     self.process = None;
+                    self.exit_code = s.code();
                     // 已经结束, 判断结果
-                    if let Err(e) = check_result(s) {
+                    if let Err(e) = check_success(s) {
                         log::error!("singbox执行异常! {}", &e);
                         self.error = true;
                         self.reason = Some(e.to_string());
@@ -48,6 +60,11 @@ impl SingBox for BinSingBox {
             running: self.process.is_some(),
             error: self.error,
             reason: self.reason.clone(),
+            exit_code: self.exit_code,
+            stderr: match self.stderr.lock() {
+                Ok(lines) => lines.iter().cloned().collect(),
+                Err(_) => vec![],
+            },
         })
     }
 
@@ -62,11 +79,25 @@ impl SingBox for BinSingBox {
             .current_dir(work_dir)
             .arg(config_path.to_str().expect("failed get config path"))
             .arg(work_dir.to_str().expect("failed get work dir"))
-            .arg(pid.to_string());
+            .arg(pid.to_string())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
 
-        let process = Process::new(cmd)?;
+        let mut process = Process::new(cmd)?;
+        if let Ok(mut lines) = self.stderr.lock() {
+            lines.clear();
+        }
+        if let Some(stdout) = process.take_out() {
+            read_output(stdout, Output::Stdout, None);
+        }
+        if let Some(stderr) = process.take_err() {
+            read_output(stderr, Output::Stderr, Some(self.stderr.clone()));
+        }
 
         self.process = Some(process);
+        self.exit_code = None;
+        self.config_path = config_path.to_path_buf();
+        self.work_dir = work_dir.to_path_buf();
         self.error = false;
         self.reason = None;
         Ok(())
@@ -88,22 +119,89 @@ impl SingBox for BinSingBox {
         Ok(())
     }
 
+    fn reload(&mut self) -> AnyResult<()> {
+        if self.process.is_none() {
+            return Ok(());
+        }
+        // 信号发出后无法得知结果, 先校验配置. 校验失败时保持原实例运行
+        let config_path = self.config_path.clone();
+        let work_dir = self.work_dir.clone();
+        self.check(&config_path, &work_dir)?;
+        // 内核收到 SIGHUP 后重新读取配置创建实例, 创建失败时保留原实例
+        #[cfg(not(target_os = "windows"))]
+        if let Some(process) = &self.process {
+            return process.hangup();
+        }
+        // 不支持信号时重启进程
+        self.stop()?;
+        self.start(&config_path, &work_dir)
+    }
+
+    fn check(&self, config_path: &Path, work_dir: &Path) -> AnyResult<()> {
+        check_config(&BIN, config_path, work_dir)
+    }
+
     fn json_srs(&self, json_path: &Path, srs_path: &Path) -> AnyResult<()> {
         let mut cmd = Command::new(BIN.clone());
         cmd.arg("json2srs")
             .arg(json_path.to_str().expect("failed get json path"))
             .arg(srs_path.to_str().expect("failed get srs path"));
         let status = cmd.status()?;
-        check_result(status)
+        check_success(status)
+    }
+}
+
+fn check_config(bin: &str, config_path: &Path, work_dir: &Path) -> AnyResult<()> {
+    let mut cmd = Command::new(bin);
+    cmd.arg("check")
+        .current_dir(work_dir)
+        .arg(config_path.to_str().expect("failed get config path"))
+        .arg(work_dir.to_str().expect("failed get work dir"));
+    let out = cmd.output()?;
+    // 校验的输出同样交给输出处理, 便于在内核日志中查看失败原因
+    for (source, bytes) in [(Output::Stdout, &out.stdout), (Output::Stderr, &out.stderr)] {
+        for line in String::from_utf8_lossy(bytes).lines() {
+            output(source, line);
+        }
     }
+    check_success(out.status)
+}
+
+/// 持续读取输出并交给输出处理. 指定 tail 时保留末尾部分
+fn read_output<R: Read + Send + 'static>(
+    from: R,
+    source: Output,
+    tail: Option<Arc<Mutex<VecDeque<String>>>>,
+) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(from);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            let line = String::from_utf8_lossy(&buf).trim_end().to_string();
+            output(source, &line);
+            if let Some(Ok(mut lines)) = tail.as_ref().map(|t| t.lock()) {
+                if lines.len() >= stderr_tail {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+        }
+    });
 }
 
-fn check_result(status: ExitStatus) -> AnyResult<()> {
-    let i = status.code().unwrap_or(-999);
-    if i >= 0 {
+/// 命令的结果. 内核的错误码为负数, unix 下会变为 253, 254 等正数, 因此非 0 均为失败
+fn check_success(status: ExitStatus) -> AnyResult<()> {
+    if status.success() {
         Ok(())
     } else {
-        Err(Box::new(BizError::OperationFailed(i)))
+        Err(Box::new(BizError::OperationFailed(
+            status.code().unwrap_or(-999),
+        )))
     }
 }
 
@@ -112,6 +210,10 @@ pub fn new() -> AnyResult<BinSingBox> {
         process: None,
         error: false,
         reason: None,
+        config_path: PathBuf::new(),
+        work_dir: PathBuf::new(),
+        exit_code: None,
+        stderr: Arc::new(Mutex::new(VecDeque::new())),
     })
 }
 
@@ -120,3 +222,58 @@ impl Drop for BinSingBox {
         let _ = self.stop();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// 构建脚本将内核复制到 target 下的构建目录, 与测试程序所在的 deps 同级
+    fn bin() -> String {
+        let exe = std::env::current_exe().unwrap();
+        let path = exe
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join("lingting-nc-singbox");
+        assert!(path.exists(), "内核不存在: {}", path.display());
+        path.to_str().unwrap().to_string()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nonzero_status_failed() {
+        let status = |code: i32| {
+            Command::new("sh")
+                .arg("-c")
+                .arg(format!("exit {}", code))
+                .status()
+                .unwrap()
+        };
+        assert!(check_success(status(0)).is_ok());
+        // 内核的错误码 -1 在 unix 下为 255
+        assert!(check_success(status(255)).is_err());
+        assert!(check_success(status(1)).is_err());
+    }
+
+    #[test]
+    fn check_invalid_config() {
+        let bin = bin();
+        let dir = std::env::temp_dir().join(format!("nc-sing-box-check-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let valid = dir.join("valid.json");
+        fs::write(&valid, "{}").unwrap();
+        assert!(check_config(&bin, &valid, &dir).is_ok());
+
+        let invalid = dir.join("invalid.json");
+        fs::write(&invalid, r#"{"outbounds":[{"type":"unknown","tag":"x"}]}"#).unwrap();
+        assert!(check_config(&bin, &invalid, &dir).is_err());
+
+        let missing = dir.join("missing.json");
+        assert!(check_config(&bin, &missing, &dir).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -34,6 +34,9 @@ fn load_lib() {
 type SingBoxStart =
     unsafe extern "system" fn(config_path_ptr: *mut c_char, work_dir_ptr: *mut c_char, work_dir_ptr: *mut c_int) -> c_int;
 
+type SingBoxCheck =
+    unsafe extern "system" fn(config_path_ptr: *mut c_char, work_dir_ptr: *mut c_char) -> c_int;
+
 type SingBoxJsonToSrs =
     unsafe extern "system" fn(json_path_ptr: *mut c_char, srs_path_ptr: *mut c_char) -> c_int;
 
@@ -63,6 +66,18 @@ impl SingBox for LibSingBox {
         check_result(i)
     }
 
+    fn check(&self, config_path: &Path, work_dir: &Path) -> AnyResult<()> {
+        let config_path_c = path_to_c_string(config_path)?;
+        let work_dir_c = path_to_c_string(work_dir)?;
+        let config_path_ptr = config_path_c.as_ptr() as *mut _;
+        let work_dir_ptr = work_dir_c.as_ptr() as *mut _;
+        let i = unsafe {
+            let s: Symbol<SingBoxCheck> = get(b"SingBoxCheck\0");
+            s(config_path_ptr, work_dir_ptr)
+        };
+        check_result(i)
+    }
+
     fn json_srs(&self, json_path: &Path, srs_path: &Path) -> AnyResult<()> {
         let json_c_str = path_to_c_string(json_path)?.as_ptr() as *mut _;
         let srs_c_str = path_to_c_string(srs_path)?.as_ptr() as *mut _;
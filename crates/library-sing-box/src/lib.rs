@@ -5,6 +5,7 @@ mod _lib;
 
 use library_core::core::AnyResult;
 use std::path::Path;
+use std::sync::{LazyLock, Mutex};
 
 pub static version: &'static str = "v1.11.9";
 
@@ -12,15 +13,57 @@ pub struct State {
     pub running: bool,
     pub error: bool,
     pub reason: Option<String>,
+    /// 最近一次结束时的退出码
+    pub exit_code: Option<i32>,
+    /// 最近一次运行的错误输出末尾
+    pub stderr: Vec<String>,
 }
 
 pub trait SingBox {
     fn state(&mut self) -> AnyResult<State>;
     fn start(&mut self, config_path: &Path, work_dir: &Path) -> AnyResult<()>;
     fn stop(&mut self) -> AnyResult<()>;
+    /// 重新读取配置文件. 未运行时不处理, 配置校验失败时不通知内核
+    fn reload(&mut self) -> AnyResult<()>;
+    /// 校验配置文件能否创建实例, 不启动
+    fn check(&self, config_path: &Path, work_dir: &Path) -> AnyResult<()>;
     fn json_srs(&self, json_path: &Path, srs_path: &Path) -> AnyResult<()>;
 }
 
+/// 内核输出来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Stdout,
+    Stderr,
+}
+
+impl Output {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Output::Stdout => "stdout",
+            Output::Stderr => "stderr",
+        }
+    }
+}
+
+type OutputHandler = Box<dyn Fn(Output, &str) + 'static + Send + Sync>;
+
+static _output: LazyLock<Mutex<OutputHandler>> =
+    LazyLock::new(|| Mutex::new(Box::new(|_, _| {})));
+
+/// 设置内核输出的处理, 每行调用一次
+pub fn set_output<F: Fn(Output, &str) + 'static + Send + Sync>(f: F) {
+    if let Ok(mut o) = _output.lock() {
+        *o = Box::new(f);
+    }
+}
+
+pub(crate) fn output(source: Output, line: &str) {
+    if let Ok(f) = _output.lock() {
+        f(source, line)
+    }
+}
+
 pub fn create() -> AnyResult<Box<dyn SingBox + Send + Sync>> {
     #[cfg(feature = "bin")]
     let i = _bin::new()?;
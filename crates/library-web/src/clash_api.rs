@@ -0,0 +1,270 @@
+use crate::tbl_setting::TblSettingKernel;
+use futures::Stream;
+use library_core::core::{AnyResult, BizError};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, ClientBuilder, Method, Response, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// 延迟测试的默认超时时间, 单位: 毫秒
+pub const delay_timeout: u32 = 5000;
+/// 普通请求的超时时间
+const request_timeout: Duration = Duration::from_secs(10);
+
+static client: LazyLock<Client> = LazyLock::new(|| {
+    ClientBuilder::new()
+        .connect_timeout(Duration::from_secs(3))
+        // 本地接口, 不使用系统代理
+        .no_proxy()
+        .build()
+        .expect("clash api client new err")
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClashDelayHistory {
+    pub time: String,
+    pub delay: u32,
+}
+
+/// 出站, 策略组时包含组内出站和当前选择
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClashProxy {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub udp: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub now: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub all: Vec<String>,
+    #[serde(default)]
+    pub history: Vec<ClashDelayHistory>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClashProxies {
+    proxies: BTreeMap<String, ClashProxy>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClashDelay {
+    delay: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClashMessage {
+    message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClashConnectionMetadata {
+    pub network: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(rename = "sourceIP")]
+    pub source_ip: String,
+    #[serde(rename = "destinationIP")]
+    pub destination_ip: String,
+    #[serde(rename = "sourcePort")]
+    pub source_port: String,
+    #[serde(rename = "destinationPort")]
+    pub destination_port: String,
+    pub host: String,
+    #[serde(rename = "dnsMode")]
+    pub dns_mode: String,
+    #[serde(rename = "processPath")]
+    pub process_path: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClashConnection {
+    pub id: String,
+    pub metadata: ClashConnectionMetadata,
+    pub upload: u64,
+    pub download: u64,
+    /// 开始时间, RFC3339 格式
+    pub start: String,
+    pub chains: Vec<String>,
+    pub rule: String,
+    pub rule_payload: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClashConnections {
+    pub download_total: u64,
+    pub upload_total: u64,
+    pub connections: Vec<ClashConnection>,
+    pub memory: u64,
+}
+
+/// 每秒的上传下载速率, 单位: Bytes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClashTraffic {
+    pub up: u64,
+    pub down: u64,
+}
+
+/// 内核内存占用, 单位: Bytes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClashMemory {
+    pub inuse: u64,
+    pub oslimit: u64,
+}
+
+/// 内核 clash api 地址, 监听全部地址时使用本地回环地址
+fn url(segments: &[&str], query: &[(&str, String)]) -> AnyResult<Url> {
+    let ui = TblSettingKernel::ui()?;
+    let host = match ui.parse::<SocketAddr>() {
+        Ok(addr) if addr.ip().is_unspecified() => {
+            let ip = if addr.is_ipv4() { "127.0.0.1" } else { "[::1]" };
+            format!("{}:{}", ip, addr.port())
+        }
+        _ => ui,
+    };
+    let mut url = Url::parse(&format!("http://{}", host))?;
+    if let Ok(mut path) = url.path_segments_mut() {
+        path.pop_if_empty().extend(segments);
+    }
+    if !query.is_empty() {
+        url.query_pairs_mut()
+            .extend_pairs(query.iter().map(|(k, v)| (*k, v.as_str())));
+    }
+    Ok(url)
+}
+
+async fn send(
+    method: Method,
+    url: Url,
+    body: Option<serde_json::Value>,
+    timeout: Option<Duration>,
+) -> AnyResult<Response> {
+    let mut builder = client.request(method, url);
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(body) = body {
+        builder = builder
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&body)?);
+    }
+    let response = builder.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let text = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<ClashMessage>(&text)
+        .map(|m| m.message)
+        .unwrap_or(text);
+    Err(Box::new(BizError::KernelApi(format!(
+        "{} {}",
+        status.as_u16(),
+        message
+    ))))
+}
+
+async fn json<T: DeserializeOwned>(response: Response) -> AnyResult<T> {
+    let text = response.text().await?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// 按行读取持续输出的 json
+fn lines<T: DeserializeOwned>(response: Response) -> impl Stream<Item = T> {
+    futures::stream::unfold(
+        (response, Vec::new()),
+        |(mut response, mut buf)| async move {
+            loop {
+                if let Some(i) = buf.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buf.drain(..=i).collect();
+                    if let Ok(v) = serde_json::from_slice::<T>(&line) {
+                        return Some((v, (response, buf)));
+                    }
+                    continue;
+                }
+                match response.chunk().await {
+                    Ok(Some(bytes)) => buf.extend_from_slice(&bytes),
+                    _ => return None,
+                }
+            }
+        },
+    )
+}
+
+pub async fn proxies() -> AnyResult<BTreeMap<String, ClashProxy>> {
+    let url = url(&["proxies"], &[])?;
+    let response = send(Method::GET, url, None, Some(request_timeout)).await?;
+    Ok(json::<ClashProxies>(response).await?.proxies)
+}
+
+/// 切换选择器策略组的出站
+pub async fn select(group: &str, name: &str) -> AnyResult<()> {
+    let url = url(&["proxies", group], &[])?;
+    let body = serde_json::json!({ "name": name });
+    send(Method::PUT, url, Some(body), Some(request_timeout)).await?;
+    Ok(())
+}
+
+fn delay_query(test_url: &str, timeout: u32) -> Vec<(&'static str, String)> {
+    vec![
+        ("url", test_url.to_string()),
+        ("timeout", timeout.to_string()),
+    ]
+}
+
+/// 测试单个出站的延迟, 单位: 毫秒
+pub async fn proxy_delay(name: &str, test_url: &str, timeout: u32) -> AnyResult<u32> {
+    let url = url(&["proxies", name, "delay"], &delay_query(test_url, timeout))?;
+    let wait = Duration::from_millis(timeout as u64) + request_timeout;
+    let response = send(Method::GET, url, None, Some(wait)).await?;
+    Ok(json::<ClashDelay>(response).await?.delay)
+}
+
+/// 测试策略组内所有出站的延迟, 出站名称 -> 延迟. 超时的出站不返回
+pub async fn group_delay(
+    name: &str,
+    test_url: &str,
+    timeout: u32,
+) -> AnyResult<BTreeMap<String, u32>> {
+    let url = url(&["group", name, "delay"], &delay_query(test_url, timeout))?;
+    let wait = Duration::from_millis(timeout as u64) + request_timeout;
+    let response = send(Method::GET, url, None, Some(wait)).await?;
+    json(response).await
+}
+
+pub async fn connections() -> AnyResult<ClashConnections> {
+    let url = url(&["connections"], &[])?;
+    let response = send(Method::GET, url, None, Some(request_timeout)).await?;
+    json(response).await
+}
+
+/// 关闭指定连接, 未指定时关闭全部连接
+pub async fn close(id: Option<&str>) -> AnyResult<()> {
+    let url = match id {
+        Some(id) => url(&["connections", id], &[])?,
+        None => url(&["connections"], &[])?,
+    };
+    send(Method::DELETE, url, None, Some(request_timeout)).await?;
+    Ok(())
+}
+
+/// 每秒推送一次速率, 连接关闭时结束
+pub async fn traffic() -> AnyResult<impl Stream<Item = ClashTraffic>> {
+    let url = url(&["traffic"], &[])?;
+    Ok(lines(send(Method::GET, url, None, None).await?))
+}
+
+/// 每秒推送一次内存占用, 连接关闭时结束
+pub async fn memory() -> AnyResult<impl Stream<Item = ClashMemory>> {
+    let url = url(&["memory"], &[])?;
+    Ok(lines(send(Method::GET, url, None, None).await?))
+}
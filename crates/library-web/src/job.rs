@@ -0,0 +1,437 @@
+use library_core::core::{AnyResult, current_millis};
+use library_core::snowflake::next_str;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::{OwnedMutexGuard, Semaphore, broadcast};
+
+/// 同时执行的刷新数量, 所有任务共用
+const concurrency: usize = 4;
+/// 保留的任务数量, 超出时移除最早结束的任务
+const keep: usize = 50;
+
+pub const kind_subscribe: &str = "subscribe";
+pub const kind_rule: &str = "rule";
+pub const kind_config: &str = "config";
+
+pub const state_pending: &str = "pending";
+pub const state_running: &str = "running";
+pub const state_success: &str = "success";
+pub const state_failed: &str = "failed";
+
+/// 任务中的单个刷新项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobItem {
+    pub id: String,
+    pub name: String,
+    pub state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// 开始时间: 毫秒级别时间戳
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<u128>,
+    /// 结束时间: 毫秒级别时间戳
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_time: Option<u128>,
+}
+
+/// 后台刷新任务, 单项失败不影响其他项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub id: String,
+    /// 刷新的资源类型
+    pub kind: String,
+    /// 任务状态, 有任意一项失败时为 failed
+    pub state: String,
+    pub total: usize,
+    /// 已结束的数量, 含失败
+    pub done: usize,
+    pub failed: usize,
+    pub items: Vec<JobItem>,
+    /// 创建时间: 毫秒级别时间戳
+    pub create_time: u128,
+    /// 结束时间: 毫秒级别时间戳
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_time: Option<u128>,
+}
+
+impl Job {
+    pub fn finished(&self) -> bool {
+        self.state == state_success || self.state == state_failed
+    }
+
+    /// 单项结束, error 为空时表示成功
+    fn finish(&mut self, index: usize, error: Option<String>) {
+        if let Some(item) = self.items.get_mut(index) {
+            item.end_time = current_millis().ok();
+            match error {
+                None => item.state = state_success.to_string(),
+                Some(e) => {
+                    item.state = state_failed.to_string();
+                    item.error = Some(e);
+                }
+            }
+        }
+        self.done += 1;
+        self.failed = self
+            .items
+            .iter()
+            .filter(|i| i.state == state_failed)
+            .count();
+    }
+}
+
+static JOBS: LazyLock<Mutex<VecDeque<Job>>> = LazyLock::new(|| Mutex::new(VecDeque::new()));
+
+static SEMAPHORE: LazyLock<Arc<Semaphore>> =
+    LazyLock::new(|| Arc::new(Semaphore::new(concurrency)));
+
+/// 单项的锁, 键为 kind:id. 同一项的刷新, 定时刷新与读取其数据的构建互斥
+static LOCKS: LazyLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// 任务每次变化时推送完整的任务
+static SENDER: LazyLock<broadcast::Sender<Job>> = LazyLock::new(|| broadcast::channel(256).0);
+
+/// 修改任务并推送
+fn update<F: FnOnce(&mut Job)>(id: &str, f: F) {
+    let Ok(mut jobs) = JOBS.lock() else {
+        return;
+    };
+    if let Some(job) = jobs.iter_mut().find(|j| j.id == id) {
+        f(job);
+        // 没有订阅时发送失败, 忽略
+        let _ = SENDER.send(job.clone());
+    }
+}
+
+fn update_item<F: FnOnce(&mut JobItem)>(id: &str, index: usize, f: F) {
+    update(id, |job| {
+        if let Some(item) = job.items.get_mut(index) {
+            f(item);
+        }
+    })
+}
+
+/// 获取单项的锁, 等待其他持有者结束
+pub async fn lock(kind: &str, id: &str) -> OwnedMutexGuard<()> {
+    let lock = {
+        let mut locks = LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+        locks.entry(format!("{}:{}", kind, id)).or_default().clone()
+    };
+    lock.lock_owned().await
+}
+
+/// 按 id 顺序获取多项的锁, 避免相互等待
+pub async fn lock_all(kind: &str, ids: &[String]) -> Vec<OwnedMutexGuard<()>> {
+    let mut ids: Vec<&String> = ids.iter().collect();
+    ids.sort();
+    ids.dedup();
+    let mut guards = Vec::new();
+    for id in ids {
+        guards.push(lock(kind, id).await);
+    }
+    guards
+}
+
+/// 未结束任务中尚未开始的同一项, 返回所在任务的 id.
+/// 已开始的项可能读取了旧的数据, 不合并
+fn pending(jobs: &VecDeque<Job>, kind: &str, id: &str) -> Option<String> {
+    jobs.iter()
+        .find(|j| {
+            j.kind == kind
+                && !j.finished()
+                && j.items
+                    .iter()
+                    .any(|i| i.id == id && i.state == state_pending)
+        })
+        .map(|j| j.id.clone())
+}
+
+fn insert(jobs: &mut VecDeque<Job>, job: Job) {
+    if jobs.len() >= keep
+        && let Some(i) = jobs.iter().position(|j| j.finished())
+    {
+        jobs.remove(i);
+    }
+    jobs.push_back(job);
+}
+
+/// 提交刷新任务并立即返回任务 id. items 为 (id, 名称), f 刷新单项.
+/// 已在其他任务中等待执行的项不重复提交, 全部等待中时返回首个所在任务的 id
+pub fn submit<F, R>(kind: &str, mut items: Vec<(String, String)>, f: F) -> String
+where
+    F: Fn(String) -> R + Send + Sync + 'static,
+    R: Future<Output = AnyResult<()>> + Send + 'static,
+{
+    let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut existing = None;
+    items.retain(|(id, _)| match pending(&jobs, kind, id) {
+        Some(job_id) => {
+            existing.get_or_insert(job_id);
+            false
+        }
+        None => true,
+    });
+    if items.is_empty()
+        && let Some(job_id) = existing
+    {
+        log::debug!("[任务] [{}] {} 刷新已在等待执行", job_id, kind);
+        return job_id;
+    }
+
+    let job = Job {
+        id: next_str(),
+        kind: kind.to_string(),
+        state: if items.is_empty() {
+            state_success.to_string()
+        } else {
+            state_pending.to_string()
+        },
+        total: items.len(),
+        done: 0,
+        failed: 0,
+        items: items
+            .into_iter()
+            .map(|(id, name)| JobItem {
+                id,
+                name,
+                state: state_pending.to_string(),
+                error: None,
+                start_time: None,
+                end_time: None,
+            })
+            .collect(),
+        create_time: current_millis().unwrap_or(0),
+        end_time: None,
+    };
+    let id = job.id.clone();
+    let targets: Vec<String> = job.items.iter().map(|i| i.id.clone()).collect();
+    insert(&mut jobs, job);
+    drop(jobs);
+    if targets.is_empty() {
+        return id;
+    }
+
+    log::info!(
+        "[任务] [{}] 开始 {} 刷新, 数量: {}",
+        id,
+        kind,
+        targets.len()
+    );
+    let f = Arc::new(f);
+    let job_id = id.clone();
+    tokio::spawn(async move {
+        let mut handles = Vec::new();
+        for (index, target) in targets.into_iter().enumerate() {
+            let f = f.clone();
+            let job_id = job_id.clone();
+            handles.push(tokio::spawn(async move {
+                let Ok(_permit) = SEMAPHORE.clone().acquire_owned().await else {
+                    return;
+                };
+                let now = current_millis().ok();
+                update(&job_id, |job| job.state = state_running.to_string());
+                update_item(&job_id, index, |item| {
+                    item.state = state_running.to_string();
+                    item.start_time = now;
+                });
+
+                let result = f(target.clone()).await;
+
+                if let Err(e) = &result {
+                    log::error!("[任务] [{}] [{}] 刷新异常! {}", job_id, target, e);
+                }
+                let error = result.err().map(|e| e.to_string());
+                update(&job_id, |job| job.finish(index, error));
+            }));
+        }
+        for (index, handle) in handles.into_iter().enumerate() {
+            // 单项 panic 时记为失败, 不影响其他项
+            if let Err(e) = handle.await {
+                log::error!("[任务] [{}] 第 {} 项刷新异常! {}", job_id, index + 1, e);
+                update(&job_id, |job| job.finish(index, Some(e.to_string())));
+            }
+        }
+        update(&job_id, |job| {
+            job.state = if job.failed > 0 {
+                state_failed.to_string()
+            } else {
+                state_success.to_string()
+            };
+            job.end_time = current_millis().ok();
+            log::info!(
+                "[任务] [{}] 结束, 成功: {}, 失败: {}",
+                job.id,
+                job.done - job.failed,
+                job.failed
+            );
+        });
+    });
+    id
+}
+
+pub fn find(id: &str) -> Option<Job> {
+    JOBS.lock()
+        .ok()
+        .and_then(|jobs| jobs.iter().find(|j| j.id == id).cloned())
+}
+
+/// 所有任务, 新的在前
+pub fn list() -> Vec<Job> {
+    JOBS.lock()
+        .map(|jobs| jobs.iter().rev().cloned().collect())
+        .unwrap_or_default()
+}
+
+pub fn subscribe() -> broadcast::Receiver<Job> {
+    SENDER.subscribe()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    fn job(id: &str, kind: &str, state: &str, items: &[(&str, &str)]) -> Job {
+        Job {
+            id: id.into(),
+            kind: kind.into(),
+            state: state.into(),
+            total: items.len(),
+            done: 0,
+            failed: 0,
+            items: items
+                .iter()
+                .map(|(id, state)| JobItem {
+                    id: id.to_string(),
+                    name: id.to_string(),
+                    state: state.to_string(),
+                    error: None,
+                    start_time: None,
+                    end_time: None,
+                })
+                .collect(),
+            create_time: 0,
+            end_time: None,
+        }
+    }
+
+    #[test]
+    fn pending_merge() {
+        let jobs = VecDeque::from([
+            job("1", kind_rule, state_success, &[("a", state_success)]),
+            job(
+                "2",
+                kind_rule,
+                state_running,
+                &[("a", state_running), ("b", state_pending)],
+            ),
+            job("3", kind_rule, state_pending, &[("a", state_pending)]),
+            job("4", kind_config, state_pending, &[("c", state_pending)]),
+        ]);
+        // 已结束或已开始的项不合并
+        assert_eq!(pending(&jobs, kind_rule, "a").as_deref(), Some("3"));
+        assert_eq!(pending(&jobs, kind_rule, "b").as_deref(), Some("2"));
+        assert_eq!(pending(&jobs, kind_rule, "c"), None);
+        assert_eq!(pending(&jobs, kind_config, "c").as_deref(), Some("4"));
+        assert_eq!(pending(&jobs, kind_subscribe, "a"), None);
+    }
+
+    #[test]
+    fn insert_keep() {
+        let mut jobs = VecDeque::new();
+        for i in 0..keep {
+            let state = if i == 0 { state_running } else { state_success };
+            insert(&mut jobs, job(&i.to_string(), kind_rule, state, &[]));
+        }
+        assert_eq!(jobs.len(), keep);
+        // 超出时移除最早结束的任务, 未结束的保留
+        insert(&mut jobs, job("new", kind_rule, state_pending, &[]));
+        assert_eq!(jobs.len(), keep);
+        assert_eq!(jobs[0].id, "0");
+        assert_eq!(jobs[1].id, "2");
+        assert_eq!(jobs.back().unwrap().id, "new");
+
+        // 全部未结束时不移除
+        let mut jobs: VecDeque<Job> = (0..keep)
+            .map(|i| job(&i.to_string(), kind_rule, state_running, &[]))
+            .collect();
+        insert(&mut jobs, job("new", kind_rule, state_pending, &[]));
+        assert_eq!(jobs.len(), keep + 1);
+    }
+
+    #[test]
+    fn lock_all_order() {
+        block_on(async {
+            let ids = ["b", "a", "b"].map(String::from);
+            let guards = lock_all("test", &ids).await;
+            assert_eq!(guards.len(), 2);
+            // 持有期间其他获取需要等待
+            let wait = tokio::time::timeout(Duration::from_millis(50), lock("test", "a")).await;
+            assert!(wait.is_err());
+            drop(guards);
+
+            // 以不同的顺序同时获取不会相互等待
+            let mut handles = Vec::new();
+            for i in 0..20 {
+                let ids = if i % 2 == 0 {
+                    ["x", "y", "z"]
+                } else {
+                    ["z", "y", "x"]
+                };
+                handles.push(tokio::spawn(async move {
+                    let _guards = lock_all("test", &ids.map(String::from)).await;
+                    tokio::task::yield_now().await;
+                }));
+            }
+            for handle in handles {
+                tokio::time::timeout(Duration::from_secs(5), handle)
+                    .await
+                    .expect("lock_all deadlock")
+                    .unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn submit_panic() {
+        let job = block_on(async {
+            let items = ["ok", "err", "panic"]
+                .map(|v| (v.to_string(), v.to_string()))
+                .to_vec();
+            let id = submit("test", items, |id| async move {
+                match id.as_str() {
+                    "ok" => Ok(()),
+                    "err" => Err("failed".into()),
+                    _ => panic!("boom"),
+                }
+            });
+            for _ in 0..500 {
+                match find(&id) {
+                    Some(job) if job.finished() => return job,
+                    _ => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+            panic!("job not finished");
+        });
+        assert_eq!(job.state, state_failed);
+        assert_eq!((job.total, job.done, job.failed), (3, 3, 2));
+        let states: Vec<&str> = job.items.iter().map(|i| i.state.as_str()).collect();
+        assert_eq!(states, [state_success, state_failed, state_failed]);
+        assert!(job.items.iter().all(|i| i.end_time.is_some()));
+        assert_eq!(job.items[1].error.as_deref(), Some("failed"));
+        assert!(job.items[2].error.is_some());
+    }
+}
@@ -0,0 +1,288 @@
+use library_core::app::get_app;
+use library_core::core::current_millis;
+use library_sing_box::Output;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+use tokio::sync::broadcast;
+
+/// 内存中保留的日志行数
+const keep: usize = 2000;
+/// 日志文件达到此大小后轮转, 单位: Bytes
+const file_max: u64 = 5 * 1024 * 1024;
+/// 保留的历史日志文件数量
+const file_keep: usize = 3;
+
+/// 日志级别, 由低到高
+pub const levels: [&str; 7] = ["trace", "debug", "info", "warn", "error", "fatal", "panic"];
+
+/// sing-box 日志格式: `[-0700 2006-01-02 15:04:05 ]LEVEL[[0000]] message`
+static LINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?:([+-]\d{4} \d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}) )?(TRACE|DEBUG|INFO|WARN|ERROR|FATAL|PANIC)(?:\[\d+\])? (.*)$",
+    )
+    .expect("invalid kernel log regex")
+});
+
+/// 终端颜色控制符, 内核启动前的输出可能带有颜色
+static ANSI: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-9;]*m").expect("invalid ansi regex"));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KernelLog {
+    /// 递增序号, 用于增量获取
+    pub seq: u64,
+    /// 接收时间: 毫秒级别时间戳
+    pub time: u128,
+    /// 内核输出的时间, 没有时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    pub level: String,
+    /// stdout / stderr
+    pub source: String,
+    pub message: String,
+}
+
+impl KernelLog {
+    fn parse(seq: u64, source: Output, line: &str) -> Self {
+        let time = current_millis().unwrap_or(0);
+        let (timestamp, level, message) = match LINE.captures(line) {
+            Some(c) => (
+                c.get(1).map(|m| m.as_str().to_string()),
+                c[2].to_lowercase(),
+                c[3].to_string(),
+            ),
+            // 无法识别的内容, 错误输出中一般是崩溃信息
+            None => (
+                None,
+                match source {
+                    Output::Stdout => "info".to_string(),
+                    Output::Stderr => "error".to_string(),
+                },
+                line.to_string(),
+            ),
+        };
+        Self {
+            seq,
+            time,
+            timestamp,
+            level,
+            source: source.name().to_string(),
+            message,
+        }
+    }
+
+    /// 级别不低于指定级别, 未知级别不过滤
+    pub fn at_least(&self, level: &str) -> bool {
+        let index = |l: &str| levels.iter().position(|v| *v == l);
+        match (index(&self.level), index(&level.to_lowercase())) {
+            (Some(a), Some(b)) => a >= b,
+            _ => true,
+        }
+    }
+}
+
+struct LogBuffer {
+    seq: u64,
+    lines: VecDeque<KernelLog>,
+    file: Option<File>,
+    file_size: u64,
+}
+
+impl LogBuffer {
+    fn new() -> Self {
+        Self {
+            seq: 0,
+            lines: VecDeque::new(),
+            file: None,
+            file_size: 0,
+        }
+    }
+}
+
+static BUFFER: LazyLock<Mutex<LogBuffer>> = LazyLock::new(|| Mutex::new(LogBuffer::new()));
+
+static SENDER: LazyLock<broadcast::Sender<KernelLog>> = LazyLock::new(|| broadcast::channel(256).0);
+
+fn file_path(i: usize) -> PathBuf {
+    let dir = get_app().logs_dir;
+    if i == 0 {
+        dir.join("kernel.log")
+    } else {
+        dir.join(format!("kernel.{}.log", i))
+    }
+}
+
+impl LogBuffer {
+    fn write_file(&mut self, line: &str) {
+        if self.file.is_none() || self.file_size >= file_max {
+            self.file = None;
+            if self.file_size >= file_max {
+                let _ = std::fs::remove_file(file_path(file_keep));
+                for i in (0..file_keep).rev() {
+                    let _ = std::fs::rename(file_path(i), file_path(i + 1));
+                }
+            }
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file_path(0))
+            {
+                Ok(f) => {
+                    self.file_size = f.metadata().map(|m| m.len()).unwrap_or(0);
+                    self.file = Some(f);
+                }
+                Err(e) => {
+                    log::error!("[内核] 日志文件打开异常! {}", e);
+                    return;
+                }
+            }
+        }
+        if let Some(f) = &mut self.file
+            && writeln!(f, "{}", line).is_ok()
+        {
+            self.file_size += line.len() as u64 + 1;
+        }
+    }
+}
+
+impl LogBuffer {
+    /// 去除颜色后记录一行, 超出保留行数时丢弃最早的. 返回去除颜色后的内容与日志, 空行返回 None
+    fn push<'a>(&mut self, source: Output, line: &'a str) -> Option<(Cow<'a, str>, KernelLog)> {
+        let line = ANSI.replace_all(line, "");
+        if line.is_empty() {
+            return None;
+        }
+        self.seq += 1;
+        let log = KernelLog::parse(self.seq, source, &line);
+        if self.lines.len() >= keep {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(log.clone());
+        Some((line, log))
+    }
+
+    fn list(&self, after: u64, level: Option<&str>, limit: usize) -> Vec<KernelLog> {
+        let vec: Vec<KernelLog> = self
+            .lines
+            .iter()
+            .filter(|l| l.seq > after && level.is_none_or(|v| l.at_least(v)))
+            .cloned()
+            .collect();
+        let skip = vec.len().saturating_sub(limit);
+        vec.into_iter().skip(skip).collect()
+    }
+}
+
+/// 接收内核输出的一行
+pub fn push(source: Output, line: &str) {
+    let Ok(mut buffer) = BUFFER.lock() else {
+        return;
+    };
+    let Some((line, log)) = buffer.push(source, line) else {
+        return;
+    };
+    buffer.write_file(&line);
+    // 没有订阅时发送失败, 忽略
+    let _ = SENDER.send(log);
+}
+
+/// 序号大于 after 且级别不低于 level 的日志, 最多返回最新的 limit 条
+pub fn list(after: u64, level: Option<&str>, limit: usize) -> Vec<KernelLog> {
+    let Ok(buffer) = BUFFER.lock() else {
+        return vec![];
+    };
+    buffer.list(after, level, limit)
+}
+
+pub fn subscribe() -> broadcast::Receiver<KernelLog> {
+    SENDER.subscribe()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_parse() {
+        let mut buffer = LogBuffer::new();
+        let (line, log) = buffer
+            .push(
+                Output::Stdout,
+                "\x1b[36m+0800 2024-01-02 03:04:05\x1b[0m \x1b[32mINFO\x1b[0m[0012] inbound started",
+            )
+            .unwrap();
+        assert_eq!(line, "+0800 2024-01-02 03:04:05 INFO[0012] inbound started");
+        assert_eq!(log.seq, 1);
+        assert_eq!(log.timestamp.as_deref(), Some("+0800 2024-01-02 03:04:05"));
+        assert_eq!(log.level, "info");
+        assert_eq!(log.source, "stdout");
+        assert_eq!(log.message, "inbound started");
+
+        let (_, log) = buffer.push(Output::Stdout, "WARN dns timeout").unwrap();
+        assert_eq!((log.timestamp, log.level.as_str()), (None, "warn"));
+        assert_eq!(log.message, "dns timeout");
+
+        // 无法识别的内容按来源确定级别
+        let (_, log) = buffer.push(Output::Stderr, "panic: boom").unwrap();
+        assert_eq!(
+            (log.level.as_str(), log.message.as_str()),
+            ("error", "panic: boom")
+        );
+        let (_, log) = buffer.push(Output::Stdout, "hello").unwrap();
+        assert_eq!(log.level, "info");
+
+        // 只有颜色控制符的行忽略, 不占用序号
+        assert!(buffer.push(Output::Stdout, "").is_none());
+        assert!(buffer.push(Output::Stdout, "\x1b[0m").is_none());
+        assert_eq!(buffer.seq, 4);
+        assert_eq!(buffer.lines.len(), 4);
+    }
+
+    #[test]
+    fn push_evict() {
+        let mut buffer = LogBuffer::new();
+        for i in 0..keep + 5 {
+            buffer.push(Output::Stdout, &format!("INFO {}", i));
+        }
+        assert_eq!(buffer.lines.len(), keep);
+        assert_eq!(buffer.lines.front().unwrap().seq, 6);
+        assert_eq!(buffer.lines.front().unwrap().message, "5");
+        assert_eq!(buffer.lines.back().unwrap().seq, keep as u64 + 5);
+    }
+
+    #[test]
+    fn list_filter() {
+        let mut buffer = LogBuffer::new();
+        for line in [
+            "DEBUG a", "INFO b", "WARN c", "ERROR d", "INFO e", "FATAL f",
+        ] {
+            buffer.push(Output::Stdout, line);
+        }
+        let messages = |vec: Vec<KernelLog>| vec.into_iter().map(|l| l.message).collect::<Vec<_>>();
+
+        assert_eq!(
+            messages(buffer.list(0, None, 100)),
+            ["a", "b", "c", "d", "e", "f"]
+        );
+        assert_eq!(messages(buffer.list(3, None, 100)), ["d", "e", "f"]);
+        assert!(buffer.list(6, None, 100).is_empty());
+        assert_eq!(messages(buffer.list(0, Some("warn"), 100)), ["c", "d", "f"]);
+        // 级别不区分大小写, 未知级别不过滤
+        assert_eq!(messages(buffer.list(0, Some("ERROR"), 100)), ["d", "f"]);
+        assert_eq!(messages(buffer.list(4, Some("unknown"), 100)), ["e", "f"]);
+        // 超过数量时返回最新的
+        assert_eq!(messages(buffer.list(0, None, 2)), ["e", "f"]);
+        assert_eq!(messages(buffer.list(1, Some("info"), 2)), ["e", "f"]);
+        assert_eq!(
+            messages(buffer.list(0, Some("info"), 0)),
+            Vec::<String>::new()
+        );
+    }
+}
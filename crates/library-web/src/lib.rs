@@ -1,21 +1,34 @@
+mod clash_api;
 mod http;
+mod job;
+mod kernel_log;
+mod refresh_retry;
+mod route_clash;
 mod route_config;
 mod route_global;
+mod route_job;
 mod route_kernel;
 mod route_rule;
 mod route_setting;
+mod route_stats;
 mod route_subscribe;
+mod route_system;
 pub mod settings;
 mod singbox;
 mod startup;
 mod tbl_config;
+mod tbl_config_history;
 mod tbl_rule;
 mod tbl_setting;
 mod tbl_subscribe;
+mod tbl_subscribe_alert;
+mod tbl_subscribe_usage;
+mod tbl_traffic_stat;
 pub mod updater;
 pub mod webserver;
 
 pub use crate::route_kernel::set_open;
+pub use crate::route_subscribe::set_alert;
 pub use crate::singbox::start;
 pub use crate::singbox::stop;
 
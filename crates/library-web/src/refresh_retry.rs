@@ -0,0 +1,89 @@
+use library_core::core::{AnyResult, current_millis};
+use library_core::sqlite::{StatementExt, execute, query};
+
+/// 首次失败后的重试间隔, 单位: 毫秒
+const retry_base: u128 = 60 * 1000;
+/// 重试间隔上限, 单位: 毫秒
+const retry_max: u128 = 6 * 60 * 60 * 1000;
+
+/// 定时刷新时跳过还在退避中的数据, 参数为当前时间
+pub const sql_where_retry: &str = "`next_retry_time` <= cast(? as INTEGER)";
+
+/// 连续失败 n 次后的重试间隔, 每次翻倍
+pub fn delay(fail_count: u32) -> u128 {
+    let shift = fail_count.saturating_sub(1).min(16);
+    (retry_base << shift).min(retry_max)
+}
+
+/// 记录刷新结果. 成功时清除失败状态, 失败时累计次数并推迟下次定时刷新
+pub fn record<T>(table: &str, tag: &str, id: &str, name: &str, result: &AnyResult<T>) {
+    let r = match result {
+        Ok(_) => _success(table, id),
+        Err(e) => _failure(table, tag, id, name, &e.to_string()),
+    };
+    if let Err(e) = r {
+        log::error!("[{}] [{}] 记录刷新状态失败! {}", tag, name, e);
+    }
+}
+
+fn _success(table: &str, id: &str) -> AnyResult<()> {
+    let sql = format!(
+        "update {} set `fail_count`=0,`last_error`='',`next_retry_time`=0 where `id`=? and `fail_count`>0",
+        table
+    );
+    execute(&sql, vec![id.into()])?;
+    Ok(())
+}
+
+fn _failure(table: &str, tag: &str, id: &str, name: &str, error: &str) -> AnyResult<()> {
+    let sql = format!("select `fail_count` from {} where `id`=?", table);
+    let vec = query(&sql, vec![id.into()], |stmt| stmt.read_u32("fail_count"))?;
+    let Some(count) = vec.into_iter().next() else {
+        return Ok(());
+    };
+    let count = count.unwrap_or(0) + 1;
+    let delay = delay(count);
+    let next = current_millis()? + delay;
+
+    let sql = format!(
+        "update {} set `fail_count`=?,`last_error`=?,`next_retry_time`=? where `id`=?",
+        table
+    );
+    let args = vec![
+        (count as i64).into(),
+        error.into(),
+        next.to_string().into(),
+        id.into(),
+    ];
+    execute(&sql, args)?;
+    log::warn!(
+        "[{}] [{}] 连续刷新失败 {} 次, {} 秒后重试",
+        tag,
+        name,
+        count,
+        delay / 1000
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_backoff() {
+        let minute = 60 * 1000;
+        // 未失败时按首次失败处理
+        assert_eq!(delay(0), minute);
+        assert_eq!(delay(1), minute);
+        assert_eq!(delay(2), 2 * minute);
+        assert_eq!(delay(3), 4 * minute);
+        assert_eq!(delay(9), 256 * minute);
+        // 不超过 6 小时
+        assert_eq!(delay(10), retry_max);
+        assert_eq!(delay(17), retry_max);
+        // 位移次数有上限, 不会溢出
+        assert_eq!(delay(18), retry_max);
+        assert_eq!(delay(u32::MAX), retry_max);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::clash_api;
+use crate::clash_api::{ClashConnections, ClashProxy};
+use crate::route_global::{IdPo, R};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use library_core::core::AnyResult;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// 切换策略组的出站
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClashSelectPo {
+    pub group: String,
+    pub name: String,
+}
+
+/// 延迟测试, 未指定时使用默认测试地址和超时时间
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClashDelayPo {
+    pub name: String,
+    pub url: Option<String>,
+    /// 超时时间, 单位: 毫秒
+    pub timeout: Option<u32>,
+}
+
+impl ClashDelayPo {
+    fn url(&self) -> &str {
+        self.url.as_deref().unwrap_or(library_nc::kernel::test_url)
+    }
+
+    fn timeout(&self) -> u32 {
+        self.timeout.unwrap_or(clash_api::delay_timeout)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClashDelayVo {
+    pub delay: u32,
+}
+
+async fn proxies() -> R<BTreeMap<String, ClashProxy>> {
+    clash_api::proxies().await.into()
+}
+
+async fn select(Json(po): Json<ClashSelectPo>) -> R<()> {
+    let result = clash_api::select(&po.group, &po.name).await;
+    if result.is_ok() {
+        log::info!("[内核] 策略组 [{}] 切换到 [{}]", po.group, po.name);
+    }
+    result.into()
+}
+
+async fn proxy_delay(Json(po): Json<ClashDelayPo>) -> R<ClashDelayVo> {
+    clash_api::proxy_delay(&po.name, po.url(), po.timeout())
+        .await
+        .map(|delay| ClashDelayVo { delay })
+        .into()
+}
+
+async fn group_delay(Json(po): Json<ClashDelayPo>) -> R<BTreeMap<String, u32>> {
+    clash_api::group_delay(&po.name, po.url(), po.timeout())
+        .await
+        .into()
+}
+
+async fn connections() -> R<ClashConnections> {
+    clash_api::connections().await.into()
+}
+
+/// 未指定 id 时关闭全部连接
+async fn close(Json(po): Json<IdPo>) -> R<()> {
+    clash_api::close(po.id.as_deref()).await.into()
+}
+
+type EventStream = BoxStream<'static, Result<Event, axum::Error>>;
+
+/// 转发内核推送的数据, 连接失败时推送一次 error 事件后结束
+fn relay<T: Serialize + Send + 'static>(
+    name: &'static str,
+    result: AnyResult<impl futures::Stream<Item = T> + Send + 'static>,
+) -> Sse<EventStream> {
+    let stream: EventStream = match result {
+        Ok(stream) => stream
+            .map(move |v| Event::default().event(name).json_data(&v))
+            .boxed(),
+        Err(e) => {
+            log::warn!("[内核] 接口 [{}] 连接异常! {}", name, e);
+            let event = Event::default().event("error").data(e.to_string());
+            futures::stream::once(async move { Ok(event) }).boxed()
+        }
+    };
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn traffic() -> Sse<EventStream> {
+    relay("traffic", clash_api::traffic().await)
+}
+
+async fn memory() -> Sse<EventStream> {
+    relay("memory", clash_api::memory().await)
+}
+
+pub fn fill(router: Router) -> Router {
+    router
+        .route("/clash/proxies", get(proxies))
+        .route("/clash/proxies/select", post(select))
+        .route("/clash/proxies/delay", post(proxy_delay))
+        .route("/clash/group/delay", post(group_delay))
+        .route("/clash/connections", get(connections))
+        .route("/clash/connections/close", post(close))
+        .route("/clash/traffic", get(traffic))
+        .route("/clash/memory", get(memory))
+}
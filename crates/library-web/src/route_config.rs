@@ -1,11 +1,17 @@
 use crate::http;
+use crate::job;
+use crate::refresh_retry;
+use crate::http::ResponseExt;
 use crate::route_global::{current_millis, from_err_box, to_value, IdPo, R};
-use crate::tbl_config::{TblConfig, TblConfigUpsertDTO};
+use crate::route_kernel;
+use crate::tbl_config::{TblConfig, TblConfigExplainDTO, TblConfigUpsertDTO};
+use crate::tbl_config_history::{TblConfigHistory, TblConfigHistoryDiffDTO};
 use crate::tbl_rule::TblRule;
 use crate::tbl_setting::TblSettingKernel;
 use crate::tbl_subscribe::TblSubscribe;
 use axum::routing::{get, patch, post};
 use axum::{Json, Router};
+use library_core::app::get_app;
 use library_core::app_config::AppConfig;
 use library_core::core::{AnyResult, BizError};
 use library_core::file;
@@ -13,32 +19,62 @@ use library_core::snowflake::next_str;
 use library_core::sqlite::{execute, query};
 use library_core::timer::Timer;
 use library_nc::core::fast;
+use library_nc::diff::{self, SingBoxDiff};
 use library_nc::kernel::{
-    dns_default_cn, dns_default_proxy, exclude_default, include_main, KernelConfig, NodeContains,
+    dns_default_cn, dns_default_proxy, exclude_default, include_main, key_direct, key_proxy,
+    key_reject, KernelConfig, NodeContains,
 };
-use library_nc::rule::{Rule, RuleType, SinBoxJsonRule};
+use library_nc::rule::{Rule, RuleSeen, RuleType, SinBoxJsonRule};
+use library_nc::singbox_explain::Explanation;
 use library_nc::subscribe::{Subscribe, SubscribeNode, HEADER_INFO};
+use library_nc::validate;
+use sha2::{Digest, Sha256};
 use sqlite::Value;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, LazyLock};
 use std::time::Duration;
 use tokio::task::id;
 
-fn _rule_sing_box(
-    config_root: &PathBuf,
-    config: &TblConfig,
-    ids: &Vec<String>,
-) -> AnyResult<Vec<Rule>> {
+/// 规则的数据文件, 优先使用srs
+fn _rule_path(root: &Path, r: RuleType) -> Option<PathBuf> {
+    let path = root.join(format!("{}.srs", r.name()));
+    if path.exists() {
+        return Some(path);
+    }
+    let path = root.join(format!("{}.json", r.name()));
+    if path.exists() { Some(path) } else { None }
+}
+
+/// 直接引用规则数据目录中的文件, 不复制
+fn _rule_local(ids: &[String]) -> Vec<Rule> {
     let mut vec = Vec::new();
     for id in ids {
         let root = TblRule::dir_data(id);
-        log::debug!("[配置] [{}] [SingBox] 处理规则[{}]", config.name, id);
-
         for r in RuleType::all() {
-            let mut path = root.join(format!("{}.srs", r.name()));
-            if !path.exists() {
-                path = root.join(format!("{}.json", r.name()));
+            if let Some(path) = _rule_path(&root, *r) {
+                vec.push(Rule::from_local(*r, path.to_string_lossy().to_string()));
             }
+        }
+    }
+    vec
+}
+
+/// 合并列表中所有规则的同类数据为一个规则集, 按目标内核的版本编译为srs.
+/// 列表需按路由中的匹配顺序处理, 之前列表中已有的值会从之后的列表中移除
+fn _rule_sing_box(
+    config_root: &Path,
+    config: &TblConfig,
+    key: &str,
+    ids: &[String],
+    version: u8,
+    seen: &mut RuleSeen,
+) -> AnyResult<Vec<Rule>> {
+    let mut vec = Vec::new();
+    for r in RuleType::all() {
+        let mut jsons = Vec::new();
+        for id in ids {
+            let path = TblRule::dir_data(id).join(format!("{}.json", r.name()));
             if !path.exists() {
                 log::debug!(
                     "[配置] [{}] [SingBox] 规则[{}]无可用[{}]类型配置",
@@ -48,51 +84,80 @@ fn _rule_sing_box(
                 );
                 continue;
             }
+            jsons.push(std::fs::read_to_string(path)?);
+        }
+        if jsons.is_empty() {
+            continue;
+        }
 
-            let source = path
-                .clone()
-                .to_str()
-                .ok_or_else(|| BizError::PathNotFound(path.clone()))?
-                .to_string();
-
+        let Some(merged) = SinBoxJsonRule::merge(*r, &jsons, seen)? else {
             log::debug!(
-                "[配置] [{}] [SingBox] 规则[{}]找到[{}]类型配置: {}",
+                "[配置] [{}] [SingBox] [{}]的[{}]类型规则已全部包含在之前的规则中, 跳过",
                 config.name,
-                id,
+                key,
                 r.name(),
-                &source
             );
+            continue;
+        };
+        log::debug!(
+            "[配置] [{}] [SingBox] [{}]的[{}]类型规则合并, 合并前: {}, 合并后: {}",
+            config.name,
+            key,
+            r.name(),
+            merged.count_raw,
+            merged.count
+        );
 
-            let _filename = path
-                .clone()
-                .file_name()
-                .ok_or_else(|| BizError::FileNotFound(source.clone()))?
-                .to_str()
-                .ok_or_else(|| BizError::FileNotFound(source.clone()))?
-                .to_string();
-
-            let filename = format!("rule_{}_{}", id, _filename);
-            let rule_path = config_root.join(filename);
-            let target = rule_path
-                .clone()
-                .to_str()
-                .ok_or_else(|| BizError::PathNotFound(rule_path.clone()))?
-                .to_string();
+        let rule_path = config_root.join(format!("rule_{}_{}.srs", key, r.name()));
+        let target = rule_path
+            .to_str()
+            .ok_or_else(|| BizError::PathNotFound(rule_path.clone()))?
+            .to_string();
+        file::overwrite_bytes(&rule_path, &merged.srs(version)?)?;
+        vec.push(Rule::from_local(*r, target).with_ipcidr(merged.ipcidr));
+    }
+    Ok(vec)
+}
 
-            log::debug!(
-                "[配置] [{}] [SingBox] 规则[{}]的[{}]类型配置复制到: {}",
-                config.name,
-                id,
-                r.name(),
-                &target
-            );
-            file::copy_force(path, rule_path)?;
+fn _digest(data: impl AsRef<[u8]>) -> String {
+    hex::encode(Sha256::digest(data))
+}
 
-            let rule = Rule::from_local(*r, target);
-            vec.push(rule);
+/// 生成配置时各来源的摘要, 用于判断新版本由哪些变化触发
+fn _source(
+    setting: &TblSettingKernel,
+    config: &TblConfig,
+    nodes: &[SubscribeNode],
+) -> AnyResult<BTreeMap<String, String>> {
+    let mut map = BTreeMap::new();
+    map.insert(
+        TblConfigHistory::source_config.to_string(),
+        config.update_time.to_string(),
+    );
+    map.insert(
+        TblConfigHistory::source_setting.to_string(),
+        _digest(serde_json::to_string(setting)?),
+    );
+    map.insert(
+        format!("subscribe:{}", config.subscribe_id),
+        _digest(serde_json::to_string(nodes)?),
+    );
+    let ids = config
+        .rule_direct_ids
+        .iter()
+        .chain(&config.rule_proxy_ids)
+        .chain(&config.rule_reject_ids);
+    for id in ids {
+        let root = TblRule::dir_data(id);
+        let mut hasher = Sha256::new();
+        for r in RuleType::all() {
+            if let Some(path) = _rule_path(&root, *r) {
+                hasher.update(std::fs::read(path)?);
+            }
         }
+        map.insert(format!("rule:{}", id), hex::encode(hasher.finalize()));
     }
-    Ok(vec)
+    Ok(map)
 }
 
 fn _build_sing_box(
@@ -101,39 +166,70 @@ fn _build_sing_box(
     nodes: Vec<SubscribeNode>,
     include: &NodeContains,
     exclude: &NodeContains,
+    cause: &str,
 ) -> AnyResult<()> {
+    let source = _source(setting, config, &nodes)?;
     let root = config.sing_box_dir();
+    let version = setting.sing_box_target().rule_set_version();
+    // 与路由中的匹配顺序一致: 拒绝, 直连, 代理
+    let mut seen = RuleSeen::default();
+    log::debug!(
+        "[配置] [{}] [SingBox] 获取拒绝规则数据: {}",
+        config.name,
+        config.rule_reject_ids.join(", ")
+    );
+    let rules_reject = _rule_sing_box(
+        &root,
+        config,
+        key_reject,
+        &config.rule_reject_ids,
+        version,
+        &mut seen,
+    )?;
     log::debug!(
         "[配置] [{}] [SingBox] 获取直连规则数据: {}",
         config.name,
         config.rule_direct_ids.join(", ")
     );
-    let rules_direct = _rule_sing_box(&root, &config, &config.rule_direct_ids)?;
+    let rules_direct = _rule_sing_box(
+        &root,
+        config,
+        key_direct,
+        &config.rule_direct_ids,
+        version,
+        &mut seen,
+    )?;
     log::debug!(
         "[配置] [{}] [SingBox] 获取代理规则数据: {}",
         config.name,
         config.rule_proxy_ids.join(", ")
     );
-    let rules_proxy = _rule_sing_box(&root, &config, &config.rule_proxy_ids)?;
-    log::debug!(
-        "[配置] [{}] [SingBox] 获取拒绝规则数据: {}",
-        config.name,
-        config.rule_reject_ids.join(", ")
-    );
-    let rules_reject = _rule_sing_box(&root, &config, &config.rule_reject_ids)?;
-
+    let rules_proxy = _rule_sing_box(
+        &root,
+        config,
+        key_proxy,
+        &config.rule_proxy_ids,
+        version,
+        &mut seen,
+    )?;
     let kc = KernelConfig {
         nodes,
         debug: false,
         tun: config.tun,
+        tun_setting: setting.tun.clone(),
         fake_ip: config.fake_ip,
         ipv6: config.ipv6,
         geo_cn_direct: config.geo_cn,
         rules_direct,
         rules_proxy,
         rules_reject,
+        rules_custom: config.rules_custom.clone(),
+        rules_geo: config.rules_geo.clone(),
         dns_cn: setting.dns_cn.clone(),
         dns_proxy: setting.dns_proxy.clone(),
+        dns: setting.dns.clone(),
+        inbounds: setting.inbounds.clone(),
+        allow_lan: setting.allow_lan,
     }
     .with_default(include, exclude);
 
@@ -141,31 +237,91 @@ fn _build_sing_box(
         return Err(Box::new(BizError::NodesEmpty(config.id.clone())));
     }
 
+    let geo = kc.geo_rules();
+    for r in &config.rules_geo {
+        if !geo
+            .iter()
+            .any(|g| g.name() == r.name() && g.target == r.target)
+        {
+            log::warn!(
+                "[配置] [{}] [SingBox] geo 规则无效或重复, 忽略: {} -> {}",
+                config.name,
+                r.name(),
+                r.target
+            );
+        }
+    }
+
+    let version = setting.sing_box_target();
+    if version.is_legacy() && !kc.dns.hosts.is_empty() {
+        log::warn!(
+            "[配置] [{}] [SingBox] 内核 {} 不支持 dns hosts, 忽略",
+            config.name,
+            setting.sing_box_version
+        );
+    }
+
     log::debug!("[配置] [{}] [SingBox] 构建配置", config.name,);
-    let json = kc.sing_box(&setting.ui, &setting.mixed_listen, setting.mixed_port)?;
+    let json = kc.sing_box(
+        &setting.ui,
+        &setting.mixed_listen,
+        setting.mixed_port,
+        version,
+    )?;
+    // 校验不通过时保留原有配置
+    if let Err(e) = validate::sing_box(&json) {
+        log::error!(
+            "[配置] [{}] [SingBox] 配置校验失败, 不写入: {}",
+            config.name,
+            e
+        );
+        return Err(e);
+    }
     log::debug!("[配置] [{}] [SingBox] 写入配置", config.name,);
     let path = config.sing_box_json();
+    let changed = !std::fs::read_to_string(&path).is_ok_and(|old| old == json);
     file::overwrite(path, &json)?;
+    if changed {
+        route_kernel::reload(config);
+    }
+    // 历史记录失败不影响配置生效
+    match TblConfigHistory::record(&config.id, cause, source, &json) {
+        Ok(true) => log::info!("[配置] [{}] [SingBox] 记录新版本", config.name),
+        Ok(false) => log::debug!("[配置] [{}] [SingBox] 配置未变化", config.name),
+        Err(e) => log::warn!("[配置] [{}] [SingBox] 记录版本失败! {}", config.name, e),
+    }
     Ok(())
 }
 
-async fn _refresh_id(option: Option<String>) -> AnyResult<()> {
-    if let Some(id) = option {
-        let o = TblConfig::find(&id)?;
-        match o {
-            None => Ok(()),
-            Some(dto) => _refresh(dto).await,
-        }
-    } else {
-        let vec = TblConfig::all()?;
-        for s in vec {
-            _refresh(s).await?
-        }
-        Ok(())
+async fn _refresh_id(id: String, cause: &str) -> AnyResult<()> {
+    match TblConfig::find(&id)? {
+        None => Ok(()),
+        Some(dto) => _refresh(dto, cause).await,
     }
 }
 
-async fn _refresh(config: TblConfig) -> AnyResult<()> {
+/// 刷新并记录结果, 失败时推迟下次定时刷新
+/// 同一配置的构建依次执行, 加锁后重新读取. 构建期间锁定引用的规则, 避免读取到刷新中的数据
+async fn _refresh(config: TblConfig, cause: &str) -> AnyResult<()> {
+    let _guard = job::lock(job::kind_config, &config.id).await;
+    let Some(config) = TblConfig::find(&config.id)? else {
+        return Ok(());
+    };
+    let ids: Vec<String> = config
+        .rule_direct_ids
+        .iter()
+        .chain(&config.rule_proxy_ids)
+        .chain(&config.rule_reject_ids)
+        .cloned()
+        .collect();
+    let _rules = job::lock_all(job::kind_rule, &ids).await;
+    let (id, name) = (config.id.clone(), config.name.clone());
+    let result = _build(config, cause).await;
+    refresh_retry::record(TblConfig::table_name, "配置", &id, &name, &result);
+    result
+}
+
+async fn _build(config: TblConfig, cause: &str) -> AnyResult<()> {
     log::info!("[配置] [{}] 刷新配置", config.name);
     log::debug!(
         "[配置] [{}] 获取订阅数据: {}",
@@ -197,7 +353,7 @@ async fn _refresh(config: TblConfig) -> AnyResult<()> {
         config.name,
         config.subscribe_id
     );
-    _build_sing_box(&setting, &config, nodes, &include, &exclude)?;
+    _build_sing_box(&setting, &config, nodes, &include, &exclude, cause)?;
     log::info!("[配置] [{}] 刷新完成", config.name);
 
     let time = current_millis();
@@ -210,12 +366,75 @@ async fn _refresh(config: TblConfig) -> AnyResult<()> {
     Ok(())
 }
 
+/// 远程规则集缓存到本地, 超过一天重新下载
+async fn _rule_remote(url: &str) -> AnyResult<Vec<u8>> {
+    // 以完整地址的摘要命名, 避免不同地址的文件名相同时互相覆盖
+    let path = get_app().cache_dir.join("rule_set").join(_digest(url));
+    let expired = match path.metadata().and_then(|m| m.modified()) {
+        Ok(t) => t.elapsed().map_or(true, |e| e > Duration::from_secs(86400)),
+        Err(_) => true,
+    };
+    if expired {
+        log::debug!("[配置] 下载远程规则集: {}", url);
+        file::create(&path)?;
+        http::get(url).await?.overwrite(&path).await?;
+    }
+    Ok(std::fs::read(path)?)
+}
+
+async fn _explain(dto: TblConfigExplainDTO) -> AnyResult<Explanation> {
+    let config = TblConfig::find(&dto.id)?.ok_or(BizError::ConfigNotFound)?;
+    let setting = TblSettingKernel::get()?;
+    log::debug!("[配置] [{}] 分析匹配规则", config.name);
+
+    let kc = KernelConfig {
+        nodes: vec![],
+        debug: false,
+        tun: config.tun,
+        tun_setting: setting.tun,
+        fake_ip: config.fake_ip,
+        ipv6: config.ipv6,
+        geo_cn_direct: config.geo_cn,
+        rules_direct: _rule_local(&config.rule_direct_ids),
+        rules_proxy: _rule_local(&config.rule_proxy_ids),
+        rules_reject: _rule_local(&config.rule_reject_ids),
+        rules_custom: config.rules_custom.clone(),
+        rules_geo: config.rules_geo.clone(),
+        dns_cn: setting.dns_cn,
+        dns_proxy: setting.dns_proxy,
+        dns: setting.dns,
+        inbounds: setting.inbounds,
+        allow_lan: setting.allow_lan,
+    };
+
+    let mut explainer = kc.sing_box_explainer();
+    explainer.load_local();
+
+    let remotes: Vec<(String, String)> = explainer
+        .rule_sets()
+        .iter()
+        .filter_map(|r| r.url.clone().map(|u| (r.tag.clone(), u)))
+        .collect();
+    for (tag, url) in remotes {
+        let result = match _rule_remote(&url).await {
+            Ok(bytes) => explainer.load(&tag, &bytes),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::warn!("[配置] [{}] 规则集[{}]加载失败! {}", config.name, tag, e);
+            explainer.warn(&tag, &e.to_string());
+        }
+    }
+
+    Ok(explainer.explain(&dto.query))
+}
+
 pub static TIMER_CONFIG: LazyLock<Arc<Timer>> = LazyLock::new(|| {
-    Timer::new("Rule".into(), Duration::from_secs(60), || async {
+    Timer::new("Config".into(), Duration::from_secs(60), || async {
         let vec = TblConfig::need_refresh()?;
         for s in vec {
             let name = &s.name.clone();
-            match _refresh(s).await {
+            match _refresh(s, TblConfigHistory::cause_timer).await {
                 Ok(_) => {}
                 Err(e) => {
                     log::error!("[配置] [{}] 刷新异常! {}", name, e)
@@ -230,7 +449,13 @@ async fn list() -> R<Vec<TblConfig>> {
     TblConfig::all().into()
 }
 
-async fn upsert(Json(entity): Json<TblConfigUpsertDTO>) -> R<()> {
+async fn upsert(Json(entity): Json<TblConfigUpsertDTO>) -> R<String> {
+    for r in &entity.rules_custom {
+        if let Err(e) = r.validate() {
+            return from_err_box(e);
+        }
+    }
+
     let sql: String;
     let args: Vec<Value>;
 
@@ -276,6 +501,8 @@ async fn upsert(Json(entity): Json<TblConfigUpsertDTO>) -> R<()> {
     ))
     .unwrap()
     .into();
+    let rules_custom = serde_json::to_string(&entity.rules_custom).unwrap().into();
+    let rules_geo = serde_json::to_string(&entity.rules_geo).unwrap().into();
     let include_area_non = to_value(entity.include_area_non);
     let include_area = serde_json::to_string(&serde_json::Value::Array(
         entity
@@ -320,11 +547,11 @@ async fn upsert(Json(entity): Json<TblConfigUpsertDTO>) -> R<()> {
             insert into {}(`id`,`name`
             ,`tun`,`fake_ip`,`ipv6`
             ,`subscribe_id`,`geo_cn`
-            ,`rule_direct_ids`,`rule_proxy_ids`,`rule_reject_ids`
+            ,`rule_direct_ids`,`rule_proxy_ids`,`rule_reject_ids`,`rules_custom`,`rules_geo`
             ,`include_area_non`,`include_area`,`include_name_contains`
             ,`exclude_area`,`exclude_name_contains`
             ,`interval`,`refresh_time`,`update_time`,`create_time`)
-VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
+VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
         ",
             TblConfig::table_name
         );
@@ -339,6 +566,8 @@ VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
             rule_direct_ids,
             rule_proxy_ids,
             rule_reject_ids,
+            rules_custom,
+            rules_geo,
             include_area_non,
             include_area,
             include_name_contains,
@@ -354,7 +583,7 @@ VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
             "update {} set `name`=?
             ,`tun`=?,`fake_ip`=?,`ipv6`=?
             ,`subscribe_id`=?,`geo_cn`=?
-            ,`rule_direct_ids`=?,`rule_proxy_ids`=?,`rule_reject_ids`=?
+            ,`rule_direct_ids`=?,`rule_proxy_ids`=?,`rule_reject_ids`=?,`rules_custom`=?,`rules_geo`=?
             ,`include_area_non`=?,`include_area`=?,`include_name_contains`=?
             ,`exclude_area`=?,`exclude_name_contains`=?
             ,`interval`=?,`update_time`=? where `id`=?",
@@ -370,6 +599,8 @@ VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
             rule_direct_ids,
             rule_proxy_ids,
             rule_reject_ids,
+            rules_custom,
+            rules_geo,
             include_area_non,
             include_area,
             include_name_contains,
@@ -383,13 +614,30 @@ VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
     _upsert(sql, args, id).await.into()
 }
 
-async fn _upsert(sql: String, args: Vec<Value>, id: String) -> AnyResult<()> {
+async fn _upsert(sql: String, args: Vec<Value>, id: String) -> AnyResult<String> {
     execute(&sql, args)?;
-    _refresh_id(Some(id)).await
+    _submit(Some(id), TblConfigHistory::cause_upsert)
+}
+
+/// 后台刷新, 返回任务 id
+fn _submit(option: Option<String>, cause: &'static str) -> AnyResult<String> {
+    let vec = match option {
+        Some(id) => TblConfig::find(&id)?.into_iter().collect(),
+        None => TblConfig::all()?,
+    };
+    let items = vec.into_iter().map(|c| (c.id, c.name)).collect();
+    Ok(job::submit(job::kind_config, items, move |id| {
+        _refresh_id(id, cause)
+    }))
+}
+
+/// 设置变更后重新生成所有配置, 返回任务 id
+pub(crate) fn rebuild_all() -> AnyResult<String> {
+    _submit(None, TblConfigHistory::cause_setting)
 }
 
-async fn refresh(Json(po): Json<IdPo>) -> R<()> {
-    _refresh_id(po.id).await.into()
+async fn refresh(Json(po): Json<IdPo>) -> R<String> {
+    _submit(po.id, TblConfigHistory::cause_refresh).into()
 }
 
 async fn delete(Json(po): Json<IdPo>) -> R<()> {
@@ -399,6 +647,7 @@ async fn delete(Json(po): Json<IdPo>) -> R<()> {
 
         match execute(&sql, args) {
             Ok(_) => {
+                let _ = TblConfigHistory::delete_config(&id);
                 let _ = file::delete_dir(TblConfig::dir_data(&id));
             }
             Err(e) => return from_err_box(e),
@@ -407,6 +656,64 @@ async fn delete(Json(po): Json<IdPo>) -> R<()> {
     R::from(())
 }
 
+async fn explain(Json(dto): Json<TblConfigExplainDTO>) -> R<Explanation> {
+    _explain(dto).await.into()
+}
+
+async fn history(Json(po): Json<IdPo>) -> R<Vec<TblConfigHistory>> {
+    TblConfigHistory::list(&po.id.unwrap_or_default()).into()
+}
+
+fn _diff(dto: TblConfigHistoryDiffDTO) -> AnyResult<SingBoxDiff> {
+    let from = TblConfigHistory::find(&dto.from)?.ok_or(BizError::ConfigNotFound)?;
+    let to = match dto.to {
+        Some(id) => {
+            TblConfigHistory::find(&id)?
+                .ok_or(BizError::ConfigNotFound)?
+                .content
+        }
+        None => {
+            let config = TblConfig::find(&from.config_id)?.ok_or(BizError::ConfigNotFound)?;
+            std::fs::read_to_string(config.sing_box_json())?
+        }
+    };
+    diff::sing_box(&from.content, &to)
+}
+
+async fn diff(Json(dto): Json<TblConfigHistoryDiffDTO>) -> R<SingBoxDiff> {
+    _diff(dto).into()
+}
+
+/// 回滚到指定版本, 作为新版本记录. 在下次定时刷新前保持生效
+///
+/// 规则数据引用的是当前的规则文件, 不随版本回滚
+async fn _rollback(id: &str) -> AnyResult<()> {
+    let history = TblConfigHistory::find(id)?.ok_or(BizError::ConfigNotFound)?;
+    let _guard = job::lock(job::kind_config, &history.config_id).await;
+    let config = TblConfig::find(&history.config_id)?.ok_or(BizError::ConfigNotFound)?;
+    log::info!("[配置] [{}] 回滚到版本: {}", config.name, id);
+    validate::sing_box(&history.content)?;
+    file::overwrite(config.sing_box_json(), &history.content)?;
+    route_kernel::reload(&config);
+    TblConfigHistory::record(
+        &config.id,
+        TblConfigHistory::cause_rollback,
+        history.source,
+        &history.content,
+    )?;
+
+    let sql = format!(
+        "update {} set `refresh_time`=? where `id`=?",
+        TblConfig::table_name
+    );
+    execute(&sql, vec![current_millis(), config.id.into()])?;
+    Ok(())
+}
+
+async fn rollback(Json(po): Json<IdPo>) -> R<()> {
+    _rollback(&po.id.unwrap_or_default()).await.into()
+}
+
 async fn default() -> R<TblConfigUpsertDTO> {
     R::from(TblConfigUpsertDTO::default.clone())
 }
@@ -418,4 +725,8 @@ pub fn fill(router: Router) -> Router {
         .route("/config/refresh", patch(refresh))
         .route("/config/delete", post(delete))
         .route("/config/default", get(default))
+        .route("/config/explain", post(explain))
+        .route("/config/history", post(history))
+        .route("/config/diff", post(diff))
+        .route("/config/rollback", post(rollback))
 }
@@ -0,0 +1,67 @@
+use crate::job;
+use crate::job::Job;
+use crate::route_global::R;
+use axum::Router;
+use axum::extract::Path;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use futures::Stream;
+use library_core::core::BizError;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+
+async fn list() -> R<Vec<Job>> {
+    R::from(job::list())
+}
+
+async fn find(Path(id): Path<String>) -> R<Job> {
+    match job::find(&id) {
+        Some(job) => R::from(job),
+        None => R::from(Err(
+            BizError::InvalidParam(format!("未找到任务: {}", id)).to_string()
+        )),
+    }
+}
+
+/// 推送任务的当前状态和后续变化, 任务结束后关闭
+async fn stream(Path(id): Path<String>) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    // 先订阅再读取当前状态, 避免遗漏中间的变化
+    let rx = job::subscribe();
+    let current = job::find(&id);
+    // 任务不存在时直接结束
+    let done = current.is_none();
+    let state: (Receiver<Job>, String, Option<Job>, bool) = (rx, id, current, done);
+    let stream = futures::stream::unfold(state, |(mut rx, id, current, done)| async move {
+        if done {
+            return None;
+        }
+        if let Some(job) = current {
+            let finished = job.finished();
+            let event = Event::default().event("job").json_data(&job);
+            return Some((event, (rx, id, None, finished)));
+        }
+        loop {
+            let job = match rx.recv().await {
+                Ok(job) if job.id == id => job,
+                Ok(_) => continue,
+                // 丢失了部分变化, 重新读取当前状态
+                Err(RecvError::Lagged(_)) => match job::find(&id) {
+                    Some(job) => job,
+                    None => return None,
+                },
+                Err(RecvError::Closed) => return None,
+            };
+            let finished = job.finished();
+            let event = Event::default().event("job").json_data(&job);
+            return Some((event, (rx, id, None, finished)));
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub fn fill(router: Router) -> Router {
+    router
+        .route("/jobs", get(list))
+        .route("/jobs/{id}", get(find))
+        .route("/jobs/{id}/stream", get(stream))
+}
@@ -1,15 +1,24 @@
+use crate::kernel_log;
+use crate::kernel_log::KernelLog;
 use crate::route_global::{IdPo, R};
 use crate::singbox;
+use crate::singbox::Supervisor;
 use crate::tbl_config::TblConfig;
 use crate::tbl_setting::{TblSettingKernel, TblSettingRun};
+use axum::extract::Query;
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::routing::{get, post};
 use axum::{Json, Router};
+use futures::Stream;
 use library_core::app::get_app;
 use library_core::app_config::AppConfig;
-use library_core::core::{AnyResult, BizError};
+use library_core::core::{current_millis, AnyResult, BizError};
 use library_core::file;
 use serde::{Deserialize, Serialize};
-use std::sync::{LazyLock, LockResult, Mutex};
+use library_core::timer::Timer;
+use std::sync::{Arc, LazyLock, LockResult, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -21,16 +30,72 @@ pub struct KernelState {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub config_id: Option<String>,
     pub ui: String,
+    /// 最近一次自动重载的结果, 启动后清空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reload: Option<KernelReload>,
+    pub supervisor: Supervisor,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KernelReload {
+    pub config_id: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// 重载时间: 毫秒级别时间戳
+    pub time: u128,
 }
 
+/// 内核日志查询
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KernelLogQuery {
+    /// 只返回序号大于此值的日志
+    pub after: Option<u64>,
+    /// 最低级别
+    pub level: Option<String>,
+    pub limit: Option<usize>,
+}
+
+static _reload: Mutex<Option<KernelReload>> = Mutex::new(None);
+
 pub(crate) fn start(config: TblConfig) -> AnyResult<()> {
     TblSettingRun::set_selected(&config.id)?;
     let work_dir = get_app().cache_dir.join("sing_box");
     file::create_dir(&work_dir)?;
     let json = config.sing_box_json();
+    if let Ok(mut r) = _reload.lock() {
+        *r = None;
+    }
     singbox::start(&json, &work_dir)
 }
 
+/// 配置重新生成后, 如果是正在运行的配置则重载内核
+pub(crate) fn reload(config: &TblConfig) {
+    let selected = TblSettingRun::get().ok().and_then(|r| r.selected);
+    if selected.as_deref() != Some(config.id.as_str()) {
+        return;
+    }
+    if !singbox::state().is_ok_and(|s| s.running) {
+        return;
+    }
+    log::info!("[内核] [{}] 运行中的配置已变化, 重载内核", config.name);
+    let result = singbox::reload();
+    if let Err(e) = &result {
+        log::error!("[内核] [{}] 重载异常! {}", config.name, e);
+    }
+    let reload = KernelReload {
+        config_id: config.id.clone(),
+        success: result.is_ok(),
+        reason: result.err().map(|e| e.to_string()),
+        time: current_millis().unwrap_or(0),
+    };
+    if let Ok(mut r) = _reload.lock() {
+        *r = Some(reload);
+    }
+}
+
 fn _state() -> AnyResult<KernelState> {
     let _state = singbox::state()?;
     let map = AppConfig::keys(vec![TblSettingKernel::key_ui, TblSettingRun::key_selected])?;
@@ -46,9 +111,17 @@ fn _state() -> AnyResult<KernelState> {
         reason: _state.reason,
         config_id,
         ui,
+        reload: _reload.lock().ok().and_then(|r| r.clone()),
+        supervisor: singbox::supervisor(),
     })
 }
 
+pub static TIMER_KERNEL: LazyLock<Arc<Timer>> = LazyLock::new(|| {
+    Timer::new("Kernel".into(), Duration::from_secs(1), || async {
+        singbox::supervise()
+    })
+});
+
 async fn state() -> R<KernelState> {
     _state().into()
 }
@@ -63,6 +136,35 @@ async fn stop() -> R<()> {
     singbox::stop().into()
 }
 
+async fn logs(Query(q): Query<KernelLogQuery>) -> R<Vec<KernelLog>> {
+    let limit = q.limit.unwrap_or(500);
+    R::from(kernel_log::list(
+        q.after.unwrap_or(0),
+        q.level.as_deref(),
+        limit,
+    ))
+}
+
+/// 实时推送新的内核日志
+async fn logs_stream(
+    Query(q): Query<KernelLogQuery>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let rx = kernel_log::subscribe();
+    let stream = futures::stream::unfold((rx, q.level), |(mut rx, level)| async move {
+        loop {
+            match rx.recv().await {
+                Ok(log) if level.as_deref().is_none_or(|l| log.at_least(l)) => {
+                    let event = Event::default().event("log").json_data(&log);
+                    return Some((event, (rx, level)));
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
 static _open: LazyLock<Mutex<Box<dyn Fn(String) -> AnyResult<()> + 'static + Send + Sync>>> =
     LazyLock::new(|| Mutex::new(Box::new(|_| Err(Box::new(BizError::Unsupported)))));
 
@@ -93,4 +195,6 @@ pub fn fill(router: Router) -> Router {
         .route("/kernel/start", post(_start))
         .route("/kernel/stop", post(stop))
         .route("/kernel/open", post(open))
+        .route("/kernel/logs", get(logs))
+        .route("/kernel/logs/stream", get(logs_stream))
 }
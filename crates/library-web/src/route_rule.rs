@@ -1,7 +1,10 @@
 use crate::http::ResponseExt;
 use crate::route_global::{current_millis, from_err_box, IdPo, R};
 use crate::tbl_rule::{TblRule, TblRuleRefreshDTO, TblRuleUpsertDTO};
-use crate::{http, singbox};
+use crate::tbl_setting::TblSettingKernel;
+use crate::http;
+use crate::job;
+use crate::refresh_retry;
 use axum::routing::{get, patch, post};
 use axum::{Json, Router};
 use library_core::app_config::AppConfig;
@@ -11,46 +14,54 @@ use library_core::snowflake::next_str;
 use library_core::sqlite::{execute, query};
 use library_core::timer::Timer;
 use library_nc::core::fast;
-use library_nc::rule::{RuleType, SinBoxJsonRule};
+use library_nc::rule::{RuleFormat, RuleType, SinBoxJsonRule, SinBoxJsonRules};
+use library_nc::rule_srs;
 use library_nc::subscribe::{Subscribe, HEADER_INFO};
 use sqlite::Value;
 use std::sync::{Arc, LazyLock};
 use std::time::Duration;
 use tokio::task::id;
 
-async fn _refresh_id(option: Option<String>) -> AnyResult<()> {
-    if let Some(id) = option {
-        let o = TblRuleRefreshDTO::find(&id)?;
-        match o {
-            None => Ok(()),
-            Some(dto) => _refresh(dto).await,
-        }
-    } else {
-        let vec = TblRuleRefreshDTO::all()?;
-        for s in vec {
-            _refresh(s).await?
-        }
-        Ok(())
+async fn _refresh_id(id: String) -> AnyResult<()> {
+    match TblRuleRefreshDTO::find(&id)? {
+        None => Ok(()),
+        Some(dto) => _refresh(dto).await,
     }
 }
 
+/// 刷新并记录结果, 失败时推迟下次定时刷新. 同一规则的刷新依次执行, 加锁后重新读取
 async fn _refresh(s: TblRuleRefreshDTO) -> AnyResult<()> {
+    let _guard = job::lock(job::kind_rule, &s.id).await;
+    let Some(s) = TblRuleRefreshDTO::find(&s.id)? else {
+        return Ok(());
+    };
+    let (id, name) = (s.id.clone(), s.name.clone());
+    let result = _fetch(s).await;
+    refresh_retry::record(TblRule::table_name, "规则", &id, &name, &result);
+    result
+}
+
+async fn _fetch(s: TblRuleRefreshDTO) -> AnyResult<()> {
     log::info!("[规则] [{}] 刷新资源", s.name);
+    let format = RuleFormat::from_name(&s.format);
     let content: Option<String>;
-    let sing_box: Vec<SinBoxJsonRule>;
+    let sing_box: SinBoxJsonRules;
     if s.url.is_empty() {
         log::info!("[规则] [{}] 本地规则", s.name);
         content = None;
-        sing_box = SinBoxJsonRule::json_classical_process(s.content.as_str())?
+        sing_box = SinBoxJsonRule::json_format(s.content.as_str(), format, true)?
     } else {
         log::info!("[规则] [{}] 远端规则", s.name);
         let url_fast = fast(&s.url);
         let response = http::get(&url_fast).await?;
         let body = response.read_text().await?;
         log::debug!("[规则] [{}] 获取到远端数据", s.name);
-        sing_box = SinBoxJsonRule::json_classical_process(body.as_str())?;
+        sing_box = SinBoxJsonRule::json_format(body.as_str(), format, true)?;
         content = Some(body);
     }
+    for w in &sing_box.warnings {
+        log::warn!("[规则] [{}] 跳过无法转换的规则, {}", s.name, w);
+    }
     if let Some(c) = content.clone() {
         if c == s.content {
             log::info!("[规则] [{}] 规则内容未变更, 结束", s.name);
@@ -66,14 +77,20 @@ async fn _refresh(s: TblRuleRefreshDTO) -> AnyResult<()> {
     }
 
     let root = s.dir_data();
+    let rule_set_version = TblSettingKernel::get()?
+        .sing_box_target()
+        .rule_set_version();
     let mut count: u64 = 0;
+    let mut count_raw: u64 = 0;
     let mut count_process: u64 = 0;
     let mut count_ip: u64 = 0;
     let mut count_other: u64 = 0;
+    let mut written: Vec<&str> = vec![];
 
     log::debug!("[规则] [{}] 数据处理", s.name);
-    for r in sing_box {
+    for r in sing_box.rules {
         count += r.count;
+        count_raw += r.count_raw;
 
         match r.type_ {
             RuleType::Ip => count_ip += r.count,
@@ -82,20 +99,37 @@ async fn _refresh(s: TblRuleRefreshDTO) -> AnyResult<()> {
         }
 
         let name = r.type_.name();
-        log::debug!("[规则] [{}] SingBox 规则处理: {}", s.name, name);
+        log::debug!(
+            "[规则] [{}] SingBox 规则处理: {}, 优化前: {}, 优化后: {}",
+            s.name,
+            name,
+            r.count_raw,
+            r.count
+        );
         let path_json = root.join(format!("{}.json", name));
         let path_srs = root.join(format!("{}.srs", name));
 
         log::debug!("[规则] [{}] SingBox 写入json: {}", s.name, name);
-        file::overwrite(path_json.clone(), &r.json)?;
-        log::debug!("[规则] [{}] SingBox 尝试转换为srs: {}", s.name, name);
-        singbox::json_to_srs(&path_json, &path_srs)?;
+        file::overwrite(path_json, &r.json)?;
+        log::debug!("[规则] [{}] SingBox 编译srs: {}", s.name, name);
+        file::overwrite_bytes(path_srs, &r.srs(rule_set_version)?)?;
+        written.push(name);
+    }
+
+    // 格式或内容变更后不再产生的类型, 删除旧文件避免继续被配置引用
+    for r in RuleType::all() {
+        if written.contains(&r.name()) {
+            continue;
+        }
+        log::debug!("[规则] [{}] 删除过期的规则文件: {}", s.name, r.name());
+        file::delete(root.join(format!("{}.json", r.name())))?;
+        file::delete(root.join(format!("{}.srs", r.name())))?;
     }
 
     log::debug!("[规则] [{}] 数据保存", s.name);
     let time = current_millis();
     let sql = format!(
-        "update {} set {}`refresh_time`=?,`count`=?,`count_process`=?,`count_ip`=?,`count_other`=? where `id`=?",
+        "update {} set {}`refresh_time`=?,`count`=?,`count_raw`=?,`count_process`=?,`count_ip`=?,`count_other`=? where `id`=?",
         TblRule::table_name,
         content.clone().map_or("", |v| "`content`=?,")
     );
@@ -105,6 +139,7 @@ async fn _refresh(s: TblRuleRefreshDTO) -> AnyResult<()> {
     }
     args.push(time.into());
     args.push(count.to_string().into());
+    args.push(count_raw.to_string().into());
     args.push(count_process.to_string().into());
     args.push(count_ip.to_string().into());
     args.push(count_other.to_string().into());
@@ -115,6 +150,25 @@ async fn _refresh(s: TblRuleRefreshDTO) -> AnyResult<()> {
     Ok(())
 }
 
+/// 内核版本变更后按新的规则集版本重新编译所有规则的srs
+pub(crate) async fn recompile(version: u8) -> AnyResult<()> {
+    for s in TblRuleRefreshDTO::all()? {
+        let _guard = job::lock(job::kind_rule, &s.id).await;
+        let root = s.dir_data();
+        for r in RuleType::all() {
+            let path_json = root.join(format!("{}.json", r.name()));
+            if !path_json.exists() {
+                continue;
+            }
+            let json = std::fs::read_to_string(path_json)?;
+            let srs = rule_srs::from_json_version(&json, Some(version))?;
+            file::overwrite_bytes(root.join(format!("{}.srs", r.name())), &srs)?;
+        }
+        log::info!("[规则] [{}] 按规则集版本 {} 重新编译srs", s.name, version);
+    }
+    Ok(())
+}
+
 pub static TIMER_RULE: LazyLock<Arc<Timer>> = LazyLock::new(|| {
     Timer::new("Rule".into(), Duration::from_secs(60), || async {
         let vec = TblRuleRefreshDTO::need_refresh()?;
@@ -134,7 +188,7 @@ pub static TIMER_RULE: LazyLock<Arc<Timer>> = LazyLock::new(|| {
 async fn list() -> R<Vec<TblRule>> {
     let sql = format!(
         "
-select `id`,`name`,`url`, {}, `interval`,`update_time`,`create_time`,`refresh_time`,`count`,`count_process`,`count_ip`,`count_other`
+select `id`,`name`,`url`, {}, `format`, `interval`,`update_time`,`create_time`,`refresh_time`,`count`,`count_raw`,`count_process`,`count_ip`,`count_other`,`fail_count`,`last_error`,`next_retry_time`
 from {}",
         TblRule::sql_field_content,
         TblRule::table_name
@@ -142,7 +196,7 @@ from {}",
     query(&sql, vec![], |stmt| TblRule::from_db(stmt)).into()
 }
 
-async fn upsert(Json(entity): Json<TblRuleUpsertDTO>) -> R<()> {
+async fn upsert(Json(entity): Json<TblRuleUpsertDTO>) -> R<String> {
     let sql: String;
     let args: Vec<Value>;
 
@@ -156,6 +210,7 @@ async fn upsert(Json(entity): Json<TblRuleUpsertDTO>) -> R<()> {
         entity.id.unwrap()
     };
     let content = entity.content.unwrap_or("".into()).into();
+    let format = RuleFormat::from_name(&entity.format.unwrap_or_default()).name().into();
     let time = current_millis();
     let interval = entity.interval.to_string().into();
 
@@ -163,10 +218,10 @@ async fn upsert(Json(entity): Json<TblRuleUpsertDTO>) -> R<()> {
         sql = format!(
             "
             insert into {}(`id`,`name`
-            ,`url`,`content`
+            ,`url`,`content`,`format`
             ,`interval`,`update_time`,`create_time`
             ,`refresh_time`,`count`,`count_process`,`count_ip`,`count_other`)
-VALUES(?,?,?,?,?,?,?,?,?,?,?,?)
+VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?)
         ",
             TblRule::table_name
         );
@@ -175,6 +230,7 @@ VALUES(?,?,?,?,?,?,?,?,?,?,?,?)
             entity.name.into(),
             entity.url.into(),
             content,
+            format,
             interval,
             time.clone(),
             time,
@@ -186,13 +242,14 @@ VALUES(?,?,?,?,?,?,?,?,?,?,?,?)
         ];
     } else {
         sql = format!(
-            "update {} set `name`=?,`url`=?,`content`=?,`interval`=?,`update_time`=? where `id`=?",
+            "update {} set `name`=?,`url`=?,`content`=?,`format`=?,`interval`=?,`update_time`=? where `id`=?",
             TblRule::table_name
         );
         args = vec![
             entity.name.into(),
             entity.url.into(),
             content,
+            format,
             interval,
             time,
             id.clone().into(),
@@ -201,13 +258,23 @@ VALUES(?,?,?,?,?,?,?,?,?,?,?,?)
     _upsert(sql, args, id).await.into()
 }
 
-async fn _upsert(sql: String, args: Vec<Value>, id: String) -> AnyResult<()> {
+async fn _upsert(sql: String, args: Vec<Value>, id: String) -> AnyResult<String> {
     execute(&sql, args)?;
-    _refresh_id(Some(id)).await
+    _submit(Some(id))
+}
+
+/// 后台刷新, 返回任务 id
+fn _submit(option: Option<String>) -> AnyResult<String> {
+    let vec = match option {
+        Some(id) => TblRuleRefreshDTO::find(&id)?.into_iter().collect(),
+        None => TblRuleRefreshDTO::all()?,
+    };
+    let items = vec.into_iter().map(|s| (s.id, s.name)).collect();
+    Ok(job::submit(job::kind_rule, items, _refresh_id))
 }
 
-async fn refresh(Json(po): Json<IdPo>) -> R<()> {
-    _refresh_id(po.id).await.into()
+async fn refresh(Json(po): Json<IdPo>) -> R<String> {
+    _submit(po.id).into()
 }
 
 async fn delete(Json(po): Json<IdPo>) -> R<()> {
@@ -1,8 +1,11 @@
+use crate::route_config;
 use crate::route_global::{from_err_box, R};
-use crate::tbl_setting::TblSetting;
+use crate::route_rule;
+use crate::tbl_setting::{TblSetting, TblSettingKernel};
 use crate::updater;
 use axum::routing::{get, post};
 use axum::{Json, Router};
+use library_core::core::AnyResult;
 use serde::{Deserialize, Serialize};
 use std::sync::{LazyLock, Mutex};
 use std::thread;
@@ -12,7 +15,26 @@ async fn _get() -> R<TblSetting> {
 }
 
 async fn upsert(Json(entity): Json<TblSetting>) -> R<i32> {
-    entity.upsert().into()
+    _upsert(entity).await.into()
+}
+
+/// 目标内核的规则集版本变更时, 已编译的srs需要按新版本重新生成
+async fn _upsert(entity: TblSetting) -> AnyResult<i32> {
+    let before = TblSettingKernel::get()?
+        .sing_box_target()
+        .rule_set_version();
+    let count = entity.upsert()?;
+    let version = entity.kernel.sing_box_target().rule_set_version();
+    if version != before {
+        log::info!(
+            "[设置] 规则集版本 {} -> {}, 重新编译规则并生成配置",
+            before,
+            version
+        );
+        route_rule::recompile(version).await?;
+        route_config::rebuild_all()?;
+    }
+    Ok(count)
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,309 @@
+use crate::clash_api;
+use crate::clash_api::ClashConnections;
+use crate::route_global::R;
+use crate::singbox;
+use crate::tbl_setting::TblSettingRun;
+use crate::tbl_traffic_stat::{TblTrafficStat, TblTrafficStatQuery, TblTrafficStatVo};
+use axum::Router;
+use axum::extract::Query;
+use axum::routing::get;
+use library_core::core::AnyResult;
+use library_core::timer::Timer;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// 采样间隔较短以便按连接归属流量, 按此间隔写入统计
+const flush_interval: Duration = Duration::from_secs(10);
+
+type Traffic = BTreeMap<String, (u64, u64)>;
+
+/// 上一次采样时的内核累计流量
+#[derive(Debug, Default)]
+struct Sample {
+    config_id: String,
+    upload_total: u64,
+    download_total: u64,
+    /// 连接 id -> (上传, 下载, 出站)
+    connections: HashMap<String, (u64, u64, String)>,
+    /// 未写入的流量
+    pending: Traffic,
+    /// 上次写入时间, 为空时表示未写入过
+    flush_time: Option<Instant>,
+}
+
+fn add(traffic: &mut Traffic, outbound: &str, upload: u64, download: u64) {
+    let v = traffic.entry(outbound.into()).or_default();
+    v.0 += upload;
+    v.1 += download;
+}
+
+/// 按权重分摊 value, 余数计入最后一个
+fn share(value: u64, weights: &[u64]) -> Vec<u64> {
+    let total: u128 = weights.iter().map(|w| *w as u128).sum();
+    let mut vec: Vec<u64> = weights
+        .iter()
+        .map(|w| (value as u128 * *w as u128 / total.max(1)) as u64)
+        .collect();
+    let rest = value - vec.iter().sum::<u64>();
+    if let Some(last) = vec.last_mut() {
+        *last += rest;
+    }
+    vec
+}
+
+impl Sample {
+    /// 切换配置或内核重启后累计流量从零开始, 需要重新采样
+    fn restarted(&self, config_id: &str, current: &ClashConnections) -> bool {
+        self.config_id != config_id
+            || current.upload_total < self.upload_total
+            || current.download_total < self.download_total
+    }
+
+    /// 计算与上次采样的差值并替换为本次采样, 出站 -> (上传, 下载)
+    fn diff(&mut self, current: ClashConnections) -> Traffic {
+        let mut traffic = Traffic::new();
+        let (mut upload, mut download) = (0, 0);
+        let mut connections = HashMap::new();
+        for c in current.connections {
+            let (u, d) = self
+                .connections
+                .get(&c.id)
+                .map_or((0, 0), |(u, d, _)| (*u, *d));
+            let (u, d) = (c.upload.saturating_sub(u), c.download.saturating_sub(d));
+            // chains 由实际出站到顶层出站
+            let outbound = c.chains.last().cloned().unwrap_or_default();
+            add(&mut traffic, &outbound, u, d);
+            upload += u;
+            download += d;
+            connections.insert(c.id, (c.upload, c.download, outbound));
+        }
+        // 两次采样之间关闭的连接只体现在累计流量中, 按关闭的连接最后所在的出站分摊.
+        // 没有关闭的连接时, 为两次采样之间开始并结束的连接, 无法归属
+        let rest_upload = current
+            .upload_total
+            .saturating_sub(self.upload_total)
+            .saturating_sub(upload);
+        let rest_download = current
+            .download_total
+            .saturating_sub(self.download_total)
+            .saturating_sub(download);
+        // 按 id 排序, 余数的归属不随遍历顺序变化
+        let mut closed: Vec<(&String, &(u64, u64, String))> = self
+            .connections
+            .iter()
+            .filter(|(id, _)| !connections.contains_key(*id))
+            .collect();
+        closed.sort_by_key(|(id, _)| *id);
+        if closed.is_empty() {
+            add(&mut traffic, "", rest_upload, rest_download);
+        } else {
+            let weights: Vec<u64> = closed.iter().map(|(_, (u, d, _))| u + d + 1).collect();
+            let uploads = share(rest_upload, &weights);
+            let downloads = share(rest_download, &weights);
+            for (i, (_, (_, _, outbound))) in closed.iter().enumerate() {
+                add(&mut traffic, outbound, uploads[i], downloads[i]);
+            }
+        }
+
+        self.upload_total = current.upload_total;
+        self.download_total = current.download_total;
+        self.connections = connections;
+        traffic
+    }
+
+    /// 合并到未写入的流量, 达到写入间隔时取出
+    fn merge(&mut self, traffic: Traffic) -> Option<Traffic> {
+        for (outbound, (u, d)) in traffic {
+            add(&mut self.pending, &outbound, u, d);
+        }
+        let due = self
+            .flush_time
+            .is_none_or(|t| t.elapsed() >= flush_interval);
+        if !due {
+            return None;
+        }
+        self.flush_time = Some(Instant::now());
+        Some(std::mem::take(&mut self.pending))
+    }
+}
+
+static SAMPLE: LazyLock<Mutex<Option<Sample>>> = LazyLock::new(|| Mutex::new(None));
+
+async fn sample() -> AnyResult<()> {
+    let selected = TblSettingRun::get()?.selected;
+    let config_id = match selected {
+        Some(id) if singbox::state()?.running => id,
+        _ => {
+            // 内核停止时写入剩余的流量
+            let last = SAMPLE.lock().ok().and_then(|mut s| s.take());
+            if let Some(s) = last {
+                TblTrafficStat::add(&s.config_id, &s.pending)?;
+            }
+            return Ok(());
+        }
+    };
+    let current = clash_api::connections().await?;
+    let (flush, traffic) = {
+        let Ok(mut s) = SAMPLE.lock() else {
+            return Ok(());
+        };
+        let restarted = s.as_ref().is_none_or(|v| v.restarted(&config_id, &current));
+        let mut flush = None;
+        if restarted {
+            let last = s.replace(Sample {
+                config_id: config_id.clone(),
+                ..Default::default()
+            });
+            flush = last.map(|v| (v.config_id, v.pending));
+        }
+        match s.as_mut() {
+            Some(v) => {
+                let traffic = v.diff(current);
+                (flush, v.merge(traffic))
+            }
+            None => return Ok(()),
+        }
+    };
+    if let Some((id, pending)) = flush {
+        TblTrafficStat::add(&id, &pending)?;
+    }
+    match traffic {
+        Some(traffic) => TblTrafficStat::add(&config_id, &traffic),
+        None => Ok(()),
+    }
+}
+
+pub static TIMER_TRAFFIC: LazyLock<Arc<Timer>> = LazyLock::new(|| {
+    Timer::new("Traffic".into(), Duration::from_secs(1), || async {
+        sample().await
+    })
+});
+
+async fn traffic(Query(q): Query<TblTrafficStatQuery>) -> R<TblTrafficStatVo> {
+    TblTrafficStatVo::query(&q).into()
+}
+
+pub fn fill(router: Router) -> Router {
+    router.route("/stats/traffic", get(traffic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn connections(
+        upload_total: u64,
+        download_total: u64,
+        list: &[(&str, u64, u64, &str)],
+    ) -> ClashConnections {
+        let list: Vec<_> = list
+            .iter()
+            .map(|(id, u, d, outbound)| {
+                json!({"id": id, "upload": u, "download": d, "chains": ["节点", outbound]})
+            })
+            .collect();
+        serde_json::from_value(json!({
+            "uploadTotal": upload_total,
+            "downloadTotal": download_total,
+            "connections": list,
+        }))
+        .unwrap()
+    }
+
+    fn traffic(list: &[(&str, u64, u64)]) -> Traffic {
+        list.iter()
+            .map(|(k, u, d)| (k.to_string(), (*u, *d)))
+            .collect()
+    }
+
+    #[test]
+    fn share_rest() {
+        assert_eq!(share(100, &[1, 1, 2]), [25, 25, 50]);
+        // 余数计入最后一个
+        assert_eq!(share(10, &[1, 1, 1]), [3, 3, 4]);
+        assert_eq!(share(1, &[5, 5]), [0, 1]);
+        assert_eq!(share(0, &[3, 7]), [0, 0]);
+        assert_eq!(share(7, &[0, 0]), [0, 7]);
+        assert!(share(7, &[]).is_empty());
+        assert_eq!(
+            share(u64::MAX, &[u64::MAX, u64::MAX]).iter().sum::<u64>(),
+            u64::MAX
+        );
+    }
+
+    #[test]
+    fn diff_consecutive() {
+        let mut sample = Sample::default();
+        let t = sample.diff(connections(
+            30,
+            300,
+            &[("a", 10, 100, "hk"), ("b", 20, 200, "jp")],
+        ));
+        assert_eq!(t, traffic(&[("", 0, 0), ("hk", 10, 100), ("jp", 20, 200)]));
+
+        // 只计算增量, 新连接从零开始
+        let t = sample.diff(connections(
+            65,
+            650,
+            &[
+                ("a", 15, 150, "hk"),
+                ("b", 20, 200, "jp"),
+                ("c", 30, 300, "hk"),
+            ],
+        ));
+        assert_eq!(t, traffic(&[("", 0, 0), ("hk", 35, 350), ("jp", 0, 0)]));
+        assert_eq!((sample.upload_total, sample.download_total), (65, 650));
+        assert_eq!(sample.connections.len(), 3);
+
+        // 两次采样之间开始并结束的连接无法归属
+        let t = sample.diff(connections(
+            70,
+            700,
+            &[
+                ("a", 15, 150, "hk"),
+                ("b", 20, 200, "jp"),
+                ("c", 30, 300, "hk"),
+            ],
+        ));
+        assert_eq!(t, traffic(&[("", 5, 50), ("hk", 0, 0), ("jp", 0, 0)]));
+    }
+
+    #[test]
+    fn diff_closed() {
+        let mut sample = Sample::default();
+        sample.diff(connections(
+            40,
+            400,
+            &[
+                ("a", 10, 100, "hk"),
+                ("b", 29, 299, "jp"),
+                ("c", 1, 1, "us"),
+            ],
+        ));
+        // a 与 b 关闭, 剩余流量按最后的流量分摊到各自的出站
+        let t = sample.diff(connections(84, 840, &[("c", 3, 21, "us")]));
+        assert_eq!(
+            t,
+            traffic(&[("hk", 10, 105), ("jp", 32, 315), ("us", 2, 20)])
+        );
+        assert_eq!(t.values().map(|v| v.0).sum::<u64>(), 84 - 40);
+        assert_eq!(t.values().map(|v| v.1).sum::<u64>(), 840 - 400);
+        assert_eq!(sample.connections.keys().collect::<Vec<_>>(), ["c"]);
+    }
+
+    #[test]
+    fn restarted() {
+        let mut sample = Sample {
+            config_id: "1".into(),
+            ..Default::default()
+        };
+        sample.diff(connections(100, 1000, &[]));
+        assert!(!sample.restarted("1", &connections(100, 1000, &[])));
+        assert!(!sample.restarted("1", &connections(150, 1500, &[])));
+        assert!(sample.restarted("2", &connections(150, 1500, &[])));
+        assert!(sample.restarted("1", &connections(10, 1500, &[])));
+        assert!(sample.restarted("1", &connections(150, 10, &[])));
+    }
+}
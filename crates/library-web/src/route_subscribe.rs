@@ -1,10 +1,17 @@
 use crate::http;
+use crate::job;
+use crate::refresh_retry;
 use crate::http::ResponseExt;
 use crate::route_global::{current_millis, from_err_box, IdPo, R};
+use crate::tbl_setting::TblSettingSoftware;
 use crate::tbl_subscribe::{TblSubscribe, TblSubscribeRefreshDTO, TblSubscribeUpsertDTO};
+use crate::tbl_subscribe_alert::{TblSubscribeAlert, TblSubscribeAlertQuery};
+use crate::tbl_subscribe_usage::{TblSubscribeUsage, TblSubscribeUsageVo};
+use axum::extract::Query;
 use axum::routing::{get, patch, post};
 use axum::{Json, Router};
 use library_core::core::AnyResult;
+use library_core::data_size::DataSize;
 use library_core::snowflake::next_str;
 use library_core::sqlite::{execute, query};
 use library_core::timer::Timer;
@@ -13,26 +20,30 @@ use library_nc::subscribe::{Subscribe, HEADER_INFO};
 use sqlite::Value;
 use std::convert::Into;
 use std::ops::Deref;
-use std::sync::{Arc, LazyLock};
+use std::sync::{Arc, LazyLock, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-async fn _refresh_id(option: Option<String>) -> AnyResult<()> {
-    if let Some(id) = option {
-        let o = TblSubscribeRefreshDTO::find(&id)?;
-        match o {
-            None => Ok(()),
-            Some(dto) => _refresh(dto).await,
-        }
-    } else {
-        let vec = TblSubscribeRefreshDTO::all()?;
-        for s in vec {
-            _refresh(s).await?
-        }
-        Ok(())
+async fn _refresh_id(id: String) -> AnyResult<()> {
+    match TblSubscribeRefreshDTO::find(&id)? {
+        None => Ok(()),
+        Some(dto) => _refresh(dto).await,
     }
 }
 
+/// 刷新并记录结果, 失败时推迟下次定时刷新
+/// 同一订阅的刷新依次执行, 加锁后重新读取, 等待期间可能已修改
 async fn _refresh(s: TblSubscribeRefreshDTO) -> AnyResult<()> {
+    let _guard = job::lock(job::kind_subscribe, &s.id).await;
+    let Some(s) = TblSubscribeRefreshDTO::find(&s.id)? else {
+        return Ok(());
+    };
+    let (id, name) = (s.id.clone(), s.name.clone());
+    let result = _fetch(s).await;
+    refresh_retry::record(TblSubscribe::table_name, "订阅", &id, &name, &result);
+    result
+}
+
+async fn _fetch(s: TblSubscribeRefreshDTO) -> AnyResult<()> {
     log::info!("[订阅] [{}] 刷新资源", s.name);
     let content: Option<String>;
     let subscribe: Subscribe;
@@ -57,6 +68,7 @@ async fn _refresh(s: TblSubscribeRefreshDTO) -> AnyResult<()> {
     if let Some(c) = content.clone() {
         if c == s.content {
             log::info!("[订阅] [{}] 订阅内容未变更, 结束", s.name);
+            _after_refresh(&s.id, &s.name, &subscribe);
             let time = current_millis();
             let sql = format!(
                 "update {} set `refresh_time`=? where `id`=?",
@@ -91,14 +103,104 @@ async fn _refresh(s: TblSubscribeRefreshDTO) -> AnyResult<()> {
     args.push(subscribe.upload.unwrap_or(0).to_string().into());
     args.push(subscribe.max.unwrap_or(0).to_string().into());
     args.push(subscribe.expire.unwrap_or(0).to_string().into());
-    args.push(s.id.into());
+    args.push(s.id.clone().into());
 
     log::debug!("[订阅] [{}] 保存数据", s.name);
     execute(&sql, args)?;
     log::info!("[订阅] [{}] 刷新完成", s.name);
+    _after_refresh(&s.id, &s.name, &subscribe);
     Ok(())
 }
 
+/// 刷新后记录流量使用并检查提醒, 异常不影响刷新结果
+fn _after_refresh(id: &str, name: &str, subscribe: &Subscribe) {
+    if let Err(e) = TblSubscribeUsage::record(id, subscribe) {
+        log::error!("[订阅] [{}] 记录流量使用异常! {}", name, e);
+    }
+    _check_alert(id, name, subscribe);
+}
+
+type AlertHandler = Box<dyn Fn(&TblSubscribeAlert) -> AnyResult<()> + 'static + Send + Sync>;
+
+static _alert: LazyLock<Mutex<AlertHandler>> = LazyLock::new(|| Mutex::new(Box::new(|_| Ok(()))));
+
+/// 设置新提醒的通知实现, 如桌面通知
+pub fn set_alert<F: Fn(&TblSubscribeAlert) -> AnyResult<()> + 'static + Send + Sync>(
+    f: F,
+) -> AnyResult<()> {
+    *_alert.lock().unwrap() = Box::new(f);
+    Ok(())
+}
+
+const millis_day: u64 = 24 * 60 * 60 * 1000;
+
+/// 流量和过期提醒, 返回 (类型, 条件成立时的提醒内容)
+fn _alert_messages(
+    name: &str,
+    subscribe: &Subscribe,
+) -> AnyResult<Vec<(&'static str, Option<String>)>> {
+    let (traffic_percent, expire_days) = TblSettingSoftware::alert_thresholds()?;
+    let max = subscribe.max.unwrap_or(0);
+    let traffic = if traffic_percent > 0 && max > 0 {
+        let used = subscribe.download.unwrap_or(0) + subscribe.upload.unwrap_or(0);
+        let remain = max.saturating_sub(used);
+        // 剩余比例低于阈值
+        (remain as u128 * 100 < max as u128 * traffic_percent as u128).then(|| {
+            format!(
+                "订阅 [{}] 剩余流量 {} ({:.1}%)",
+                name,
+                DataSize::of_bytes(remain),
+                remain as f64 * 100.0 / max as f64
+            )
+        })
+    } else {
+        None
+    };
+
+    let expire = subscribe.expire.unwrap_or(0);
+    let now = library_core::core::current_millis()? as u64;
+    let expire = if expire_days > 0 && expire > 0 {
+        if expire <= now {
+            Some(format!("订阅 [{}] 已过期", name))
+        } else if expire - now < expire_days as u64 * millis_day {
+            let days = (expire - now).div_ceil(millis_day);
+            Some(format!("订阅 [{}] 将在 {} 天内过期", name, days))
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+
+    Ok(vec![
+        (TblSubscribeAlert::kind_traffic, traffic),
+        (TblSubscribeAlert::kind_expire, expire),
+    ])
+}
+
+/// 刷新后检查提醒, 新的提醒才会通知, 条件解除后移除提醒
+fn _check_alert(id: &str, name: &str, subscribe: &Subscribe) {
+    let result = _alert_messages(name, subscribe).and_then(|vec| {
+        for (kind, message) in vec {
+            let Some(message) = message else {
+                TblSubscribeAlert::resolve(id, kind)?;
+                continue;
+            };
+            if let Some(alert) = TblSubscribeAlert::raise(id, kind, &message)? {
+                log::warn!("[订阅] {}", alert.message);
+                match _alert.lock() {
+                    Ok(f) => f(&alert)?,
+                    Err(_) => log::error!("获取订阅提醒通知实现异常!"),
+                }
+            }
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        log::error!("[订阅] [{}] 检查提醒异常! {}", name, e);
+    }
+}
+
 pub static TIMER_SUBSCRIBE: LazyLock<Arc<Timer>> = LazyLock::new(|| {
     Timer::new("Subscribe".into(), Duration::from_secs(60), || async {
         let vec = TblSubscribeRefreshDTO::need_refresh()?;
@@ -118,7 +220,7 @@ pub static TIMER_SUBSCRIBE: LazyLock<Arc<Timer>> = LazyLock::new(|| {
 async fn list() -> R<Vec<TblSubscribe>> {
     let sql = format!(
         "
-select `id`,`name`,`url`, {}, `interval`,`update_time`,`create_time`,`refresh_time`,`download`,`upload`,`max`,`expire_time`
+select `id`,`name`,`url`, {}, `interval`,`update_time`,`create_time`,`refresh_time`,`download`,`upload`,`max`,`expire_time`,`fail_count`,`last_error`,`next_retry_time`
 from {}",
         TblSubscribe::sql_field_content,
         TblSubscribe::table_name
@@ -126,7 +228,7 @@ from {}",
     query(&sql, vec![], |stmt| TblSubscribe::from_db(stmt)).into()
 }
 
-async fn upsert(Json(entity): Json<TblSubscribeUpsertDTO>) -> R<()> {
+async fn upsert(Json(entity): Json<TblSubscribeUpsertDTO>) -> R<String> {
     let sql: String;
     let args: Vec<Value>;
 
@@ -187,17 +289,33 @@ VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?)
     _upsert(sql, args, id).await.into()
 }
 
-async fn _upsert(sql: String, args: Vec<Value>, id: String) -> AnyResult<()> {
+async fn _upsert(sql: String, args: Vec<Value>, id: String) -> AnyResult<String> {
     execute(&sql, args)?;
-    _refresh_id(Some(id)).await
+    _submit(Some(id))
+}
+
+/// 后台刷新, 返回任务 id
+fn _submit(option: Option<String>) -> AnyResult<String> {
+    let vec = match option {
+        Some(id) => TblSubscribeRefreshDTO::find(&id)?.into_iter().collect(),
+        None => TblSubscribeRefreshDTO::all()?,
+    };
+    let items = vec.into_iter().map(|s| (s.id, s.name)).collect();
+    Ok(job::submit(job::kind_subscribe, items, _refresh_id))
 }
 
-async fn refresh(Json(po): Json<IdPo>) -> R<()> {
-    _refresh_id(po.id).await.into()
+async fn refresh(Json(po): Json<IdPo>) -> R<String> {
+    _submit(po.id).into()
 }
 
 async fn delete(Json(po): Json<IdPo>) -> R<i32> {
     if let Some(id) = po.id {
+        if let Err(e) = TblSubscribeAlert::delete_subscribe(&id) {
+            return from_err_box(e);
+        }
+        if let Err(e) = TblSubscribeUsage::delete_subscribe(&id) {
+            return from_err_box(e);
+        }
         let sql = format!("delete from {} where id = ?", TblSubscribe::table_name);
         let args = vec![id.into()];
         return execute(&sql, args).into();
@@ -206,10 +324,29 @@ async fn delete(Json(po): Json<IdPo>) -> R<i32> {
     R::from(0)
 }
 
+async fn alerts(Query(q): Query<TblSubscribeAlertQuery>) -> R<Vec<TblSubscribeAlert>> {
+    TblSubscribeAlert::list(q.all).into()
+}
+
+/// 未指定 id 时确认全部
+async fn alerts_ack(Json(po): Json<IdPo>) -> R<i32> {
+    TblSubscribeAlert::ack(po.id.as_deref()).into()
+}
+
+async fn usage(Query(po): Query<IdPo>) -> R<TblSubscribeUsageVo> {
+    match po.id {
+        Some(id) => TblSubscribeUsageVo::of(&id).into(),
+        None => R::from(Err("必须指定订阅!".to_string())),
+    }
+}
+
 pub fn fill(router: Router) -> Router {
     router
         .route("/subscribe/list", get(list))
         .route("/subscribe/upsert", post(upsert))
         .route("/subscribe/refresh", patch(refresh))
         .route("/subscribe/delete", post(delete))
+        .route("/subscribe/alerts", get(alerts))
+        .route("/subscribe/alerts/ack", post(alerts_ack))
+        .route("/subscribe/usage", get(usage))
 }
@@ -0,0 +1,115 @@
+use crate::route_global::R;
+use crate::tbl_setting::TblSettingTimer;
+use crate::webserver;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use library_core::core::{AnyResult, BizError};
+use library_core::timer::{Timer, TimerStatus};
+use serde::{Deserialize, Serialize};
+
+/// 定时器状态, 时间均为毫秒级别时间戳
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimerVo {
+    pub name: String,
+    /// 调度方式: 固定间隔或 cron 表达式
+    pub schedule: String,
+    /// 随机延迟上限, 单位: 毫秒
+    pub jitter: u128,
+    pub running: bool,
+    pub paused: bool,
+    pub stopped: bool,
+    pub runs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<u128>,
+    /// 上次执行耗时, 单位: 毫秒
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_duration: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<u128>,
+}
+
+impl From<TimerStatus> for TimerVo {
+    fn from(s: TimerStatus) -> Self {
+        Self {
+            name: s.name,
+            schedule: s.schedule,
+            jitter: s.jitter,
+            running: s.running,
+            paused: s.paused,
+            stopped: s.stopped,
+            runs: s.runs,
+            last_run: s.last_run,
+            last_duration: s.last_duration,
+            last_success: s.last_success,
+            last_error: s.last_error,
+            next_run: s.next_run,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimerPo {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimerSchedulePo {
+    pub name: String,
+    #[serde(flatten)]
+    pub setting: TblSettingTimer,
+}
+
+fn _find(name: &str) -> AnyResult<&'static Timer> {
+    webserver::timers()
+        .into_iter()
+        .find(|t| t.name() == name)
+        .ok_or_else(|| Box::new(BizError::InvalidParam(format!("未找到定时器: {}", name))).into())
+}
+
+async fn timers() -> R<Vec<TimerVo>> {
+    let vec: Vec<TimerVo> = webserver::timers()
+        .into_iter()
+        .map(|t| t.status().into())
+        .collect();
+    R::from(vec)
+}
+
+/// 立即执行一次
+async fn timer_run(Json(po): Json<TimerPo>) -> R<()> {
+    _find(&po.name).map(|t| t.wake()).into()
+}
+
+async fn timer_pause(Json(po): Json<TimerPo>) -> R<()> {
+    _find(&po.name).map(|t| t.pause()).into()
+}
+
+async fn timer_resume(Json(po): Json<TimerPo>) -> R<()> {
+    _find(&po.name).map(|t| t.resume()).into()
+}
+
+/// 停止后不可恢复, 重启程序后重新运行
+async fn timer_stop(Json(po): Json<TimerPo>) -> R<()> {
+    _find(&po.name).map(|t| t.stop()).into()
+}
+
+/// 修改调度方式, cron 为空时恢复默认间隔
+async fn timer_schedule(Json(po): Json<TimerSchedulePo>) -> R<()> {
+    _find(&po.name).and_then(|t| po.setting.upsert(t)).into()
+}
+
+pub fn fill(router: Router) -> Router {
+    router
+        .route("/system/timers", get(timers))
+        .route("/system/timers/run", post(timer_run))
+        .route("/system/timers/pause", post(timer_pause))
+        .route("/system/timers/resume", post(timer_resume))
+        .route("/system/timers/stop", post(timer_stop))
+        .route("/system/timers/schedule", post(timer_schedule))
+}
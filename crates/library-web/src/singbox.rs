@@ -1,9 +1,11 @@
 use crate::tbl_config::TblConfig;
 use crate::tbl_setting::TblSettingRun;
-use crate::{route_kernel, settings};
-use library_core::core::{AnyResult, BizError};
+use crate::{kernel_log, route_kernel, settings};
+use library_core::core::{current_millis, AnyResult, BizError};
 use library_sing_box::{SingBox, State};
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, LazyLock, Mutex};
 
 static INSTANCE: LazyLock<Arc<Mutex<Box<dyn SingBox + 'static + Send>>>> = LazyLock::new(|| {
@@ -21,7 +23,7 @@ pub fn state() -> AnyResult<State> {
     }
 }
 
-pub fn start(config_path: &Path, work_dir: &Path) -> AnyResult<()> {
+fn _start(config_path: &Path, work_dir: &Path) -> AnyResult<()> {
     match INSTANCE.lock() {
         Ok(mut x) => x.start(config_path, work_dir),
         Err(e) => {
@@ -31,7 +33,24 @@ pub fn start(config_path: &Path, work_dir: &Path) -> AnyResult<()> {
     }
 }
 
+pub fn start(config_path: &Path, work_dir: &Path) -> AnyResult<()> {
+    let mut s = SUPERVISOR.lock().map_err(|_| BizError::SingBoxInit)?;
+    let result = _start(config_path, work_dir);
+    s.expected = result.is_ok();
+    s.failures = 0;
+    s.gave_up = false;
+    s.restart_time = None;
+    s.start_time = current_millis()?;
+    s.config_path = config_path.to_path_buf();
+    s.work_dir = work_dir.to_path_buf();
+    result
+}
+
 pub fn stop() -> AnyResult<()> {
+    if let Ok(mut s) = SUPERVISOR.lock() {
+        s.expected = false;
+        s.restart_time = None;
+    }
     match INSTANCE.lock() {
         Ok(mut x) => x.stop(),
         Err(e) => {
@@ -41,9 +60,9 @@ pub fn stop() -> AnyResult<()> {
     }
 }
 
-pub fn json_to_srs(json_path: &Path, srs_path: &Path) -> AnyResult<()> {
+pub fn reload() -> AnyResult<()> {
     match INSTANCE.lock() {
-        Ok(x) => x.json_srs(json_path, srs_path),
+        Ok(mut x) => x.reload(),
         Err(e) => {
             log::error!("获取sing box 实例异常! {}", e);
             Err(Box::new(BizError::SingBoxInit))
@@ -51,7 +70,135 @@ pub fn json_to_srs(json_path: &Path, srs_path: &Path) -> AnyResult<()> {
     }
 }
 
+/// 保留的异常退出记录数量
+const crash_keep: usize = 20;
+/// 运行时间短于此值的退出视为立即失败, 单位: 毫秒
+const crash_stable: u128 = 30_000;
+/// 重启等待时间, 每次连续失败翻倍, 单位: 毫秒
+const restart_delay: u128 = 1_000;
+const restart_delay_max: u128 = 60_000;
+
+/// 内核异常退出的记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KernelCrash {
+    /// 退出时间: 毫秒级别时间戳
+    pub time: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// 本次运行时长, 单位: 毫秒
+    pub uptime: u128,
+    /// 错误输出末尾
+    pub stderr: Vec<String>,
+}
+
+/// 内核守护状态
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Supervisor {
+    /// 连续立即失败的次数, 稳定运行后清零
+    pub failures: u32,
+    /// 下次重启时间: 毫秒级别时间戳
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restart_time: Option<u128>,
+    /// 连续失败次数达到上限, 不再重启
+    pub gave_up: bool,
+    /// 最近的异常退出, 新的在前
+    pub crashes: VecDeque<KernelCrash>,
+    /// 内核应当处于运行中, 启动后为 true, 手动停止后为 false
+    #[serde(skip)]
+    expected: bool,
+    #[serde(skip)]
+    start_time: u128,
+    #[serde(skip)]
+    config_path: PathBuf,
+    #[serde(skip)]
+    work_dir: PathBuf,
+}
+
+impl Supervisor {
+    /// 记录异常退出并安排重启, 超过上限时放弃
+    fn crash(&mut self, now: u128, exit_code: Option<i32>, stderr: Vec<String>, limit: u32) {
+        let uptime = now.saturating_sub(self.start_time);
+        self.crashes.push_front(KernelCrash {
+            time: now,
+            exit_code,
+            uptime,
+            stderr,
+        });
+        self.crashes.truncate(crash_keep);
+        let Some((failures, delay)) = next_restart(self.failures, uptime, limit) else {
+            log::error!(
+                "[内核] 异常退出, 退出码: {:?}, 已连续失败 {} 次, 不再重启",
+                exit_code,
+                self.failures
+            );
+            self.expected = false;
+            self.gave_up = true;
+            return;
+        };
+        self.failures = failures;
+        self.restart_time = Some(now + delay);
+        log::warn!(
+            "[内核] 异常退出, 退出码: {:?}, {} 毫秒后第 {} 次重启",
+            exit_code,
+            delay,
+            self.failures
+        );
+    }
+}
+
+/// 退出后的重启安排, 返回新的连续失败次数与等待时间. 稳定运行后重新计数,
+/// 连续失败达到上限时返回 None. 等待时间每次翻倍, 不超过上限
+fn next_restart(failures: u32, uptime: u128, limit: u32) -> Option<(u32, u128)> {
+    let failures = if uptime >= crash_stable { 0 } else { failures };
+    if failures >= limit {
+        return None;
+    }
+    let delay = (restart_delay << failures.min(16)).min(restart_delay_max);
+    Some((failures + 1, delay))
+}
+
+static SUPERVISOR: LazyLock<Mutex<Supervisor>> =
+    LazyLock::new(|| Mutex::new(Supervisor::default()));
+
+pub fn supervisor() -> Supervisor {
+    match SUPERVISOR.lock() {
+        Ok(s) => s.clone(),
+        Err(_) => Supervisor::default(),
+    }
+}
+
+/// 检查内核进程, 异常退出时按退避时间重启
+pub fn supervise() -> AnyResult<()> {
+    let state = state()?;
+    let now = current_millis()?;
+    let mut s = SUPERVISOR.lock().map_err(|_| BizError::SingBoxInit)?;
+    if !s.expected || state.running {
+        return Ok(());
+    }
+    match s.restart_time {
+        None => {
+            let limit = TblSettingRun::get()?.restart_limit;
+            s.crash(now, state.exit_code, state.stderr, limit);
+        }
+        Some(time) if now >= time => {
+            s.restart_time = None;
+            s.start_time = now;
+            log::info!("[内核] 第 {} 次重启", s.failures);
+            if let Err(e) = _start(&s.config_path, &s.work_dir) {
+                log::error!("[内核] 重启异常! {}", e);
+                let limit = TblSettingRun::get()?.restart_limit;
+                s.crash(now, None, vec![e.to_string()], limit);
+            }
+        }
+        Some(_) => {}
+    }
+    Ok(())
+}
+
 pub(crate) fn init() -> AnyResult<()> {
+    library_sing_box::set_output(kernel_log::push);
     let run = TblSettingRun::get()?;
     if run.auto
         && let Some(config_id) = run.selected
@@ -64,3 +211,67 @@ pub(crate) fn init() -> AnyResult<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restart_backoff() {
+        let delays: Vec<u128> = (0..8)
+            .map(|failures| next_restart(failures, 0, 10).unwrap().1)
+            .collect();
+        assert_eq!(
+            delays,
+            [1_000, 2_000, 4_000, 8_000, 16_000, 32_000, 60_000, 60_000]
+        );
+        // 位移次数有上限, 不会溢出
+        assert_eq!(next_restart(1_000, 0, u32::MAX), Some((1_001, 60_000)));
+        assert_eq!(next_restart(2, 0, 10), Some((3, 4_000)));
+    }
+
+    #[test]
+    fn restart_limit() {
+        assert_eq!(next_restart(2, 0, 3), Some((3, 4_000)));
+        assert_eq!(next_restart(3, 0, 3), None);
+        assert_eq!(next_restart(0, 0, 0), None);
+        // 稳定运行后重新计数
+        assert_eq!(next_restart(3, crash_stable, 3), Some((1, 1_000)));
+        assert_eq!(next_restart(3, crash_stable - 1, 3), None);
+    }
+
+    #[test]
+    fn crash_until_give_up() {
+        let mut s = Supervisor {
+            expected: true,
+            start_time: 1_000,
+            ..Default::default()
+        };
+        s.crash(2_000, Some(255), vec!["a".into()], 2);
+        assert_eq!(
+            (s.failures, s.restart_time, s.gave_up),
+            (1, Some(3_000), false)
+        );
+        assert_eq!(s.crashes[0].uptime, 1_000);
+        assert_eq!(s.crashes[0].exit_code, Some(255));
+
+        s.start_time = 3_000;
+        s.crash(4_000, None, vec![], 2);
+        assert_eq!((s.failures, s.restart_time), (2, Some(6_000)));
+
+        s.start_time = 6_000;
+        s.restart_time = None;
+        s.crash(7_000, None, vec![], 2);
+        assert!(s.gave_up);
+        assert!(!s.expected);
+        assert_eq!(s.restart_time, None);
+        assert_eq!(s.crashes.len(), 3);
+        assert_eq!(s.crashes[0].time, 7_000);
+
+        // 只保留最近的记录
+        for i in 0..crash_keep as u128 {
+            s.crash(8_000 + i, None, vec![], 100);
+        }
+        assert_eq!(s.crashes.len(), crash_keep);
+    }
+}
@@ -0,0 +1,4 @@
+use library_core::core::AnyResult;
+pub fn is_startup() -> AnyResult<bool> { Ok(false) }
+pub fn enable() -> AnyResult<bool> { Ok(false) }
+pub fn disable() -> AnyResult<bool> { Ok(true) }
@@ -1,9 +1,11 @@
+use crate::refresh_retry;
 use crate::route_global::current_millis;
 use library_core::app::get_app;
 use library_core::core::AnyResult;
 use library_core::json::JsonValueExt;
 use library_core::sqlite::{query, StatementExt};
-use library_nc::kernel::{exclude_default, include_main};
+use library_nc::kernel::{exclude_default, include_main, CustomRule, GeoRule};
+use library_nc::singbox_explain::ExplainQuery;
 use serde::{Deserialize, Serialize};
 use sqlite::Statement;
 use std::clone::Clone;
@@ -27,6 +29,10 @@ pub struct TblConfig {
     pub rule_direct_ids: Vec<String>,
     pub rule_proxy_ids: Vec<String>,
     pub rule_reject_ids: Vec<String>,
+    /// 自定义规则, 按顺序排在订阅规则之前
+    pub rules_custom: Vec<CustomRule>,
+    /// geo 分类规则
+    pub rules_geo: Vec<GeoRule>,
     /// 包含
     pub include_area_non: bool,
     /// 包含指定区域 json字符串
@@ -46,6 +52,12 @@ pub struct TblConfig {
     pub update_time: u128,
     /// 创建时间: 毫秒级别时间戳
     pub create_time: u128,
+    /// 连续刷新失败次数, 成功后清零
+    pub fail_count: u32,
+    /// 最近一次刷新失败的原因
+    pub last_error: String,
+    /// 失败后下次定时刷新的时间: 毫秒级别时间戳
+    pub next_retry_time: u128,
 }
 
 impl TblConfig {
@@ -84,6 +96,22 @@ impl TblConfig {
                 .read_json_array("rule_reject_ids")
                 .map(|v| v.into_iter().map(|_v| _v.string()).flatten().collect())
                 .unwrap_or(TblConfigUpsertDTO::default.rule_reject_ids.clone()),
+            rules_custom: stmt
+                .read_json_array("rules_custom")
+                .map(|v| {
+                    v.into_iter()
+                        .flat_map(|_v| serde_json::from_value(_v).ok())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            rules_geo: stmt
+                .read_json_array("rules_geo")
+                .map(|v| {
+                    v.into_iter()
+                        .flat_map(|_v| serde_json::from_value(_v).ok())
+                        .collect()
+                })
+                .unwrap_or_default(),
             include_area_non: stmt
                 .read_bool("include_area_non")
                 .unwrap_or(TblConfigUpsertDTO::default.include_area_non.clone()),
@@ -109,6 +137,9 @@ impl TblConfig {
             refresh_time: stmt.read_u128("refresh_time").unwrap_or(0),
             update_time: stmt.read_u128("update_time").unwrap_or(0),
             create_time: stmt.read_u128("create_time").unwrap_or(0),
+            fail_count: stmt.read_u32("fail_count").unwrap_or(0),
+            last_error: stmt.read_string("last_error").unwrap_or("".into()),
+            next_retry_time: stmt.read_u128("next_retry_time").unwrap_or(0),
         }
     }
 
@@ -126,10 +157,11 @@ impl TblConfig {
     pub fn need_refresh() -> AnyResult<Vec<Self>> {
         let millis = current_millis();
         let sql = format!(
-            "select * from {} where `refresh_time`+`interval` <= cast(? as INTEGER)",
+            "select * from {} where `refresh_time`+`interval` <= cast(? as INTEGER) and {}",
             Self::table_name,
+            refresh_retry::sql_where_retry,
         );
-        let args = vec![millis];
+        let args = vec![millis.clone(), millis];
 
         query(&sql, args, |stmt| Self::from_db(stmt))
     }
@@ -163,6 +195,12 @@ pub struct TblConfigUpsertDTO {
     pub rule_direct_ids: Vec<String>,
     pub rule_proxy_ids: Vec<String>,
     pub rule_reject_ids: Vec<String>,
+    /// 自定义规则, 按顺序排在订阅规则之前
+    #[serde(default)]
+    pub rules_custom: Vec<CustomRule>,
+    /// geo 分类规则
+    #[serde(default)]
+    pub rules_geo: Vec<GeoRule>,
     /// 包含
     pub include_area_non: bool,
     /// 包含指定区域 json字符串
@@ -190,6 +228,8 @@ impl TblConfigUpsertDTO {
         rule_direct_ids: vec![],
         rule_proxy_ids: vec![],
         rule_reject_ids: vec![],
+        rules_custom: vec![],
+        rules_geo: vec![],
         include_area_non: true,
         include_area: include_main.area.clone(),
         include_name_contains: include_main.name_contains.clone(),
@@ -198,3 +238,12 @@ impl TblConfigUpsertDTO {
         interval: 36000000,
     });
 }
+
+/// 分析域名/ip 在配置中的匹配规则
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TblConfigExplainDTO {
+    pub id: String,
+    #[serde(flatten)]
+    pub query: ExplainQuery,
+}
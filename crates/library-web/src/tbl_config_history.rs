@@ -0,0 +1,159 @@
+use crate::route_global::current_millis;
+use library_core::core::AnyResult;
+use library_core::json::JsonValueExt;
+use library_core::snowflake::next_str;
+use library_core::sqlite::{StatementExt, execute, query};
+use serde::{Deserialize, Serialize};
+use sqlite::Statement;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// 生成过的 sing-box 配置, 用于对比和回滚
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TblConfigHistory {
+    pub id: String,
+    pub config_id: String,
+    /// 触发方式
+    pub cause: String,
+    /// 与上一版本相比发生变化的来源
+    pub changes: Vec<String>,
+    /// 生成时各来源的摘要, 来源 -> 摘要
+    pub source: BTreeMap<String, String>,
+    /// sing-box 配置, 列表中为空
+    pub content: String,
+    /// 创建时间: 毫秒级别时间戳
+    pub create_time: u128,
+}
+
+impl TblConfigHistory {
+    pub const table_name: &'static str = "tbl_config_history";
+
+    /// 每个配置保留的版本数量
+    pub const keep: usize = 10;
+
+    pub const cause_timer: &'static str = "timer";
+    pub const cause_upsert: &'static str = "upsert";
+    pub const cause_refresh: &'static str = "refresh";
+    pub const cause_rollback: &'static str = "rollback";
+    pub const cause_setting: &'static str = "setting";
+
+    pub const source_config: &'static str = "config";
+    pub const source_setting: &'static str = "setting";
+
+    pub const sql_field_list: &'static str =
+        "`id`,`config_id`,`cause`,`changes`,`source`,'' AS content,`create_time`";
+
+    pub fn from_db(stmt: &Statement) -> Self {
+        Self {
+            id: stmt.read_string("id").unwrap_or("".into()),
+            config_id: stmt.read_string("config_id").unwrap_or("".into()),
+            cause: stmt.read_string("cause").unwrap_or("".into()),
+            changes: stmt
+                .read_json_array("changes")
+                .map(|v| v.into_iter().filter_map(|_v| _v.string()).collect())
+                .unwrap_or_default(),
+            source: stmt
+                .read_string("source")
+                .and_then(|s| serde_json::from_str(&s).ok())
+                .unwrap_or_default(),
+            content: stmt.read_string("content").unwrap_or("".into()),
+            create_time: stmt.read_u128("create_time").unwrap_or(0),
+        }
+    }
+
+    /// 配置的所有版本, 不含配置内容, 新的在前
+    pub fn list(config_id: &str) -> AnyResult<Vec<Self>> {
+        let sql = format!(
+            "select {} from {} where `config_id`=? order by `create_time` desc",
+            Self::sql_field_list,
+            Self::table_name
+        );
+        query(&sql, vec![config_id.into()], Self::from_db)
+    }
+
+    pub fn find(id: &str) -> AnyResult<Option<Self>> {
+        let sql = format!("select * from {} where `id`=?", Self::table_name);
+        let vec = query(&sql, vec![id.into()], Self::from_db)?;
+        Ok(vec.into_iter().find(|_| true))
+    }
+
+    /// 配置最新的版本
+    pub fn latest(config_id: &str) -> AnyResult<Option<Self>> {
+        let sql = format!(
+            "select * from {} where `config_id`=? order by `create_time` desc limit 1",
+            Self::table_name
+        );
+        let vec = query(&sql, vec![config_id.into()], Self::from_db)?;
+        Ok(vec.into_iter().find(|_| true))
+    }
+
+    /// 记录新版本, 与最新版本内容相同时跳过. 返回是否记录
+    pub fn record(
+        config_id: &str,
+        cause: &str,
+        source: BTreeMap<String, String>,
+        content: &str,
+    ) -> AnyResult<bool> {
+        let latest = Self::latest(config_id)?;
+        let changes: Vec<String> = match &latest {
+            Some(h) if h.content == content => return Ok(false),
+            Some(h) => source
+                .keys()
+                .chain(h.source.keys())
+                .filter(|k| source.get(*k) != h.source.get(*k))
+                .cloned()
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect(),
+            None => vec![],
+        };
+
+        let sql = format!(
+            "insert into {}(`id`,`config_id`,`cause`,`changes`,`source`,`content`,`create_time`)
+VALUES(?,?,?,?,?,?,?)",
+            Self::table_name
+        );
+        let args = vec![
+            next_str().into(),
+            config_id.into(),
+            cause.into(),
+            serde_json::to_string(&changes)?.into(),
+            serde_json::to_string(&source)?.into(),
+            content.into(),
+            current_millis(),
+        ];
+        execute(&sql, args)?;
+        Self::prune(config_id)?;
+        Ok(true)
+    }
+
+    /// 只保留最近的版本
+    fn prune(config_id: &str) -> AnyResult<()> {
+        let sql = format!(
+            "delete from {0} where `config_id`=? and `id` not in
+(select `id` from {0} where `config_id`=? order by `create_time` desc limit ?)",
+            Self::table_name
+        );
+        let args = vec![
+            config_id.into(),
+            config_id.into(),
+            (Self::keep as i64).into(),
+        ];
+        execute(&sql, args)?;
+        Ok(())
+    }
+
+    pub fn delete_config(config_id: &str) -> AnyResult<()> {
+        let sql = format!("delete from {} where `config_id`=?", Self::table_name);
+        execute(&sql, vec![config_id.into()])?;
+        Ok(())
+    }
+}
+
+/// 对比两个版本, 未指定 to 时与当前配置文件对比
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TblConfigHistoryDiffDTO {
+    pub from: String,
+    pub to: Option<String>,
+}
@@ -1,7 +1,9 @@
+use crate::refresh_retry;
 use crate::route_global::current_millis;
 use library_core::app::get_app;
 use library_core::core::AnyResult;
 use library_core::sqlite::{query, StatementExt};
+use library_nc::rule::RuleFormat;
 use serde::{Deserialize, Serialize};
 use sqlite::Statement;
 use std::path::PathBuf;
@@ -16,6 +18,8 @@ pub struct TblRule {
     pub url: String,
     /// 订阅完整内容(url返回或者本地编辑的)
     pub content: String,
+    /// 内容格式, auto 表示根据内容识别
+    pub format: String,
     /// 刷新间隔, 单位: 毫秒
     pub interval: u32,
     /// 更新时间: 毫秒级别时间戳
@@ -24,14 +28,22 @@ pub struct TblRule {
     pub create_time: u128,
     /// 刷新时间
     pub refresh_time: u128,
-    /// 可用规则数量
+    /// 可用规则数量, 为优化后的数量
     pub count: u64,
+    /// 优化前的规则数量
+    pub count_raw: u64,
     /// 进程规则数量
     pub count_process: u64,
     /// IP规则数量
     pub count_ip: u64,
     /// 其他规则数量
     pub count_other: u64,
+    /// 连续刷新失败次数, 成功后清零
+    pub fail_count: u32,
+    /// 最近一次刷新失败的原因
+    pub last_error: String,
+    /// 失败后下次定时刷新的时间: 毫秒级别时间戳
+    pub next_retry_time: u128,
 }
 
 impl TblRule {
@@ -46,14 +58,21 @@ impl TblRule {
             name: stmt.read_string("name").unwrap_or("".into()),
             url: stmt.read_string("url").unwrap_or("".into()),
             content: stmt.read_string("content").unwrap_or("".into()),
+            format: stmt
+                .read_string("format")
+                .unwrap_or(RuleFormat::Auto.name().into()),
             interval: stmt.read_u32("interval").unwrap_or(8640000),
             update_time: stmt.read_u128("update_time").unwrap_or(0),
             create_time: stmt.read_u128("create_time").unwrap_or(0),
             refresh_time: stmt.read_u128("refresh_time").unwrap_or(0),
             count: stmt.read_u64("count").unwrap_or(0),
+            count_raw: stmt.read_u64("count_raw").unwrap_or(0),
             count_process: stmt.read_u64("count_process").unwrap_or(0),
             count_ip: stmt.read_u64("count_ip").unwrap_or(0),
             count_other: stmt.read_u64("count_other").unwrap_or(0),
+            fail_count: stmt.read_u32("fail_count").unwrap_or(0),
+            last_error: stmt.read_string("last_error").unwrap_or("".into()),
+            next_retry_time: stmt.read_u128("next_retry_time").unwrap_or(0),
         }
     }
 
@@ -69,6 +88,7 @@ pub struct TblRuleUpsertDTO {
     pub name: String,
     pub url: String,
     pub content: Option<String>,
+    pub format: Option<String>,
     pub interval: u32,
 }
 
@@ -79,6 +99,7 @@ pub struct TblRuleRefreshDTO {
     pub name: String,
     pub url: String,
     pub content: String,
+    pub format: String,
 }
 
 impl TblRuleRefreshDTO {
@@ -88,12 +109,15 @@ impl TblRuleRefreshDTO {
             name: stmt.read_string("name").unwrap_or("".into()),
             url: stmt.read_string("url").unwrap_or("".into()),
             content: stmt.read_string("content").unwrap_or("".into()),
+            format: stmt
+                .read_string("format")
+                .unwrap_or(RuleFormat::Auto.name().into()),
         }
     }
 
     pub const sql_where_before: LazyLock<String> = LazyLock::new(|| {
         format!(
-            "SELECT `id`,`name`,`url`,{} FROM {}",
+            "SELECT `id`,`name`,`url`,{},`format` FROM {}",
             TblRule::sql_field_content,
             TblRule::table_name
         )
@@ -109,10 +133,11 @@ impl TblRuleRefreshDTO {
     pub fn need_refresh() -> AnyResult<Vec<Self>> {
         let millis = current_millis();
         let sql = format!(
-            "{} where interval > 0 and `refresh_time`+`interval` <= cast(? as INTEGER)",
+            "{} where interval > 0 and `refresh_time`+`interval` <= cast(? as INTEGER) and {}",
             Self::sql_where_before.clone(),
+            refresh_retry::sql_where_retry,
         );
-        let args = vec![millis];
+        let args = vec![millis.clone(), millis];
 
         query(&sql, args, |stmt| Self::from_db(stmt))
     }
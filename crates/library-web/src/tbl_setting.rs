@@ -4,17 +4,26 @@ use library_core::app_config::AppConfig;
 use library_core::boolean::is_true;
 use library_core::core::{AnyResult, BizError};
 use library_core::sqlite::execute;
+use library_core::timer::cron::Cron;
+use library_core::timer::{Schedule, Timer};
 use library_nc::core::FAST_GItHUB_PREFIX;
+use library_nc::dns;
+use library_nc::dns::DnsSetting;
+use library_nc::inbound;
+use library_nc::inbound::{local_listen, ProxyInbound};
 use library_nc::kernel::{
     default_mixed_listen, default_mixed_port, default_ui, dns_default_cn, dns_default_proxy,
     test_url,
 };
+use library_nc::singbox::SingBoxVersion;
+use library_nc::tun::TunSetting;
 use serde::{Deserialize, Serialize};
 use sqlite::Value;
 use std::clone::Clone;
 use std::convert::Into;
 use std::string::ToString;
 use std::sync::LazyLock;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -34,6 +43,8 @@ impl TblSetting {
     }
 
     pub fn upsert(&self) -> AnyResult<i32> {
+        self.kernel.validate()?;
+
         // 要开机自启
         if self.software.startup {
             // 没设置开机自启
@@ -70,25 +81,44 @@ impl TblSetting {
 pub struct TblSettingRun {
     pub auto: bool,
     pub selected: Option<String>,
+    /// 内核异常退出后连续重启的最大次数, 0 表示不自动重启
+    #[serde(default = "TblSettingRun::default_restart_limit")]
+    pub restart_limit: u32,
 }
 
 impl TblSettingRun {
     pub const default: LazyLock<TblSettingRun> = LazyLock::new(|| TblSettingRun {
         auto: false,
         selected: None,
+        restart_limit: Self::restart_limit_default,
     });
 
+    pub const restart_limit_default: u32 = 5;
+
     pub const key_auto: &'static str = "setting:run:auto";
     pub const key_selected: &'static str = "setting:run:selected";
+    pub const key_restart_limit: &'static str = "setting:run:restart_limit";
+
+    fn default_restart_limit() -> u32 {
+        Self::restart_limit_default
+    }
 
     pub fn get() -> AnyResult<Self> {
-        let map = AppConfig::keys(vec![Self::key_auto, Self::key_selected])?;
+        let map = AppConfig::keys(vec![
+            Self::key_auto,
+            Self::key_selected,
+            Self::key_restart_limit,
+        ])?;
         let run = TblSettingRun {
             auto: map
                 .get(Self::key_auto)
                 .map(|v| is_true(v))
                 .unwrap_or(Self::default.auto),
             selected: map.get(Self::key_selected).map(|v| v.to_string()),
+            restart_limit: map
+                .get(Self::key_restart_limit)
+                .and_then(|v| v.parse::<u32>().ok())
+                .unwrap_or(Self::restart_limit_default),
         };
 
         Ok(run)
@@ -98,6 +128,9 @@ impl TblSettingRun {
         sets.push("(?,?)".to_string());
         args.push(Value::from(Self::key_auto));
         args.push(to_value(self.auto));
+        sets.push("(?,?)".to_string());
+        args.push(Value::from(Self::key_restart_limit));
+        args.push(Value::Integer(self.restart_limit as i64));
     }
 
     pub fn set_selected(id: &str) -> AnyResult<()> {
@@ -113,6 +146,12 @@ pub struct TblSettingSoftware {
     pub version: String,
     pub fast_github: String,
     pub test_url: String,
+    /// 订阅剩余流量低于此百分比时提醒, 0 表示不提醒
+    #[serde(default = "TblSettingSoftware::default_alert_traffic_percent")]
+    pub alert_traffic_percent: u32,
+    /// 订阅在此天数内过期时提醒, 0 表示不提醒
+    #[serde(default = "TblSettingSoftware::default_alert_expire_days")]
+    pub alert_expire_days: u32,
 }
 
 impl TblSettingSoftware {
@@ -122,22 +161,57 @@ impl TblSettingSoftware {
         version: env!("CARGO_PKG_VERSION").to_string(),
         fast_github: FAST_GItHUB_PREFIX.clone(),
         test_url: test_url.into(),
+        alert_traffic_percent: Self::alert_traffic_percent_default,
+        alert_expire_days: Self::alert_expire_days_default,
     });
 
+    pub const alert_traffic_percent_default: u32 = 10;
+    pub const alert_expire_days_default: u32 = 3;
+
     pub const key_minimize: &'static str = "setting:software:minimize";
+    pub const key_alert_traffic_percent: &'static str = "setting:software:alert_traffic_percent";
+    pub const key_alert_expire_days: &'static str = "setting:software:alert_expire_days";
+
+    fn default_alert_traffic_percent() -> u32 {
+        Self::alert_traffic_percent_default
+    }
+
+    fn default_alert_expire_days() -> u32 {
+        Self::alert_expire_days_default
+    }
 
     pub fn get() -> AnyResult<Self> {
+        let (alert_traffic_percent, alert_expire_days) = Self::alert_thresholds()?;
         let software = TblSettingSoftware {
             startup: startup::is_startup()?,
             minimize: Self::is_minimize(),
             version: Self::default.version.clone(),
             fast_github: Self::default.fast_github.clone(),
             test_url: Self::default.test_url.clone(),
+            alert_traffic_percent,
+            alert_expire_days,
         };
 
         Ok(software)
     }
 
+    /// 订阅提醒阈值: (剩余流量百分比, 过期天数)
+    pub fn alert_thresholds() -> AnyResult<(u32, u32)> {
+        let map = AppConfig::keys(vec![
+            Self::key_alert_traffic_percent,
+            Self::key_alert_expire_days,
+        ])?;
+        let traffic_percent = map
+            .get(Self::key_alert_traffic_percent)
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(Self::alert_traffic_percent_default);
+        let expire_days = map
+            .get(Self::key_alert_expire_days)
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(Self::alert_expire_days_default);
+        Ok((traffic_percent, expire_days))
+    }
+
     pub fn is_minimize() -> bool {
         AppConfig::get(Self::key_minimize)
             .ok()
@@ -150,6 +224,12 @@ impl TblSettingSoftware {
         sets.push("(?,?)".to_string());
         args.push(Value::from(Self::key_minimize));
         args.push(to_value(self.minimize));
+        sets.push("(?,?)".to_string());
+        args.push(Value::from(Self::key_alert_traffic_percent));
+        args.push(Value::Integer(self.alert_traffic_percent as i64));
+        sets.push("(?,?)".to_string());
+        args.push(Value::from(Self::key_alert_expire_days));
+        args.push(Value::Integer(self.alert_expire_days as i64));
     }
 }
 
@@ -162,6 +242,18 @@ pub struct TblSettingKernel {
     pub mixed_port: u16,
     pub dns_cn: Vec<String>,
     pub dns_proxy: Vec<String>,
+    /// hosts, fake-ip, client subnet 等 dns 设置
+    #[serde(default)]
+    pub dns: DnsSetting,
+    /// 网络栈, mtu, 排除地址及分应用代理等 tun 设置
+    #[serde(default)]
+    pub tun: TunSetting,
+    /// 额外的代理入站
+    #[serde(default)]
+    pub inbounds: Vec<ProxyInbound>,
+    /// 是否允许局域网连接
+    #[serde(default)]
+    pub allow_lan: bool,
 }
 
 impl TblSettingKernel {
@@ -172,6 +264,10 @@ impl TblSettingKernel {
         mixed_port: default_mixed_port,
         dns_cn: dns_default_cn.clone(),
         dns_proxy: dns_default_proxy.clone(),
+        dns: DnsSetting::default(),
+        tun: TunSetting::default(),
+        inbounds: vec![],
+        allow_lan: false,
     });
 
     pub const key_sing_box_version: &'static str = "setting:kernel:sing_box_version";
@@ -180,6 +276,10 @@ impl TblSettingKernel {
     pub const key_mixed_port: &'static str = "setting:kernel:mixed_port";
     pub const key_dns_cn: &'static str = "setting:kernel:dns_cn";
     pub const key_dns_proxy: &'static str = "setting:kernel:dns_proxy";
+    pub const key_dns: &'static str = "setting:kernel:dns";
+    pub const key_tun: &'static str = "setting:kernel:tun";
+    pub const key_inbounds: &'static str = "setting:kernel:inbounds";
+    pub const key_allow_lan: &'static str = "setting:kernel:allow_lan";
 
     pub fn get() -> AnyResult<Self> {
         let map = AppConfig::keys(vec![
@@ -189,8 +289,21 @@ impl TblSettingKernel {
             Self::key_mixed_port,
             Self::key_dns_cn,
             Self::key_dns_proxy,
+            Self::key_dns,
+            Self::key_tun,
+            Self::key_inbounds,
+            Self::key_allow_lan,
         ])?;
 
+        let mixed_listen = map
+            .get(Self::key_mixed_listen)
+            .map(|v| v.to_string())
+            .unwrap_or(Self::default.mixed_listen.clone());
+        // 未设置时沿用之前的行为: 监听地址非回环即允许局域网连接
+        let allow_lan = map
+            .get(Self::key_allow_lan)
+            .map(|v| is_true(v))
+            .unwrap_or(local_listen(&mixed_listen, false) != mixed_listen);
         let kernel = Self {
             sing_box_version: map
                 .get(Self::key_sing_box_version)
@@ -200,10 +313,7 @@ impl TblSettingKernel {
                 .get(Self::key_ui)
                 .map(|v| v.to_string())
                 .unwrap_or(Self::default.ui.clone()),
-            mixed_listen: map
-                .get(Self::key_mixed_listen)
-                .map(|v| v.to_string())
-                .unwrap_or(Self::default.mixed_listen.clone()),
+            mixed_listen,
             mixed_port: map
                 .get(Self::key_mixed_port)
                 .map(|v| v.parse::<u16>().ok())
@@ -219,6 +329,19 @@ impl TblSettingKernel {
                 .map(|v| serde_json::from_str(v).ok())
                 .flatten()
                 .unwrap_or(Self::default.dns_proxy.clone()),
+            dns: map
+                .get(Self::key_dns)
+                .and_then(|v| serde_json::from_str(v).ok())
+                .unwrap_or_default(),
+            tun: map
+                .get(Self::key_tun)
+                .and_then(|v| serde_json::from_str(v).ok())
+                .unwrap_or_default(),
+            inbounds: map
+                .get(Self::key_inbounds)
+                .and_then(|v| serde_json::from_str(v).ok())
+                .unwrap_or_default(),
+            allow_lan,
         };
         Ok(kernel)
     }
@@ -243,10 +366,73 @@ impl TblSettingKernel {
         args.push(Value::String(serde_json::to_string(
             &serde_json::Value::from(self.dns_proxy.clone()),
         )?));
+        sets.push("(?,?)".to_string());
+        args.push(Value::from(Self::key_dns));
+        args.push(Value::String(serde_json::to_string(&self.dns)?));
+        sets.push("(?,?)".to_string());
+        args.push(Value::from(Self::key_tun));
+        args.push(Value::String(serde_json::to_string(&self.tun)?));
+        sets.push("(?,?)".to_string());
+        args.push(Value::from(Self::key_inbounds));
+        args.push(Value::String(serde_json::to_string(&self.inbounds)?));
+        sets.push("(?,?)".to_string());
+        args.push(Value::from(Self::key_allow_lan));
+        args.push(to_value(self.allow_lan));
         Ok(())
     }
 
+    /// 校验 dns 服务器地址, dns 设置, tun 设置与入站
+    pub fn validate(&self) -> AnyResult<()> {
+        dns::validate(&self.dns_cn)?;
+        dns::validate(&self.dns_proxy)?;
+        self.dns.validate()?;
+        self.tun.validate()?;
+        inbound::validate(&self.inbounds, self.mixed_port, &self.dns.listen)
+    }
+
+    /// 生成配置的目标内核版本
+    pub fn sing_box_target(&self) -> SingBoxVersion {
+        SingBoxVersion::parse(&self.sing_box_version).unwrap_or_default()
+    }
+
     pub fn ui() -> AnyResult<String> {
         AppConfig::get_else(Self::key_ui, || Self::default.ui.clone())
     }
 }
+
+/// 定时器的调度设置, 按定时器名称保存
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TblSettingTimer {
+    /// cron 表达式, 为空时使用默认的固定间隔
+    pub cron: String,
+    /// 随机延迟上限, 单位: 毫秒
+    pub jitter: u64,
+}
+
+impl TblSettingTimer {
+    pub const key_prefix: &'static str = "setting:timer:";
+
+    pub fn get(name: &str) -> AnyResult<Option<Self>> {
+        match AppConfig::get(&format!("{}{}", Self::key_prefix, name))? {
+            Some(v) => Ok(Some(serde_json::from_str(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// 校验后保存并应用到定时器
+    pub fn upsert(&self, timer: &Timer) -> AnyResult<()> {
+        self.apply(timer)?;
+        let key = format!("{}{}", Self::key_prefix, timer.name());
+        AppConfig::set(&key, &serde_json::to_string(self)?)
+    }
+
+    pub fn apply(&self, timer: &Timer) -> AnyResult<()> {
+        let schedule = match self.cron.trim() {
+            "" => None,
+            expr => Some(Schedule::Cron(Cron::parse(expr)?)),
+        };
+        timer.set_schedule(schedule, Duration::from_millis(self.jitter));
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use crate::refresh_retry;
 use crate::route_global::current_millis;
 use library_core::core::AnyResult;
 use library_core::sqlite::{query, StatementExt};
@@ -34,6 +35,12 @@ pub struct TblSubscribe {
     pub max: u64,
     /// 过期时间: 毫秒级别时间戳
     pub expire_time: u128,
+    /// 连续刷新失败次数, 成功后清零
+    pub fail_count: u32,
+    /// 最近一次刷新失败的原因
+    pub last_error: String,
+    /// 失败后下次定时刷新的时间: 毫秒级别时间戳
+    pub next_retry_time: u128,
 }
 
 impl TblSubscribe {
@@ -57,6 +64,9 @@ impl TblSubscribe {
             upload: stmt.read_u64("upload").unwrap_or(0),
             max: stmt.read_u64("max").unwrap_or(0),
             expire_time: stmt.read_u128("expire_time").unwrap_or(0),
+            fail_count: stmt.read_u32("fail_count").unwrap_or(0),
+            last_error: stmt.read_string("last_error").unwrap_or("".into()),
+            next_retry_time: stmt.read_u128("next_retry_time").unwrap_or(0),
         }
     }
 
@@ -129,10 +139,11 @@ impl TblSubscribeRefreshDTO {
     pub fn need_refresh() -> AnyResult<Vec<Self>> {
         let millis = current_millis();
         let sql = format!(
-            "{} where `refresh_time`+`interval` <= cast(? as INTEGER)",
+            "{} where `refresh_time`+`interval` <= cast(? as INTEGER) and {}",
             Self::sql_where_before.clone(),
+            refresh_retry::sql_where_retry,
         );
-        let args = vec![millis];
+        let args = vec![millis.clone(), millis];
 
         query(&sql, args, |stmt| Self::from_db(stmt))
     }
@@ -0,0 +1,125 @@
+use crate::route_global::current_millis;
+use library_core::core::AnyResult;
+use library_core::snowflake::next_str;
+use library_core::sqlite::{StatementExt, execute, query};
+use serde::{Deserialize, Serialize};
+use sqlite::Statement;
+
+/// 订阅提醒, 同一订阅的同一类型在条件解除前只提醒一次
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TblSubscribeAlert {
+    pub id: String,
+    pub subscribe_id: String,
+    /// 提醒类型
+    pub kind: String,
+    pub message: String,
+    /// 创建时间: 毫秒级别时间戳
+    pub create_time: u128,
+    /// 确认时间: 毫秒级别时间戳, 0 表示未确认
+    pub ack_time: u128,
+}
+
+impl TblSubscribeAlert {
+    pub const table_name: &'static str = "tbl_subscribe_alert";
+
+    pub const kind_traffic: &'static str = "traffic";
+    pub const kind_expire: &'static str = "expire";
+
+    pub fn from_db(stmt: &Statement) -> Self {
+        Self {
+            id: stmt.read_string("id").unwrap_or("".into()),
+            subscribe_id: stmt.read_string("subscribe_id").unwrap_or("".into()),
+            kind: stmt.read_string("kind").unwrap_or("".into()),
+            message: stmt.read_string("message").unwrap_or("".into()),
+            create_time: stmt.read_u128("create_time").unwrap_or(0),
+            ack_time: stmt.read_u128("ack_time").unwrap_or(0),
+        }
+    }
+
+    /// 提醒列表, 新的在前. 默认只返回未确认的
+    pub fn list(all: bool) -> AnyResult<Vec<Self>> {
+        let sql = format!(
+            "select * from {} {} order by `create_time` desc",
+            Self::table_name,
+            if all { "" } else { "where `ack_time`=0" }
+        );
+        query(&sql, vec![], Self::from_db)
+    }
+
+    /// 条件成立时记录提醒, 已存在时只更新内容. 返回新创建的提醒
+    pub fn raise(subscribe_id: &str, kind: &str, message: &str) -> AnyResult<Option<Self>> {
+        let sql = format!(
+            "select * from {} where `subscribe_id`=? and `kind`=?",
+            Self::table_name
+        );
+        let exists = query(&sql, vec![subscribe_id.into(), kind.into()], Self::from_db)?;
+        if let Some(alert) = exists.into_iter().find(|_| true) {
+            let sql = format!("update {} set `message`=? where `id`=?", Self::table_name);
+            execute(&sql, vec![message.into(), alert.id.into()])?;
+            return Ok(None);
+        }
+
+        let alert = Self {
+            id: next_str(),
+            subscribe_id: subscribe_id.into(),
+            kind: kind.into(),
+            message: message.into(),
+            create_time: library_core::core::current_millis()?,
+            ack_time: 0,
+        };
+        let insert = format!(
+            "insert into {}(`id`,`subscribe_id`,`kind`,`message`,`create_time`,`ack_time`)
+VALUES(?,?,?,?,?,0)",
+            Self::table_name
+        );
+        let args = vec![
+            alert.id.as_str().into(),
+            subscribe_id.into(),
+            kind.into(),
+            message.into(),
+            alert.create_time.to_string().into(),
+        ];
+        execute(&insert, args)?;
+        Ok(Some(alert))
+    }
+
+    /// 条件解除, 删除提醒以便再次成立时重新提醒
+    pub fn resolve(subscribe_id: &str, kind: &str) -> AnyResult<()> {
+        let sql = format!(
+            "delete from {} where `subscribe_id`=? and `kind`=?",
+            Self::table_name
+        );
+        execute(&sql, vec![subscribe_id.into(), kind.into()])?;
+        Ok(())
+    }
+
+    /// 确认提醒, 未指定 id 时确认全部
+    pub fn ack(id: Option<&str>) -> AnyResult<i32> {
+        let mut sql = format!(
+            "update {} set `ack_time`=? where `ack_time`=0",
+            Self::table_name
+        );
+        let mut args = vec![current_millis()];
+        if let Some(id) = id {
+            sql.push_str(" and `id`=?");
+            args.push(id.into());
+        }
+        execute(&sql, args)
+    }
+
+    pub fn delete_subscribe(subscribe_id: &str) -> AnyResult<()> {
+        let sql = format!("delete from {} where `subscribe_id`=?", Self::table_name);
+        execute(&sql, vec![subscribe_id.into()])?;
+        Ok(())
+    }
+}
+
+/// 提醒列表查询
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TblSubscribeAlertQuery {
+    /// 是否包含已确认的提醒
+    #[serde(default)]
+    pub all: bool,
+}
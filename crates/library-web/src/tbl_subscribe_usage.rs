@@ -0,0 +1,257 @@
+use crate::route_global::current_millis;
+use library_core::core::AnyResult;
+use library_core::snowflake::next_str;
+use library_core::sqlite::{StatementExt, execute, query};
+use library_nc::subscribe::Subscribe;
+use serde::{Deserialize, Serialize};
+use sqlite::Statement;
+
+/// 订阅每次刷新时的流量使用情况
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TblSubscribeUsage {
+    pub id: String,
+    pub subscribe_id: String,
+    /// 下载流量, 单位: Bytes
+    pub download: u64,
+    /// 上传流量, 单位: Bytes
+    pub upload: u64,
+    /// 最大可用流量, 单位: Bytes
+    pub max: u64,
+    /// 过期时间: 毫秒级别时间戳
+    pub expire_time: u128,
+    /// 创建时间: 毫秒级别时间戳
+    pub create_time: u128,
+}
+
+impl TblSubscribeUsage {
+    pub const table_name: &'static str = "tbl_subscribe_usage";
+
+    /// 每个订阅保留的记录数量
+    pub const keep: usize = 1000;
+
+    pub fn from_db(stmt: &Statement) -> Self {
+        Self {
+            id: stmt.read_string("id").unwrap_or("".into()),
+            subscribe_id: stmt.read_string("subscribe_id").unwrap_or("".into()),
+            download: stmt.read_u64("download").unwrap_or(0),
+            upload: stmt.read_u64("upload").unwrap_or(0),
+            max: stmt.read_u64("max").unwrap_or(0),
+            expire_time: stmt.read_u128("expire_time").unwrap_or(0),
+            create_time: stmt.read_u128("create_time").unwrap_or(0),
+        }
+    }
+
+    /// 已使用流量
+    pub fn used(&self) -> u64 {
+        self.download + self.upload
+    }
+
+    /// 订阅的所有记录, 旧的在前
+    pub fn list(subscribe_id: &str) -> AnyResult<Vec<Self>> {
+        let sql = format!(
+            "select * from {} where `subscribe_id`=? order by `create_time`",
+            Self::table_name
+        );
+        query(&sql, vec![subscribe_id.into()], Self::from_db)
+    }
+
+    /// 记录本次刷新的流量信息, 订阅没有流量信息时跳过
+    pub fn record(subscribe_id: &str, subscribe: &Subscribe) -> AnyResult<()> {
+        if subscribe.download.is_none() && subscribe.upload.is_none() && subscribe.max.is_none() {
+            return Ok(());
+        }
+        let sql = format!(
+            "insert into {}(`id`,`subscribe_id`,`download`,`upload`,`max`,`expire_time`,`create_time`)
+VALUES(?,?,?,?,?,?,?)",
+            Self::table_name
+        );
+        let args = vec![
+            next_str().into(),
+            subscribe_id.into(),
+            subscribe.download.unwrap_or(0).to_string().into(),
+            subscribe.upload.unwrap_or(0).to_string().into(),
+            subscribe.max.unwrap_or(0).to_string().into(),
+            subscribe.expire.unwrap_or(0).to_string().into(),
+            current_millis(),
+        ];
+        execute(&sql, args)?;
+        Self::prune(subscribe_id)
+    }
+
+    /// 只保留最近的记录
+    fn prune(subscribe_id: &str) -> AnyResult<()> {
+        let sql = format!(
+            "delete from {0} where `subscribe_id`=? and `id` not in
+(select `id` from {0} where `subscribe_id`=? order by `create_time` desc limit ?)",
+            Self::table_name
+        );
+        let args = vec![
+            subscribe_id.into(),
+            subscribe_id.into(),
+            (Self::keep as i64).into(),
+        ];
+        execute(&sql, args)?;
+        Ok(())
+    }
+
+    pub fn delete_subscribe(subscribe_id: &str) -> AnyResult<()> {
+        let sql = format!("delete from {} where `subscribe_id`=?", Self::table_name);
+        execute(&sql, vec![subscribe_id.into()])?;
+        Ok(())
+    }
+}
+
+/// 按当前周期的使用速度线性推算的耗尽时间
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TblSubscribeUsageProjection {
+    /// 平均每天使用的流量, 单位: Bytes
+    pub daily: u64,
+    /// 预计耗尽时间: 毫秒级别时间戳
+    pub exhaust_time: u128,
+    /// 过期时间: 毫秒级别时间戳, 0 表示不过期
+    pub expire_time: u128,
+    /// 是否会在过期前耗尽
+    pub before_expire: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TblSubscribeUsageVo {
+    pub series: Vec<TblSubscribeUsage>,
+    /// 记录不足或流量没有增长时为空
+    pub projection: Option<TblSubscribeUsageProjection>,
+}
+
+const millis_day: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+
+impl TblSubscribeUsageVo {
+    pub fn of(subscribe_id: &str) -> AnyResult<Self> {
+        let series = TblSubscribeUsage::list(subscribe_id)?;
+        let projection = Self::project(&series);
+        Ok(Self { series, projection })
+    }
+
+    /// 用最近一个周期的记录做最小二乘拟合, 已用流量减少视为流量重置
+    fn project(series: &[TblSubscribeUsage]) -> Option<TblSubscribeUsageProjection> {
+        let last = series.last()?;
+        if last.max == 0 {
+            return None;
+        }
+        let start = series
+            .windows(2)
+            .rposition(|w| w[1].used() < w[0].used() || w[1].max != w[0].max)
+            .map_or(0, |i| i + 1);
+        let cycle = &series[start..];
+        if cycle.len() < 2 {
+            return None;
+        }
+
+        let t0 = cycle[0].create_time as f64;
+        let points: Vec<(f64, f64)> = cycle
+            .iter()
+            .map(|u| (u.create_time as f64 - t0, u.used() as f64))
+            .collect();
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+        let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+        let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+        if sxx <= 0.0 || sxy <= 0.0 {
+            return None;
+        }
+        // 每毫秒使用的流量
+        let slope = sxy / sxx;
+        let remain = last.max.saturating_sub(last.used()) as f64;
+        let exhaust_time = last.create_time + (remain / slope) as u128;
+        Some(TblSubscribeUsageProjection {
+            daily: (slope * millis_day) as u64,
+            exhaust_time,
+            expire_time: last.expire_time,
+            before_expire: last.expire_time == 0 || exhaust_time < last.expire_time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const gb: u64 = 1 << 30;
+    const day: u128 = 24 * 60 * 60 * 1000;
+
+    fn usage(days: f64, used: f64, max: u64, expire_days: u128) -> TblSubscribeUsage {
+        TblSubscribeUsage {
+            download: (used * gb as f64) as u64,
+            max: max * gb,
+            expire_time: expire_days * day,
+            create_time: (days * day as f64) as u128,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn project_steady() {
+        let series: Vec<_> = (0..5).map(|i| usage(i as f64, i as f64, 10, 8)).collect();
+        let p = TblSubscribeUsageVo::project(&series).unwrap();
+        assert_eq!(p.daily, gb);
+        assert_eq!(p.exhaust_time, 10 * day);
+        assert_eq!(p.expire_time, 8 * day);
+        assert!(!p.before_expire);
+
+        // 不过期时总会在过期前耗尽
+        let series: Vec<_> = (0..5).map(|i| usage(i as f64, i as f64, 10, 0)).collect();
+        assert!(TblSubscribeUsageVo::project(&series).unwrap().before_expire);
+    }
+
+    #[test]
+    fn project_reset() {
+        // 第 4 天流量重置, 只使用重置后的记录
+        let series = [
+            usage(0.0, 0.0, 10, 30),
+            usage(1.0, 3.0, 10, 30),
+            usage(2.0, 6.0, 10, 30),
+            usage(3.0, 9.0, 10, 30),
+            usage(4.0, 1.0, 10, 30),
+            usage(5.0, 3.0, 10, 30),
+            usage(6.0, 5.0, 10, 30),
+        ];
+        let p = TblSubscribeUsageVo::project(&series).unwrap();
+        assert_eq!(p.daily, 2 * gb);
+        assert_eq!(p.exhaust_time, 8 * day + day / 2);
+        assert!(p.before_expire);
+
+        // 重置后只有一条记录时无法推算
+        assert!(TblSubscribeUsageVo::project(&series[..5]).is_none());
+    }
+
+    #[test]
+    fn project_max_changed() {
+        // 第 3 天更换套餐, 已用流量不变也视为新的周期
+        let series = [
+            usage(0.0, 0.0, 10, 0),
+            usage(1.0, 1.0, 10, 0),
+            usage(2.0, 2.0, 10, 0),
+            usage(3.0, 3.0, 20, 0),
+            usage(4.0, 5.0, 20, 0),
+        ];
+        let p = TblSubscribeUsageVo::project(&series).unwrap();
+        assert_eq!(p.daily, 2 * gb);
+        assert_eq!(p.exhaust_time, 4 * day + 15 * day / 2);
+        assert!(TblSubscribeUsageVo::project(&series[..4]).is_none());
+    }
+
+    #[test]
+    fn project_none() {
+        // 流量没有增长
+        let series: Vec<_> = (0..5).map(|i| usage(i as f64, 1.0, 10, 0)).collect();
+        assert!(TblSubscribeUsageVo::project(&series).is_none());
+        // 没有流量上限
+        let series: Vec<_> = (0..5).map(|i| usage(i as f64, i as f64, 0, 0)).collect();
+        assert!(TblSubscribeUsageVo::project(&series).is_none());
+        // 记录不足
+        assert!(TblSubscribeUsageVo::project(&[]).is_none());
+        assert!(TblSubscribeUsageVo::project(&series[..1]).is_none());
+    }
+}
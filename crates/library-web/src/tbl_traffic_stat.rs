@@ -0,0 +1,141 @@
+use crate::route_global::current_millis;
+use library_core::core::{AnyResult, BizError};
+use library_core::sqlite::{StatementExt, execute, query};
+use serde::{Deserialize, Serialize};
+use sqlite::Statement;
+use std::collections::BTreeMap;
+
+/// 按天, 配置, 顶层出站累计的流量
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TblTrafficStat {
+    /// 本地日期, 汇总时为空
+    pub day: String,
+    pub config_id: String,
+    /// 顶层出站的 tag, 为空时表示已关闭连接中无法归属出站的流量
+    pub outbound: String,
+    /// 上传流量, 单位: Bytes
+    pub upload: u64,
+    /// 下载流量, 单位: Bytes
+    pub download: u64,
+}
+
+impl TblTrafficStat {
+    pub const table_name: &'static str = "tbl_traffic_stat";
+
+    pub const range_day: &'static str = "day";
+    pub const range_week: &'static str = "week";
+    pub const range_month: &'static str = "month";
+
+    pub fn from_db(stmt: &Statement) -> Self {
+        Self {
+            day: stmt.read_string("day").unwrap_or("".into()),
+            config_id: stmt.read_string("config_id").unwrap_or("".into()),
+            outbound: stmt.read_string("outbound").unwrap_or("".into()),
+            upload: stmt.read_u64("upload").unwrap_or(0),
+            download: stmt.read_u64("download").unwrap_or(0),
+        }
+    }
+
+    /// 统计范围包含的天数, 含今天
+    pub fn range_days(range: &str) -> AnyResult<u32> {
+        match range {
+            Self::range_day => Ok(1),
+            Self::range_week => Ok(7),
+            Self::range_month => Ok(30),
+            _ => Err(Box::new(BizError::InvalidParam(format!(
+                "统计范围只能是 {}, {}, {}",
+                Self::range_day,
+                Self::range_week,
+                Self::range_month
+            )))),
+        }
+    }
+
+    /// 累加到当天的统计, 出站 -> (上传, 下载)
+    pub fn add(config_id: &str, traffic: &BTreeMap<String, (u64, u64)>) -> AnyResult<()> {
+        let sql = format!(
+            "insert into {}(`day`,`config_id`,`outbound`,`upload`,`download`,`update_time`)
+VALUES(date('now','localtime'),?,?,?,?,?)
+ON CONFLICT(`day`,`config_id`,`outbound`) DO UPDATE SET
+`upload`=`upload`+excluded.`upload`,`download`=`download`+excluded.`download`,`update_time`=excluded.`update_time`",
+            Self::table_name
+        );
+        for (outbound, (upload, download)) in traffic {
+            if *upload == 0 && *download == 0 {
+                continue;
+            }
+            let args = vec![
+                config_id.into(),
+                outbound.as_str().into(),
+                (*upload as i64).into(),
+                (*download as i64).into(),
+                current_millis(),
+            ];
+            execute(&sql, args)?;
+        }
+        Ok(())
+    }
+
+    /// 最近 days 天的统计, 按日期排序
+    pub fn list(days: u32, config_id: Option<&str>) -> AnyResult<Vec<Self>> {
+        let mut sql = format!(
+            "select * from {} where `day`>=date('now','localtime',?)",
+            Self::table_name
+        );
+        let mut args = vec![format!("-{} days", days.saturating_sub(1)).into()];
+        if let Some(config_id) = config_id {
+            sql.push_str(" and `config_id`=?");
+            args.push(config_id.into());
+        }
+        sql.push_str(" order by `day`,`config_id`,`outbound`");
+        query(&sql, args, Self::from_db)
+    }
+}
+
+/// 流量统计查询
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TblTrafficStatQuery {
+    /// day, week, month, 默认 day
+    pub range: Option<String>,
+    pub config_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TblTrafficStatVo {
+    pub range: String,
+    /// 每天的明细
+    pub days: Vec<TblTrafficStat>,
+    /// 范围内按配置和出站汇总
+    pub totals: Vec<TblTrafficStat>,
+}
+
+impl TblTrafficStatVo {
+    pub fn query(q: &TblTrafficStatQuery) -> AnyResult<Self> {
+        let range = q
+            .range
+            .clone()
+            .unwrap_or(TblTrafficStat::range_day.to_string());
+        let days =
+            TblTrafficStat::list(TblTrafficStat::range_days(&range)?, q.config_id.as_deref())?;
+        let mut map: BTreeMap<(String, String), TblTrafficStat> = BTreeMap::new();
+        for d in &days {
+            let total = map
+                .entry((d.config_id.clone(), d.outbound.clone()))
+                .or_insert_with(|| TblTrafficStat {
+                    config_id: d.config_id.clone(),
+                    outbound: d.outbound.clone(),
+                    ..Default::default()
+                });
+            total.upload += d.upload;
+            total.download += d.download;
+        }
+        Ok(Self {
+            range,
+            days,
+            totals: map.into_values().collect(),
+        })
+    }
+}
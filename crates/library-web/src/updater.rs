@@ -5,6 +5,7 @@ use library_core::core::AnyResult;
 use library_core::data_size::DataSize;
 use library_nc::core::fast;
 use serde::{Deserialize, Serialize};
+#[cfg(windows)]
 use std::os::windows::process::CommandExt;
 use std::process::{exit, Command};
 use std::sync::{LazyLock, Mutex};
@@ -143,7 +144,10 @@ pub fn update(listener: UpdateListener) -> AnyResult<()> {
         .arg(&path)
         .arg("/qb")
         // 独立新进程
-        .creation_flags(0x00000200)
+;
+    #[cfg(windows)]
+    cmd.creation_flags(0x00000200);
+    cmd
         .spawn()?;
 
     let guard = _exit.lock().unwrap();
@@ -1,10 +1,14 @@
 use crate::route_config::TIMER_CONFIG;
+use crate::route_kernel::TIMER_KERNEL;
 use crate::route_rule::TIMER_RULE;
+use crate::route_stats::TIMER_TRAFFIC;
 use crate::route_subscribe::TIMER_SUBSCRIBE;
-use crate::{route_config, route_global, route_kernel, route_rule, route_setting, route_subscribe};
+use crate::tbl_setting::TblSettingTimer;
+use crate::{route_clash, route_config, route_global, route_job, route_kernel, route_rule, route_setting, route_stats, route_subscribe, route_system};
 use axum::serve::Serve;
 use axum::Router;
 use library_core::core::{AnyResult, BizError, Exit};
+use library_core::timer::Timer;
 use std::pin::Pin;
 use std::process::exit;
 use std::sync::{mpsc, Arc, LazyLock, Mutex, OnceLock};
@@ -38,6 +42,10 @@ pub async fn build_port(port: u16) -> AnyResult<(u16, Serve<TcpListener, Router,
     router = route_rule::fill(router);
     router = route_config::fill(router);
     router = route_kernel::fill(router);
+    router = route_clash::fill(router);
+    router = route_stats::fill(router);
+    router = route_system::fill(router);
+    router = route_job::fill(router);
 
     // 这个必须最后设置
     router = route_global::fill(router);
@@ -63,12 +71,40 @@ pub fn wake() -> AnyResult<()> {
         log::debug!("[Web] 根据设置进行内核唤醒");
         runtime.block_on(async {
             log::debug!("[Web] 唤醒定时器");
-            TIMER_SUBSCRIBE.wake();
-            TIMER_RULE.wake();
-            TIMER_CONFIG.wake();
+            for timer in _timers() {
+                // 应用保存的调度设置
+                match TblSettingTimer::get(timer.name()) {
+                    Ok(Some(s)) => {
+                        if let Err(e) = s.apply(timer) {
+                            log::error!("[Web] [{}] 调度设置无效! {}", timer.name(), e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => log::error!("[Web] [{}] 读取调度设置异常! {}", timer.name(), e),
+                }
+                timer.wake();
+            }
         });
         runtime
     });
 
     Ok(())
 }
+
+fn _timers() -> Vec<&'static Timer> {
+    vec![
+        &TIMER_SUBSCRIBE,
+        &TIMER_RULE,
+        &TIMER_CONFIG,
+        &TIMER_KERNEL,
+        &TIMER_TRAFFIC,
+    ]
+}
+
+/// 所有定时器, 定时器运行时未启动时为空
+pub(crate) fn timers() -> Vec<&'static Timer> {
+    if _TIMER_RUNTIME.get().is_none() {
+        return vec![];
+    }
+    _timers()
+}